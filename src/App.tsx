@@ -6,7 +6,15 @@ import init from "./services/renderer/pkg";
 import { ConnectionStatus, PlayerStatus, type RankingEntry } from "./types";
 
 const websocketUri = import.meta.env.VITE_WSS_URI || "ws://localhost:5173";
-const socket = new WebSocket(websocketUri);
+// `?room=<name>` joins a specific room; otherwise the server picks a public one.
+const room = new URLSearchParams(window.location.search).get("room");
+const socket = new WebSocket(
+  (() => {
+    const url = new URL(websocketUri);
+    if (room) url.searchParams.set("room", room);
+    return url.toString();
+  })(),
+);
 const playerToken = crypto.randomUUID();
 
 const leaderboardUri = (() => {
@@ -71,6 +79,7 @@ export default function App() {
       try {
         const url = new URL(leaderboardUri);
         url.searchParams.set("player", playerToken);
+        if (room) url.searchParams.set("room", room);
         const response = await fetch(url);
         if (!response.ok) return;
         const entries: RankingEntry[] = await response.json();
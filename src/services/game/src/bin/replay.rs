@@ -0,0 +1,116 @@
+use game::coordinate::Coordinate;
+use game::replay::Replay;
+use game::view::EntityHandles;
+use std::env;
+use std::fs;
+use std::process;
+use uuid::Uuid;
+
+const DEFAULT_VIEW_WIDTH: f32 = 1920.0;
+const DEFAULT_VIEW_HEIGHT: f32 = 1080.0;
+const USAGE: &str = "usage: replay <file> [<frame> <snake-id> [<width> <height>]]";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let Some(path) = args.first() else {
+        exit_with_usage();
+    };
+    let bytes = fs::read(path).unwrap_or_else(|error| {
+        eprintln!("failed to read {path}: {error}");
+        process::exit(1);
+    });
+    let replay = Replay::from_bytes(&bytes).unwrap_or_else(|error| {
+        eprintln!("failed to decode {path}: {error}");
+        process::exit(1);
+    });
+
+    match args.len() {
+        1 => print_deaths(&replay),
+        3 | 5 => {
+            let frame = parse_or_exit::<u32>(&args[1]);
+            let id = parse_or_exit::<Uuid>(&args[2]);
+            let (width, height) = if args.len() == 5 {
+                (parse_or_exit(&args[3]), parse_or_exit(&args[4]))
+            } else {
+                (DEFAULT_VIEW_WIDTH, DEFAULT_VIEW_HEIGHT)
+            };
+            dump_view(&replay, frame, id, width, height);
+        }
+        _ => exit_with_usage(),
+    }
+}
+
+fn print_deaths(replay: &Replay) {
+    eprintln!(
+        "frames {}..{}, {} inputs, seed {}",
+        replay.start_frame(),
+        replay.end_frame,
+        replay.inputs.len(),
+        replay.start.seed
+    );
+    let mut player = replay.player();
+    while player.frame() < replay.end_frame {
+        let frame = player.frame();
+        for death in player.step().deaths {
+            let killer = death
+                .killer
+                .map(|killer| format!(", killed by {killer}"))
+                .unwrap_or_default();
+            println!(
+                "frame {frame}: {} died with score {} at ({:.0}, {:.0}) ({:?}{killer})",
+                death.id, death.score, death.position.x, death.position.y, death.cause
+            );
+        }
+    }
+}
+
+fn dump_view(replay: &Replay, frame: u32, id: Uuid, width: f32, height: f32) {
+    if !(replay.start_frame()..=replay.end_frame).contains(&frame) {
+        eprintln!(
+            "frame {frame} is outside of the recording ({}..={})",
+            replay.start_frame(),
+            replay.end_frame
+        );
+        process::exit(1);
+    }
+
+    // Keep following the last known head so that the view of a snake that
+    // just died still shows where it happened.
+    let mut last_head: Option<Coordinate> = None;
+    let mut player = replay.player();
+    loop {
+        if let Some(snake) = player.engine().get_snake(&id) {
+            last_head = Some(*snake.get_head());
+        }
+        if player.frame() == frame {
+            break;
+        }
+        player.step();
+    }
+
+    let Some(center) = last_head else {
+        eprintln!("snake {id} has not appeared by frame {frame}");
+        process::exit(1);
+    };
+    let view = player.engine().view(
+        &id,
+        center.x,
+        center.y,
+        width,
+        height,
+        &mut EntityHandles::default(),
+    );
+    println!(
+        "{}",
+        serde_json::to_string(&view).expect("view should serialize")
+    );
+}
+
+fn parse_or_exit<T: std::str::FromStr>(value: &str) -> T {
+    value.parse().unwrap_or_else(|_| exit_with_usage())
+}
+
+fn exit_with_usage() -> ! {
+    eprintln!("{USAGE}");
+    process::exit(2);
+}
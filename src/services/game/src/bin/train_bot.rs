@@ -17,7 +17,7 @@ fn main() {
     let output = env::args()
         .nth(1)
         .unwrap_or_else(|| "bot_policy.json".to_string());
-    let mut engine = GameEngine::new();
+    let mut engine = GameEngine::default();
     let bots: Vec<_> = (0..BOT_COUNT).map(|_| Uuid::new_v4()).collect();
     for id in &bots {
         spawn(&mut engine, *id);
@@ -0,0 +1,581 @@
+use rand::RngExt;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+use uuid::Uuid;
+
+use super::coordinate::{segment_distance2, torus_delta, Coordinate};
+use super::engine::{axis_cells, neighbour_cells, DeathCause, GameEngine};
+use super::snake::Snake;
+
+/// One body point of a snake in the body grid. The position is copied so
+/// that queries do not need to look the snake up.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct BodyEntry {
+    pub(crate) id: Uuid,
+    pub(crate) index: usize,
+    pub(crate) position: Coordinate,
+}
+
+impl GameEngine {
+    pub(crate) fn rebuild_body_grid(&mut self) {
+        //! Re-index every body point. Bodies move every frame, so updating
+        //! the grid incrementally would touch every entry anyway.
+
+        for cell in self.body_grid.iter_mut() {
+            cell.clear();
+        }
+        for (id, snake) in self.snakes.iter() {
+            for (index, body) in snake.bodies.iter().enumerate() {
+                let (x, y) = Self::grid_cell(&self.config, body);
+                self.body_grid[Self::grid_cell_index(&self.config, x, y)].push(BodyEntry {
+                    id: *id,
+                    index,
+                    position: *body,
+                });
+            }
+        }
+    }
+
+    pub(crate) fn insert_snake_into_body_grid(&mut self, id: Uuid) {
+        let Some(snake) = self.snakes.get(&id) else {
+            return;
+        };
+        for (index, body) in snake.bodies.iter().enumerate() {
+            let (x, y) = Self::grid_cell(&self.config, body);
+            self.body_grid[Self::grid_cell_index(&self.config, x, y)].push(BodyEntry {
+                id,
+                index,
+                position: *body,
+            });
+        }
+    }
+
+    pub(crate) fn remove_snake_from_body_grid(&mut self, id: &Uuid, snake: &Snake) {
+        let cells: BTreeSet<usize> = snake
+            .bodies
+            .iter()
+            .map(|body| {
+                let (x, y) = Self::grid_cell(&self.config, body);
+                Self::grid_cell_index(&self.config, x, y)
+            })
+            .collect();
+        for cell in cells {
+            self.body_grid[cell].retain(|entry| entry.id != *id);
+        }
+    }
+
+    pub(crate) fn nearby_body_entries(
+        &self,
+        position: &Coordinate,
+        distance: f32,
+    ) -> Vec<BodyEntry> {
+        //! Every body point that may lie within `distance` of `position`.
+
+        let grid_size = self.config.pellet_grid_size();
+        let radius = (distance.max(0.0) / self.config.pellet_cell_width()).ceil() as isize;
+        let (center_x, center_y) = Self::grid_cell(&self.config, position);
+        let x_cells = neighbour_cells(center_x, radius, grid_size);
+        let y_cells = neighbour_cells(center_y, radius, grid_size);
+        let mut entries = Vec::new();
+
+        for &x in &x_cells {
+            for &y in &y_cells {
+                entries
+                    .extend_from_slice(&self.body_grid[Self::grid_cell_index(&self.config, x, y)]);
+            }
+        }
+
+        entries
+    }
+
+    pub(crate) fn body_entries_in_rectangle(
+        &self,
+        x0: f32,
+        y0: f32,
+        width: f32,
+        height: f32,
+    ) -> BTreeMap<Uuid, Vec<BodyEntry>> {
+        //! Body points inside the rectangle, grouped by snake in the same
+        //! order as `snakes` and sorted from head to tail.
+
+        let field_size = self.config.field_size;
+        let mut snakes: BTreeMap<Uuid, Vec<BodyEntry>> = BTreeMap::new();
+        for x in axis_cells(&self.config, x0, width) {
+            for y in axis_cells(&self.config, y0, height) {
+                for entry in &self.body_grid[Self::grid_cell_index(&self.config, x, y)] {
+                    if entry
+                        .position
+                        .is_in_rectangle(x0, y0, width, height, field_size)
+                    {
+                        snakes.entry(entry.id).or_default().push(*entry);
+                    }
+                }
+            }
+        }
+        for entries in snakes.values_mut() {
+            entries.sort_by_key(|entry| entry.index);
+        }
+
+        snakes
+    }
+
+    pub(crate) fn detect_collisions(&mut self) -> Vec<(Uuid, Uuid, DeathCause)> {
+        //! Return the snakes that die this frame with their killer and the
+        //! cause, in the order of `snakes`.
+        //!
+        //! Each snake is a chain of capsules of radius `size` between
+        //! consecutive body points. The first capsule runs from the previous
+        //! head to the new one, so it is also the sweep of the head over the
+        //! frame: a head hits another snake when its sweep comes within reach
+        //! of any capsule of that snake. Two heads only meet when they come
+        //! within reach at the same moment of the frame; a head that hits the
+        //! first capsule of another snake otherwise hit its neck, which is a
+        //! body hit. Head-to-head pairs are resolved in snake id order so that
+        //! the coin flips consume the random number generator deterministically.
+
+        let field_size = self.config.field_size;
+        let max_size = self.max_snake_size();
+        let longest_segment = self.longest_body_segment();
+        let mut head_pairs: BTreeSet<(Uuid, Uuid)> = BTreeSet::new();
+        let mut body_hits: BTreeSet<(Uuid, Uuid)> = BTreeSet::new();
+
+        for (id, snake) in self.snakes.iter() {
+            // Protected snakes neither die nor kill.
+            if snake.protection_time_left > 0 {
+                continue;
+            }
+            let head = snake.get_head();
+            let origin = Coordinate::default();
+            let sweep = head_sweep(snake, field_size);
+            let sweep_length = (sweep.x * sweep.x + sweep.y * sweep.y).sqrt();
+            let distance = sweep_length + longest_segment + (snake.size + max_size) as f32;
+            for entry in self.nearby_body_entries(head, distance) {
+                if entry.id == *id {
+                    continue;
+                }
+                let Some(other) = self.snakes.get(&entry.id) else {
+                    continue;
+                };
+                if other.protection_time_left > 0 || snake.is_teammate_of(other) {
+                    continue;
+                }
+                let start = torus_delta(head, &entry.position, field_size);
+                let end = match other.bodies.get(entry.index + 1) {
+                    Some(next) => {
+                        let step = torus_delta(&entry.position, next, field_size);
+                        Coordinate {
+                            x: start.x + step.x,
+                            y: start.y + step.y,
+                        }
+                    }
+                    None => start,
+                };
+                let reach = (snake.size + other.size).pow(2) as f32;
+                if segment_distance2(&origin, &sweep, &start, &end) > reach {
+                    continue;
+                }
+                if entry.index == 0 {
+                    if heads_meet(snake, other, field_size) {
+                        head_pairs.insert((*id.min(&entry.id), *id.max(&entry.id)));
+                        continue;
+                    }
+                    if !runs_into_neck(snake, other, field_size) {
+                        continue;
+                    }
+                }
+                // Ghosts pass through bodies, but not through heads.
+                if snake.power_ups.ghost > 0 {
+                    continue;
+                }
+                body_hits.insert((*id, entry.id));
+            }
+        }
+
+        // A snake that dies in several collisions at once is attributed to
+        // the first one: head-to-head pairs, then bodies, both in id order.
+        let mut dead_snakes: BTreeMap<Uuid, (Uuid, DeathCause)> = BTreeMap::new();
+        for (first, second) in head_pairs.iter() {
+            let (loser, winner, cause) = self.head_to_head(first, second);
+            dead_snakes.entry(loser).or_insert((winner, cause));
+        }
+        let mut shielded = BTreeSet::new();
+        for (victim, other) in body_hits {
+            // Snakes that met head to head do not also collide with each
+            // other's bodies in the same frame.
+            if head_pairs.contains(&(victim.min(other), victim.max(other)))
+                || dead_snakes.contains_key(&victim)
+                || shielded.contains(&victim)
+            {
+                continue;
+            }
+            if self.absorb_with_shield(&victim) {
+                shielded.insert(victim);
+                continue;
+            }
+            dead_snakes.insert(victim, (other, DeathCause::Body));
+        }
+
+        dead_snakes
+            .into_iter()
+            .map(|(victim, (killer, cause))| (victim, killer, cause))
+            .collect()
+    }
+
+    pub(crate) fn head_to_head(&mut self, first: &Uuid, second: &Uuid) -> (Uuid, Uuid, DeathCause) {
+        //! Return the loser, the winner and the cause of a head to head
+        //! collision, rules:
+        //! 1. the acceleration snake wins
+        //! 2. the bigger snake wins
+        //! 3. random
+
+        let snake1 = &self.snakes[first];
+        let snake2 = &self.snakes[second];
+        if (snake1.acceleration_time_left > 0) == (snake2.acceleration_time_left > 0) {
+            match snake1.size.cmp(&snake2.size) {
+                Ordering::Greater => (*second, *first, DeathCause::HeadToHeadSize),
+                Ordering::Less => (*first, *second, DeathCause::HeadToHeadSize),
+                Ordering::Equal => {
+                    if self.rng.random_range(0..10) < 5 {
+                        (*first, *second, DeathCause::CoinFlip)
+                    } else {
+                        (*second, *first, DeathCause::CoinFlip)
+                    }
+                }
+            }
+        } else if snake2.acceleration_time_left > 0 {
+            (*first, *second, DeathCause::HeadToHeadBoost)
+        } else {
+            (*second, *first, DeathCause::HeadToHeadBoost)
+        }
+    }
+
+    fn longest_body_segment(&self) -> f32 {
+        //! Bounds how far a capsule reaches past the body point it is
+        //! indexed under.
+
+        let field_size = self.config.field_size;
+        self.snakes
+            .values()
+            .flat_map(|snake| {
+                snake
+                    .bodies
+                    .iter()
+                    .zip(snake.bodies.iter().skip(1))
+                    .map(move |(body, next)| body.distance2(next, field_size))
+            })
+            .fold(0.0, f32::max)
+            .sqrt()
+    }
+
+    pub(crate) fn max_snake_size(&self) -> usize {
+        self.snakes
+            .values()
+            .map(|snake| snake.size)
+            .max()
+            .unwrap_or(0)
+            .max(self.config.max_snake_size)
+    }
+}
+
+fn head_sweep(snake: &Snake, field_size: f32) -> Coordinate {
+    //! The previous head relative to the current one. The head moved there
+    //! from `bodies[1]` during the last `forward()`.
+
+    let head = snake.get_head();
+    torus_delta(head, snake.bodies.get(1).unwrap_or(head), field_size)
+}
+
+fn heads_meet(snake: &Snake, other: &Snake, field_size: f32) -> bool {
+    //! Both heads move in a straight line over the frame, so the offset
+    //! between them does too. They meet when that offset passes within
+    //! reach of zero.
+
+    let origin = Coordinate::default();
+    let now = torus_delta(snake.get_head(), other.get_head(), field_size);
+    let sweep = head_sweep(snake, field_size);
+    let other_sweep = head_sweep(other, field_size);
+    let before = Coordinate {
+        x: now.x + other_sweep.x - sweep.x,
+        y: now.y + other_sweep.y - sweep.y,
+    };
+    let reach = (snake.size + other.size).pow(2) as f32;
+    segment_distance2(&origin, &origin, &before, &now) <= reach
+}
+
+fn runs_into_neck(snake: &Snake, other: &Snake, field_size: f32) -> bool {
+    //! When two heads cross each other's necks without meeting, the one
+    //! that ends up nearer the other's neck ran into it.
+
+    let origin = Coordinate::default();
+    let head = torus_delta(snake.get_head(), other.get_head(), field_size);
+    let sweep = head_sweep(snake, field_size);
+    let other_sweep = head_sweep(other, field_size);
+    let neck = Coordinate {
+        x: head.x + other_sweep.x,
+        y: head.y + other_sweep.y,
+    };
+    segment_distance2(&origin, &origin, &head, &neck)
+        <= segment_distance2(&head, &head, &origin, &sweep)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::GameConfig;
+    use crate::engine::GameRng;
+    use rand::SeedableRng;
+
+    impl GameEngine {
+        fn detect_collisions_brute_force(&mut self) -> Vec<Uuid> {
+            let field_size = self.config.field_size;
+            let hits = |snake: &Snake, other: &Snake| {
+                let head = snake.get_head();
+                let sweep = head_sweep(snake, field_size);
+                let reach = (snake.size + other.size).pow(2) as f32;
+                (0..other.bodies.len()).any(|index| {
+                    let start = torus_delta(head, &other.bodies[index], field_size);
+                    let end = other.bodies.get(index + 1).map_or(start, |next| {
+                        let step = torus_delta(&other.bodies[index], next, field_size);
+                        Coordinate {
+                            x: start.x + step.x,
+                            y: start.y + step.y,
+                        }
+                    });
+                    segment_distance2(&Coordinate::default(), &sweep, &start, &end) <= reach
+                        && (index != 0 || runs_into_neck(snake, other, field_size))
+                })
+            };
+
+            let mut dead_snakes: BTreeSet<Uuid> = BTreeSet::new();
+            let snake_ids: Vec<Uuid> = self.snakes.keys().copied().collect();
+            for (index, id1) in snake_ids.iter().enumerate() {
+                for id2 in snake_ids.iter().skip(index + 1) {
+                    let snake1 = &self.snakes[id1];
+                    let snake2 = &self.snakes[id2];
+
+                    if heads_meet(snake1, snake2, field_size) {
+                        dead_snakes.insert(self.head_to_head(id1, id2).0);
+                        continue;
+                    }
+                    if hits(snake1, snake2) {
+                        dead_snakes.insert(*id1);
+                    }
+                    if hits(snake2, snake1) {
+                        dead_snakes.insert(*id2);
+                    }
+                }
+            }
+            dead_snakes.into_iter().collect()
+        }
+    }
+
+    fn crowded_engine(seed: u64) -> GameEngine {
+        let config = GameConfig {
+            field_size: 1000.0,
+            spawn_protection_frames: 0,
+            ..GameConfig::default()
+        };
+        let mut engine = GameEngine::with_seed(config, seed);
+        let mut rng = GameRng::seed_from_u64(seed);
+        for number in 0..24 {
+            let id = Uuid::from_u128(number);
+            engine.add_snake(id);
+            let snake = engine.get_snake_mut(&id).unwrap();
+            let heading: f32 = rng.random_range(0.0..std::f32::consts::TAU);
+            let length = rng.random_range(10..120);
+            let spacing = rng.random_range(2.0..40.0);
+            let mut position = *snake.get_head();
+            snake.bodies.clear();
+            for _ in 0..length {
+                snake.bodies.push_back(position);
+                position = Coordinate {
+                    x: (position.x - heading.cos() * spacing).rem_euclid(1000.0),
+                    y: (position.y - heading.sin() * spacing).rem_euclid(1000.0),
+                };
+            }
+            snake.size = rng.random_range(15..=40);
+            snake.acceleration_time_left = if rng.random_bool(0.3) { 10 } else { 0 };
+        }
+        engine.rebuild_body_grid();
+        engine
+    }
+
+    #[test]
+    fn indexed_collisions_match_the_brute_force_pass() {
+        let mut deaths = 0;
+        for seed in 0..200 {
+            let mut indexed = crowded_engine(seed);
+            let mut brute_force = GameEngine::restore(indexed.snapshot());
+
+            let expected = brute_force.detect_collisions_brute_force();
+            let dead: Vec<Uuid> = indexed
+                .detect_collisions()
+                .into_iter()
+                .map(|(victim, _, _)| victim)
+                .collect();
+            assert_eq!(dead, expected, "seed {seed}");
+            deaths += expected.len();
+        }
+
+        assert!(deaths > 0, "the scenarios should contain collisions");
+    }
+
+    #[test]
+    fn body_grid_follows_snakes_that_are_added_and_removed() {
+        let id = Uuid::from_u128(1);
+        let mut engine = GameEngine::default();
+        let position = Coordinate { x: 500.0, y: 500.0 };
+
+        engine.add_snake_at(id, position);
+        assert_eq!(engine.nearby_body_entries(&position, 1.0).len(), 10);
+
+        engine.remove_snake(&id);
+        assert!(engine.nearby_body_entries(&position, 1.0).is_empty());
+    }
+
+    #[test]
+    fn body_grid_finds_bodies_across_the_field_boundary() {
+        let id = Uuid::from_u128(1);
+        let mut engine = GameEngine::default();
+        let field_size = engine.config.field_size;
+        engine.add_snake_at(
+            id,
+            Coordinate {
+                x: field_size - 10.0,
+                y: 50.0,
+            },
+        );
+
+        let nearby = engine.nearby_body_entries(&Coordinate { x: 5.0, y: 50.0 }, 20.0);
+
+        assert!(nearby.iter().all(|entry| entry.id == id));
+        assert_eq!(nearby.len(), 10);
+    }
+
+    fn place_line(
+        engine: &mut GameEngine,
+        id: Uuid,
+        head: Coordinate,
+        step: Coordinate,
+        len: usize,
+    ) {
+        engine.add_snake_at(id, head);
+        let snake = engine.get_snake_mut(&id).unwrap();
+        snake.bodies = (0..len)
+            .map(|index| Coordinate {
+                x: head.x - step.x * index as f32,
+                y: head.y - step.y * index as f32,
+            })
+            .collect();
+    }
+
+    #[test]
+    fn boosted_head_cannot_jump_over_a_body() {
+        let runner = Uuid::from_u128(1);
+        let wall = Uuid::from_u128(2);
+        let config = GameConfig {
+            snake_speed: 40.0,
+            spawn_protection_frames: 0,
+            ..GameConfig::default()
+        };
+        let mut engine = GameEngine::with_seed(config, 1);
+        place_line(
+            &mut engine,
+            wall,
+            Coordinate { x: 500.0, y: 300.0 },
+            Coordinate { x: 0.0, y: -5.0 },
+            80,
+        );
+        place_line(
+            &mut engine,
+            runner,
+            Coordinate { x: 465.0, y: 500.0 },
+            Coordinate { x: 5.0, y: 0.0 },
+            20,
+        );
+        let snake = engine.get_snake_mut(&runner).unwrap();
+        snake.velocity = Coordinate { x: 1.0, y: 0.0 };
+        snake.target_velocity = snake.velocity;
+        snake.acceleration_time_left = 10;
+
+        let deaths = engine.forward().deaths;
+
+        // The head moved from 35 units before the body to 45 units past it,
+        // so neither end of the move is within the 30 unit reach.
+        assert_eq!(deaths.len(), 1);
+        assert_eq!(deaths[0].id, runner);
+        assert_eq!(deaths[0].killer, Some(wall));
+        assert_eq!(deaths[0].cause, DeathCause::Body);
+    }
+
+    #[test]
+    fn head_cannot_slip_between_sparse_body_points() {
+        let runner = Uuid::from_u128(1);
+        let wall = Uuid::from_u128(2);
+        let config = GameConfig {
+            spawn_protection_frames: 0,
+            ..GameConfig::default()
+        };
+        let mut engine = GameEngine::with_seed(config, 1);
+        place_line(
+            &mut engine,
+            wall,
+            Coordinate { x: 500.0, y: 380.0 },
+            Coordinate { x: 0.0, y: -80.0 },
+            4,
+        );
+        place_line(
+            &mut engine,
+            runner,
+            Coordinate { x: 497.0, y: 500.0 },
+            Coordinate { x: 5.0, y: 0.0 },
+            10,
+        );
+        let snake = engine.get_snake_mut(&runner).unwrap();
+        snake.velocity = Coordinate { x: 1.0, y: 0.0 };
+        snake.target_velocity = snake.velocity;
+
+        let deaths = engine.forward().deaths;
+
+        // The body points at y = 460 and y = 540 are 40 units from the head
+        // at x = 502, but the capsule between them is not.
+        assert_eq!(deaths.len(), 1);
+        assert_eq!(deaths[0].id, runner);
+    }
+
+    #[test]
+    fn hitting_a_neck_from_the_side_is_a_body_hit() {
+        let small = Uuid::from_u128(1);
+        let large = Uuid::from_u128(2);
+        let config = GameConfig {
+            spawn_protection_frames: 0,
+            ..GameConfig::default()
+        };
+        let mut engine = GameEngine::with_seed(config, 1);
+        place_line(
+            &mut engine,
+            large,
+            Coordinate { x: 500.0, y: 500.0 },
+            Coordinate { x: 80.0, y: 0.0 },
+            5,
+        );
+        place_line(
+            &mut engine,
+            small,
+            Coordinate { x: 460.0, y: 470.0 },
+            Coordinate { x: 0.0, y: 25.0 },
+            5,
+        );
+        engine.get_snake_mut(&large).unwrap().size = 20;
+        engine.get_snake_mut(&small).unwrap().size = 15;
+        engine.rebuild_body_grid();
+
+        // The small head ends 30 units from the neck between x = 420 and
+        // x = 500, but the large head had already passed: the heads were
+        // never within the 35 unit reach of each other.
+        assert_eq!(
+            engine.detect_collisions(),
+            vec![(small, large, DeathCause::Body)]
+        );
+    }
+}
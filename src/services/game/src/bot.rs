@@ -1,8 +1,8 @@
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::coordinate::Coordinate;
-use crate::engine::{GameEngine, FIELD_SIZE};
+use crate::coordinate::{torus_delta, Coordinate};
+use crate::engine::GameEngine;
 
 pub const ACTION_COUNT: usize = 7;
 pub const STATE_COUNT: usize = 216;
@@ -121,12 +121,13 @@ impl GameEngine {
         }
         let head = *snake.get_head();
         let current_heading = heading(snake.velocity, snake.target_velocity);
+        let field_size = self.config.field_size;
 
         self.snakes
             .iter()
-            .filter(|(other_id, _)| *other_id != id)
+            .filter(|(other_id, other)| *other_id != id && !snake.is_teammate_of(other))
             .filter_map(|(_, other)| {
-                let delta = torus_delta(&head, other.get_head());
+                let delta = torus_delta(&head, other.get_head(), field_size);
                 let distance = (delta.x * delta.x + delta.y * delta.y).sqrt();
                 let target_heading = delta.y.atan2(delta.x);
                 let relative_angle = normalize_angle(target_heading - current_heading);
@@ -139,15 +140,19 @@ impl GameEngine {
     }
 
     pub fn apply_bot_attack(&mut self, id: &Uuid, target_heading: f32) {
-        let Some(snake) = self.snakes.get_mut(id) else {
+        let Some(snake) = self.snakes.get(id) else {
             return;
         };
-        snake.target_velocity = Coordinate {
-            x: target_heading.cos(),
-            y: target_heading.sin(),
-        };
-        if snake.acceleration_time_left == 0 {
-            snake.accelerate();
+        let is_accelerating = snake.acceleration_time_left > 0;
+        self.set_target_velocity(
+            id,
+            Coordinate {
+                x: target_heading.cos(),
+                y: target_heading.sin(),
+            },
+        );
+        if !is_accelerating {
+            self.accelerate(id);
         }
     }
 
@@ -159,12 +164,17 @@ impl GameEngine {
         let snake = self.snakes.get(id)?;
         let head = *snake.get_head();
         let heading = heading(snake.velocity, snake.target_velocity);
+        let field_size = self.config.field_size;
 
-        let nearby =
-            Self::nearby_pellet_ids_with_radius(&self.pellet_grid, &head, BOT_PELLET_SEARCH_RADIUS);
+        let nearby = Self::nearby_pellet_ids_with_radius(
+            &self.config,
+            &self.pellet_grid,
+            &head,
+            BOT_PELLET_SEARCH_RADIUS,
+        );
         let candidate = |pellet_id: Uuid| {
             self.pellets.get(&pellet_id).map(|pellet| {
-                let delta = torus_delta(&head, &pellet.position);
+                let delta = torus_delta(&head, &pellet.position, field_size);
                 let distance = (delta.x * delta.x + delta.y * delta.y).sqrt();
                 let target_heading = delta.y.atan2(delta.x);
                 let relative_angle = normalize_angle(target_heading - heading);
@@ -185,7 +195,16 @@ impl GameEngine {
                     .filter_map(|pellet_id| candidate(*pellet_id))
                     .filter(|value| target_is_reachable(value.2, value.4))
                     .min_by(|left, right| left.3.total_cmp(&right.3))
-            });
+            })
+            // Outside the battle-royale zone, head back in instead of eating
+            .filter(|_| self.is_inside_zone(&head));
+        let fallback_heading = match self.zone() {
+            Some(zone) if !zone.contains(&head, field_size) => {
+                let delta = torus_delta(&head, &zone.center, field_size);
+                delta.y.atan2(delta.x)
+            }
+            _ => heading,
+        };
 
         let has_pellet = nearest.is_some();
         let (pellet_bucket, pellet_distance, target_heading, target_id) = nearest
@@ -213,24 +232,31 @@ impl GameEngine {
                 };
                 (bucket, distance, target_angle, Some(target_id))
             })
-            .unwrap_or((4, 300.0, heading, None));
+            .unwrap_or((4, 300.0, fallback_heading, None));
 
         let mut danger_mask = 0;
+        let probe_distance = (snake.size + self.max_snake_size()) as f32 + 8.0;
         for (index, degrees) in DANGER_ANGLES.iter().enumerate() {
             let angle = heading + degrees.to_radians();
             let lookahead = Coordinate {
-                x: (head.x + angle.cos() * 120.0).rem_euclid(FIELD_SIZE),
-                y: (head.y + angle.sin() * 120.0).rem_euclid(FIELD_SIZE),
+                x: (head.x + angle.cos() * 120.0).rem_euclid(field_size),
+                y: (head.y + angle.sin() * 120.0).rem_euclid(field_size),
             };
-            let danger = self.snakes.iter().any(|(other_id, other)| {
-                other.bodies.iter().enumerate().any(|(body_index, body)| {
-                    if other_id == id && body_index < 10 {
-                        return false;
-                    }
-                    let clearance = (snake.size + other.size) as f32 + 8.0;
-                    lookahead.distance2(body) <= clearance * clearance
-                })
-            });
+            let danger = !self.is_inside_zone(&lookahead)
+                || self.is_blocked(&lookahead, snake.size as f32 + 8.0)
+                || self
+                    .nearby_body_entries(&lookahead, probe_distance)
+                    .iter()
+                    .any(|entry| {
+                        if entry.id == *id && entry.index < 10 {
+                            return false;
+                        }
+                        let Some(other) = self.snakes.get(&entry.id) else {
+                            return false;
+                        };
+                        let clearance = (snake.size + other.size) as f32 + 8.0;
+                        lookahead.distance2(&entry.position, field_size) <= clearance * clearance
+                    });
             if danger {
                 danger_mask |= 1 << index;
             }
@@ -255,15 +281,18 @@ impl GameEngine {
     }
 
     pub fn apply_bot_action(&mut self, id: &Uuid, observation: BotObservation, action: usize) {
-        let Some(snake) = self.snakes.get_mut(id) else {
+        if !self.snakes.contains_key(id) {
             return;
-        };
+        }
         let offset = AIM_OFFSETS[action.min(ACTION_COUNT - 1)].to_radians();
         let target = observation.target_heading + offset;
-        snake.target_velocity = Coordinate {
-            x: target.cos(),
-            y: target.sin(),
-        };
+        self.set_target_velocity(
+            id,
+            Coordinate {
+                x: target.cos(),
+                y: target.sin(),
+            },
+        );
     }
 }
 
@@ -281,17 +310,6 @@ fn heading(velocity: Coordinate, target: Coordinate) -> f32 {
     }
 }
 
-fn torus_delta(origin: &Coordinate, target: &Coordinate) -> Coordinate {
-    Coordinate {
-        x: signed_axis_delta(target.x - origin.x),
-        y: signed_axis_delta(target.y - origin.y),
-    }
-}
-
-fn signed_axis_delta(delta: f32) -> f32 {
-    (delta + FIELD_SIZE / 2.0).rem_euclid(FIELD_SIZE) - FIELD_SIZE / 2.0
-}
-
 fn normalize_angle(angle: f32) -> f32 {
     (angle + std::f32::consts::PI).rem_euclid(std::f32::consts::TAU) - std::f32::consts::PI
 }
@@ -307,6 +325,7 @@ fn target_is_reachable(distance: f32, relative_angle: f32) -> bool {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::GameConfig;
 
     #[test]
     fn zero_policy_falls_back_to_straight() {
@@ -355,7 +374,7 @@ mod tests {
     #[test]
     fn observations_stay_inside_the_q_table() {
         let id = Uuid::new_v4();
-        let mut engine = GameEngine::new();
+        let mut engine = GameEngine::default();
         engine.add_snake_at(id, Coordinate { x: 100.0, y: 100.0 });
         engine.forward();
 
@@ -366,7 +385,7 @@ mod tests {
     #[test]
     fn bot_actions_do_not_consume_length_by_accelerating() {
         let id = Uuid::new_v4();
-        let mut engine = GameEngine::new();
+        let mut engine = GameEngine::default();
         engine.add_snake_at(id, Coordinate { x: 100.0, y: 100.0 });
         let snake = engine.get_snake_mut(&id).unwrap();
         while snake.bodies.len() < 20 {
@@ -382,7 +401,7 @@ mod tests {
     #[test]
     fn direct_action_aims_at_the_selected_pellet() {
         let id = Uuid::new_v4();
-        let mut engine = GameEngine::new();
+        let mut engine = GameEngine::default();
         engine.add_snake_at(id, Coordinate { x: 100.0, y: 100.0 });
         engine.forward();
         let observation = engine.bot_observation(&id, None).unwrap();
@@ -405,7 +424,7 @@ mod tests {
     #[test]
     fn preferred_target_is_kept_until_the_pellet_disappears() {
         let id = Uuid::new_v4();
-        let mut engine = GameEngine::new();
+        let mut engine = GameEngine::default();
         engine.add_snake_at(id, Coordinate { x: 100.0, y: 100.0 });
         engine.forward();
         let first = engine.bot_observation(&id, None).unwrap();
@@ -432,7 +451,7 @@ mod tests {
     fn bot_dashes_toward_an_opponent_in_front() {
         let attacker = Uuid::new_v4();
         let opponent = Uuid::new_v4();
-        let mut engine = GameEngine::new();
+        let mut engine = GameEngine::default();
         engine.add_snake_at(attacker, Coordinate { x: 100.0, y: 100.0 });
         engine.add_snake_at(opponent, Coordinate { x: 250.0, y: 100.0 });
         let snake = engine.get_snake_mut(&attacker).unwrap();
@@ -452,7 +471,7 @@ mod tests {
     #[test]
     fn active_attack_dash_is_not_restarted_every_frame() {
         let attacker = Uuid::new_v4();
-        let mut engine = GameEngine::new();
+        let mut engine = GameEngine::default();
         engine.add_snake_at(attacker, Coordinate { x: 100.0, y: 100.0 });
         let snake = engine.get_snake_mut(&attacker).unwrap();
         while snake.bodies.len() < MIN_ATTACK_LENGTH {
@@ -472,7 +491,10 @@ mod tests {
     fn attack_dash_defeats_a_non_accelerating_opponent() {
         let attacker = Uuid::new_v4();
         let opponent = Uuid::new_v4();
-        let mut engine = GameEngine::new();
+        let mut engine = GameEngine::new(GameConfig {
+            spawn_protection_frames: 0,
+            ..GameConfig::default()
+        });
         engine.add_snake_at(attacker, Coordinate { x: 100.0, y: 100.0 });
         engine.add_snake_at(opponent, Coordinate { x: 160.0, y: 100.0 });
         let snake = engine.get_snake_mut(&attacker).unwrap();
@@ -0,0 +1,221 @@
+use serde::{Deserialize, Serialize};
+
+use super::food::PelletDistribution;
+use super::level::Obstacle;
+use super::team::TEAM_COLORS;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GameConfig {
+    /// Side length of the square torus the snakes live on.
+    pub field_size: f32,
+    /// The engine tops the pellet count up to this value every frame.
+    pub max_pellet_count: usize,
+    /// Pellets dropped by boosting, starving or dying snakes may push the
+    /// pellet count this far past `max_pellet_count`, and no further.
+    pub max_dropped_pellet_count: usize,
+    /// Frames a dropped pellet lasts before it decays, or 0 for forever.
+    pub dropped_pellet_frames: u32,
+    /// Where fresh pellets spawn.
+    pub pellet_distribution: PelletDistribution,
+    /// Frames between two feasts, or 0 for none. A feast scatters a dense
+    /// cluster of pellets somewhere on the field.
+    pub feast_interval_frames: u32,
+    /// Side length of a cell in the pellet spatial index.
+    pub pellet_cell_size: f32,
+    /// Number of minimap cells along each axis.
+    pub map_size: usize,
+    /// Distance a snake head moves per frame without boosting.
+    pub snake_speed: f32,
+    /// Number of frames a single boost lasts.
+    pub boost_frames: u32,
+    /// Radius of a freshly spawned snake.
+    pub base_snake_size: usize,
+    /// A snake grows by one radius unit every `snake_growth_interval` bodies.
+    pub snake_growth_interval: usize,
+    /// Upper bound of the snake radius.
+    pub max_snake_size: usize,
+    /// Frames a freshly spawned snake can neither die nor kill.
+    pub spawn_protection_frames: u32,
+    /// Number of teams, or 0 for every snake for itself. Teammates pass
+    /// through each other.
+    pub team_count: usize,
+    /// Frames the battle-royale zone takes to shrink from around the whole
+    /// field to nothing, or 0 for no zone. Snakes outside it lose a body
+    /// point per frame.
+    pub zone_shrink_frames: u32,
+    /// Odds that a freshly spawned pellet is a power-up instead of food.
+    pub power_up_chance: f32,
+    /// Frames a collected power-up lasts.
+    pub power_up_frames: u32,
+    /// The static obstacles of the arena, usually loaded from a level file.
+    pub obstacles: Vec<Obstacle>,
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        Self {
+            field_size: 10000.0,
+            max_pellet_count: 5_000,
+            max_dropped_pellet_count: 2_500,
+            dropped_pellet_frames: 1_800,
+            pellet_distribution: PelletDistribution::Uniform,
+            feast_interval_frames: 0,
+            pellet_cell_size: 100.0,
+            map_size: 100,
+            snake_speed: 5.0,
+            boost_frames: 60,
+            base_snake_size: 15,
+            snake_growth_interval: 50,
+            max_snake_size: 40,
+            spawn_protection_frames: 90,
+            team_count: 0,
+            zone_shrink_frames: 0,
+            power_up_chance: 0.002,
+            power_up_frames: 300,
+            obstacles: Vec::new(),
+        }
+    }
+}
+
+impl GameConfig {
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        let config: Self = serde_json::from_str(json)?;
+        config.validate().map_err(|message| {
+            serde_json::Error::io(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                message,
+            ))
+        })?;
+        Ok(config)
+    }
+
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
+
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if !self.field_size.is_finite() || self.field_size <= 0.0 {
+            return Err("field_size must be positive");
+        }
+        if !self.pellet_cell_size.is_finite()
+            || self.pellet_cell_size <= 0.0
+            || self.pellet_cell_size > self.field_size
+        {
+            return Err("pellet_cell_size must be positive and fit in the field");
+        }
+        if self.map_size == 0 {
+            return Err("map_size must be positive");
+        }
+        if !self.snake_speed.is_finite() || self.snake_speed < 0.0 {
+            return Err("snake_speed must not be negative");
+        }
+        if self.snake_growth_interval == 0 {
+            return Err("snake_growth_interval must be positive");
+        }
+        if self.base_snake_size == 0 || self.max_snake_size < self.base_snake_size {
+            return Err("max_snake_size must not be smaller than base_snake_size");
+        }
+        if !(0.0..=1.0).contains(&self.power_up_chance) {
+            return Err("power_up_chance must be between 0 and 1");
+        }
+        if !self.obstacles.iter().all(Obstacle::is_valid) {
+            return Err("obstacles must have finite positions and positive sizes");
+        }
+        if !self
+            .obstacles
+            .iter()
+            .all(|obstacle| obstacle.fits_in(self.field_size))
+        {
+            return Err("walls must be shorter than half the field");
+        }
+        if self.team_count > TEAM_COLORS.len() {
+            return Err("team_count must not exceed the number of team colors");
+        }
+        Ok(())
+    }
+
+    pub fn pellet_limit(&self) -> usize {
+        self.max_pellet_count + self.max_dropped_pellet_count
+    }
+
+    pub fn decay_frame(&self, frame_count: u32) -> Option<u32> {
+        //! The frame a pellet dropped at `frame_count` decays at.
+
+        (self.dropped_pellet_frames > 0).then(|| frame_count + self.dropped_pellet_frames)
+    }
+
+    pub fn pellet_grid_size(&self) -> usize {
+        ((self.field_size / self.pellet_cell_size).floor() as usize).max(1)
+    }
+
+    pub fn pellet_cell_width(&self) -> f32 {
+        //! The configured cell size stretched so that the cells tile the torus
+        //! exactly. Neighbouring-cell lookups rely on every cell being at least
+        //! `pellet_cell_size` wide.
+
+        self.field_size / self.pellet_grid_size() as f32
+    }
+
+    pub fn snake_size(&self, length: usize) -> usize {
+        (self.base_snake_size + length / self.snake_growth_interval).min(self.max_snake_size)
+    }
+}
+
+#[cfg(test)]
+impl GameConfig {
+    pub(crate) fn small_test_field() -> Self {
+        //! A small field without pellets or spawn protection, for tests that
+        //! place their snakes by hand.
+
+        Self {
+            field_size: 1000.0,
+            max_pellet_count: 0,
+            map_size: 10,
+            spawn_protection_frames: 0,
+            ..Self::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_fields_fall_back_to_the_defaults() {
+        let config = GameConfig::from_json(r#"{"field_size": 2000.0}"#).unwrap();
+
+        assert_eq!(config.field_size, 2000.0);
+        assert_eq!(
+            config.max_pellet_count,
+            GameConfig::default().max_pellet_count
+        );
+    }
+
+    #[test]
+    fn rejects_a_degenerate_field() {
+        assert!(GameConfig::from_json(r#"{"field_size": 0.0}"#).is_err());
+        assert!(GameConfig::from_json(r#"{"snake_growth_interval": 0}"#).is_err());
+    }
+
+    #[test]
+    fn snake_size_follows_the_default_curve() {
+        let config = GameConfig::default();
+
+        assert_eq!(config.snake_size(10), 15);
+        assert_eq!(config.snake_size(100), 17);
+        assert_eq!(config.snake_size(10_000), 40);
+    }
+
+    #[test]
+    fn pellet_cells_tile_fields_that_are_not_a_multiple_of_the_cell() {
+        let config = GameConfig {
+            field_size: 950.0,
+            ..GameConfig::default()
+        };
+
+        assert_eq!(config.pellet_grid_size(), 9);
+        assert!(config.pellet_cell_width() >= config.pellet_cell_size);
+    }
+}
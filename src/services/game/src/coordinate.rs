@@ -1,7 +1,5 @@
 use serde::{Deserialize, Serialize};
 
-const FIELD_SIZE: f32 = 10000.0;
-
 #[derive(Serialize, Deserialize, Default, Clone, Copy, Debug, PartialEq)]
 #[serde(into = "(f32, f32)", from = "(f32, f32)")]
 pub struct Coordinate {
@@ -10,21 +8,31 @@ pub struct Coordinate {
 }
 
 impl Coordinate {
-    pub fn distance2(&self, other: &Coordinate) -> f32 {
-        let dx = (self.x - other.x).abs().rem_euclid(FIELD_SIZE);
-        let dy = (self.y - other.y).abs().rem_euclid(FIELD_SIZE);
-        let dx = dx.min(FIELD_SIZE - dx);
-        let dy = dy.min(FIELD_SIZE - dy);
+    pub fn distance2(&self, other: &Coordinate, field_size: f32) -> f32 {
+        //! Squared distance on a torus whose sides are `field_size` long.
+
+        let dx = (self.x - other.x).abs().rem_euclid(field_size);
+        let dy = (self.y - other.y).abs().rem_euclid(field_size);
+        let dx = dx.min(field_size - dx);
+        let dy = dy.min(field_size - dy);
 
         dx.powi(2) + dy.powi(2)
     }
 
-    pub fn is_in_rectangle(&self, x0: f32, y0: f32, width: f32, height: f32) -> bool {
+    pub fn is_in_rectangle(
+        &self,
+        x0: f32,
+        y0: f32,
+        width: f32,
+        height: f32,
+        field_size: f32,
+    ) -> bool {
         //! Check if the coordinate is in the rectangle.
         //! Left-top corner is (x0, y0) and the size is (width, height).
         //! Note that the rectangle is on the torus.
 
-        axis_contains(self.x, x0, width) && axis_contains(self.y, y0, height)
+        axis_contains(self.x, x0, width, field_size)
+            && axis_contains(self.y, y0, height, field_size)
     }
 }
 
@@ -40,29 +48,84 @@ impl From<(f32, f32)> for Coordinate {
     }
 }
 
-fn axis_contains(value: f32, start: f32, length: f32) -> bool {
+fn axis_contains(value: f32, start: f32, length: f32, field_size: f32) -> bool {
     if !value.is_finite() || !start.is_finite() || !length.is_finite() || length < 0.0 {
         return false;
     }
-    if length >= FIELD_SIZE {
+    if length >= field_size {
         return true;
     }
 
-    let value = value.rem_euclid(FIELD_SIZE);
-    let start = start.rem_euclid(FIELD_SIZE);
+    let value = value.rem_euclid(field_size);
+    let start = start.rem_euclid(field_size);
     let end = start + length;
 
-    if end <= FIELD_SIZE {
+    if end <= field_size {
         start <= value && value <= end
     } else {
-        start <= value || value <= end - FIELD_SIZE
+        start <= value || value <= end - field_size
+    }
+}
+
+pub(crate) fn torus_delta(origin: &Coordinate, target: &Coordinate, field_size: f32) -> Coordinate {
+    //! The shortest offset from `origin` to `target` on the torus.
+
+    Coordinate {
+        x: signed_axis_delta(target.x - origin.x, field_size),
+        y: signed_axis_delta(target.y - origin.y, field_size),
+    }
+}
+
+fn signed_axis_delta(delta: f32, field_size: f32) -> f32 {
+    (delta + field_size / 2.0).rem_euclid(field_size) - field_size / 2.0
+}
+
+pub(crate) fn segment_distance2(
+    a0: &Coordinate,
+    a1: &Coordinate,
+    b0: &Coordinate,
+    b1: &Coordinate,
+) -> f32 {
+    //! Squared distance between the segments `a0`-`a1` and `b0`-`b1` on the
+    //! plane. Callers unwrap torus coordinates with `torus_delta` first.
+
+    let cross = |origin: &Coordinate, end: &Coordinate, point: &Coordinate| {
+        (end.x - origin.x) * (point.y - origin.y) - (end.y - origin.y) * (point.x - origin.x)
+    };
+    let d1 = cross(b0, b1, a0);
+    let d2 = cross(b0, b1, a1);
+    let d3 = cross(a0, a1, b0);
+    let d4 = cross(a0, a1, b1);
+    if d1 * d2 < 0.0 && d3 * d4 < 0.0 {
+        return 0.0;
     }
+
+    point_segment_distance2(a0, b0, b1)
+        .min(point_segment_distance2(a1, b0, b1))
+        .min(point_segment_distance2(b0, a0, a1))
+        .min(point_segment_distance2(b1, a0, a1))
+}
+
+fn point_segment_distance2(point: &Coordinate, start: &Coordinate, end: &Coordinate) -> f32 {
+    let dx = end.x - start.x;
+    let dy = end.y - start.y;
+    let length2 = dx * dx + dy * dy;
+    let t = if length2 > 0.0 {
+        (((point.x - start.x) * dx + (point.y - start.y) * dy) / length2).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let x = start.x + dx * t - point.x;
+    let y = start.y + dy * t - point.y;
+    x * x + y * y
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    const FIELD_SIZE: f32 = 10000.0;
+
     #[test]
     fn distance_wraps_around_the_field_edges() {
         let left = Coordinate { x: 5.0, y: 10.0 };
@@ -71,7 +134,7 @@ mod tests {
             y: 10.0,
         };
 
-        assert_eq!(left.distance2(&right), 100.0);
+        assert_eq!(left.distance2(&right, FIELD_SIZE), 100.0);
     }
 
     #[test]
@@ -79,14 +142,71 @@ mod tests {
         let inside = Coordinate { x: 50.0, y: 50.0 };
         let outside = Coordinate { x: 500.0, y: 50.0 };
 
-        assert!(inside.is_in_rectangle(0.0, 0.0, 100.0, 100.0));
-        assert!(!outside.is_in_rectangle(0.0, 0.0, 100.0, 100.0));
+        assert!(inside.is_in_rectangle(0.0, 0.0, 100.0, 100.0, FIELD_SIZE));
+        assert!(!outside.is_in_rectangle(0.0, 0.0, 100.0, 100.0, FIELD_SIZE));
     }
 
     #[test]
     fn rectangle_wraps_around_the_field_edges() {
         let wrapped = Coordinate { x: 25.0, y: 50.0 };
 
-        assert!(wrapped.is_in_rectangle(FIELD_SIZE - 50.0, 0.0, 100.0, 100.0));
+        assert!(wrapped.is_in_rectangle(FIELD_SIZE - 50.0, 0.0, 100.0, 100.0, FIELD_SIZE));
+    }
+
+    #[test]
+    fn distance_wraps_around_a_smaller_field() {
+        let left = Coordinate { x: 5.0, y: 10.0 };
+        let right = Coordinate { x: 995.0, y: 10.0 };
+
+        assert_eq!(left.distance2(&right, 1000.0), 100.0);
+    }
+
+    #[test]
+    fn torus_delta_takes_the_short_way_around() {
+        let delta = torus_delta(
+            &Coordinate { x: 995.0, y: 10.0 },
+            &Coordinate { x: 5.0, y: 990.0 },
+            1000.0,
+        );
+
+        assert_eq!(delta, Coordinate { x: 10.0, y: -20.0 });
+    }
+
+    #[test]
+    fn crossing_segments_touch() {
+        let distance = segment_distance2(
+            &Coordinate { x: -10.0, y: 0.0 },
+            &Coordinate { x: 10.0, y: 0.0 },
+            &Coordinate { x: 0.0, y: -10.0 },
+            &Coordinate { x: 0.0, y: 10.0 },
+        );
+
+        assert_eq!(distance, 0.0);
+    }
+
+    #[test]
+    fn parallel_segments_are_their_gap_apart() {
+        let distance = segment_distance2(
+            &Coordinate { x: 0.0, y: 0.0 },
+            &Coordinate { x: 10.0, y: 0.0 },
+            &Coordinate { x: 5.0, y: 3.0 },
+            &Coordinate { x: 20.0, y: 3.0 },
+        );
+
+        assert_eq!(distance, 9.0);
+    }
+
+    #[test]
+    fn degenerate_segments_are_points() {
+        let point = Coordinate { x: 3.0, y: 4.0 };
+
+        let distance = segment_distance2(
+            &point,
+            &point,
+            &Coordinate::default(),
+            &Coordinate::default(),
+        );
+
+        assert_eq!(distance, 25.0);
     }
 }
@@ -1,107 +1,217 @@
-use rand::RngExt;
-use std::collections::{HashMap, HashSet, VecDeque};
-// TODO: Consider using BuildHasher trait
-use std::cmp::Ordering;
-use uuid::Uuid;
-
+use rand::rngs::Xoshiro256PlusPlus;
+use rand::{RngExt, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::{BuildHasherDefault, DefaultHasher};
+use uuid::{Builder, Uuid};
+
+use super::body_grid::BodyEntry;
+use super::config::GameConfig;
 use super::coordinate::Coordinate;
-use super::map::Map;
+use super::food::food_patches;
+use super::level::obstacle_cells;
+use super::minimap::DensityGrid;
 use super::pellet::Pellet;
+use super::replay::{Input, Replay};
 use super::snake::Snake;
-use super::view::View;
-
-pub(crate) const FIELD_SIZE: f32 = 10000.0;
-const MAX_PELLET_COUNT: usize = 5_000;
-const MAP_SIZE: usize = 100;
-const PELLET_CELL_SIZE: f32 = 100.0;
-const PELLET_GRID_SIZE: usize = (FIELD_SIZE / PELLET_CELL_SIZE) as usize;
+use super::view::{EntityHandles, View};
+use super::view_stream::{ViewPellet, ViewSnake, ViewState};
+
+/// The single random number generator that drives every random decision of
+/// the engine. Seeding it makes a run reproducible.
+pub type GameRng = Xoshiro256PlusPlus;
+
+/// Value of the pellets a dead snake drops, unless that would take more than
+/// `MAX_DEATH_DROP_COUNT` of them. Snakes too huge for that many pellets of
+/// the largest size drop more of them.
+const DEATH_DROP_VALUE: usize = 3;
+const MAX_DEATH_DROP_COUNT: usize = 40;
+/// Positions tried around a body point for a dropped pellet before its mass
+/// goes to the other pellets instead.
+const DEATH_DROP_ATTEMPTS: usize = 8;
+
+// `RandomState` reseeds per process, so iterating a standard `HashMap` visits
+// entities in a different order on every run. A fixed hasher keeps the order
+// (and therefore the sequence of random draws) identical for identical inputs.
+// Snakes are kept in a `BTreeMap` instead: their order must not depend on the
+// insertion history either, otherwise a restored snapshot would diverge.
+pub(crate) type EntityMap<V> = HashMap<Uuid, V, BuildHasherDefault<DefaultHasher>>;
+pub(crate) type EntitySet = HashSet<Uuid, BuildHasherDefault<DefaultHasher>>;
 
 pub struct GameEngine {
+    pub(crate) config: GameConfig,
+    pub(crate) seed: u64,
+    pub(crate) rng: GameRng,
     pub(crate) frame_count: u32,
-    pub(crate) snakes: HashMap<Uuid, Snake>,
-    pub(crate) pellets: HashMap<Uuid, Pellet>,
+    pub(crate) snakes: BTreeMap<Uuid, Snake>,
+    pub(crate) pellets: EntityMap<Pellet>,
     pub(crate) pellet_grid: Vec<Vec<Uuid>>,
+    /// Every body point, indexed with the same cells as `pellet_grid`.
+    /// Rebuilt each frame after the snakes move.
+    pub(crate) body_grid: Vec<Vec<BodyEntry>>,
+    pub(crate) pellet_density: DensityGrid,
+    pub(crate) snake_density: DensityGrid,
+    /// Snakes whose bodies are not counted in `snake_density`, because they
+    /// were handed out through `get_snake_mut()`.
+    pub(crate) detached_snakes: EntitySet,
+    pub(crate) recording: Option<Replay>,
+    /// The team of every player in one, whether or not they have a snake.
+    pub(crate) teams: BTreeMap<Uuid, u8>,
+    /// The frame the current battle-royale match started at.
+    pub(crate) zone_start_frame: u32,
+    /// Derived from the seed; only used with `PelletDistribution::Patches`.
+    pub(crate) food_patches: Vec<Coordinate>,
+    /// The minimap layer of the obstacles, which never move.
+    pub(crate) obstacle_cells: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeathCause {
+    /// Lost a head-to-head collision to a bigger snake.
+    HeadToHeadSize,
+    /// Lost a head-to-head collision to a boosting snake.
+    HeadToHeadBoost,
+    /// Lost the coin flip of a head-to-head collision between equals.
+    CoinFlip,
+    /// Ran into the body of another snake.
+    Body,
+    /// Removed through `remove_snake`, e.g. because the player left.
+    Disconnect,
+    /// Starved outside the battle-royale zone.
+    Zone,
+    /// Ran into a wall or another obstacle of the level.
+    Obstacle,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct DeathEvent {
     pub id: Uuid,
     pub score: usize,
+    /// The snake that won the collision, if any.
+    pub killer: Option<Uuid>,
+    pub cause: DeathCause,
+    /// Where the head was when the snake died.
+    pub position: Coordinate,
 }
 
 #[derive(Default)]
 pub struct FrameEvents {
     pub deaths: Vec<DeathEvent>,
     pub pellets_eaten: HashMap<Uuid, usize>,
+    /// In battle-royale mode, the last snake alive on the frame the others
+    /// died.
+    pub winner: Option<Uuid>,
+    /// Where a feast was laid out this frame.
+    pub feast: Option<Coordinate>,
 }
 
 impl Default for GameEngine {
     fn default() -> Self {
-        Self::new()
+        Self::new(GameConfig::default())
     }
 }
 
 impl GameEngine {
-    pub fn new() -> GameEngine {
+    pub fn new(config: GameConfig) -> GameEngine {
+        Self::with_seed(config, rand::random())
+    }
+
+    pub fn with_seed(config: GameConfig, seed: u64) -> GameEngine {
+        //! Create an engine whose every random decision is derived from `seed`.
+        //! Two engines with the same seed produce identical frames as long as
+        //! they receive identical inputs.
+
+        let pellet_grid_size = config.pellet_grid_size();
         GameEngine {
+            food_patches: food_patches(&config, seed),
+            obstacle_cells: obstacle_cells(&config),
+            pellet_density: DensityGrid::new(&config),
+            snake_density: DensityGrid::new(&config),
+            config,
+            seed,
+            rng: GameRng::seed_from_u64(seed),
             frame_count: 0,
-            snakes: HashMap::new(),
-            pellets: HashMap::new(),
-            pellet_grid: vec![Vec::new(); PELLET_GRID_SIZE * PELLET_GRID_SIZE],
+            snakes: BTreeMap::new(),
+            pellets: EntityMap::default(),
+            pellet_grid: vec![Vec::new(); pellet_grid_size * pellet_grid_size],
+            body_grid: vec![Vec::new(); pellet_grid_size * pellet_grid_size],
+            detached_snakes: EntitySet::default(),
+            recording: None,
+            teams: BTreeMap::new(),
+            zone_start_frame: 0,
         }
     }
 
-    fn pellet_cell(position: &Coordinate) -> (usize, usize) {
-        let x = (position.x.rem_euclid(FIELD_SIZE) / PELLET_CELL_SIZE).floor() as usize;
-        let y = (position.y.rem_euclid(FIELD_SIZE) / PELLET_CELL_SIZE).floor() as usize;
-        (x.min(PELLET_GRID_SIZE - 1), y.min(PELLET_GRID_SIZE - 1))
+    pub fn config(&self) -> &GameConfig {
+        &self.config
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub(crate) fn random_id(rng: &mut GameRng) -> Uuid {
+        Builder::from_random_bytes(rng.random()).into_uuid()
     }
 
-    fn pellet_cell_index(x: usize, y: usize) -> usize {
-        y * PELLET_GRID_SIZE + x
+    pub(crate) fn grid_cell(config: &GameConfig, position: &Coordinate) -> (usize, usize) {
+        let grid_size = config.pellet_grid_size();
+        let cell_width = config.pellet_cell_width();
+        let x = (position.x.rem_euclid(config.field_size) / cell_width).floor() as usize;
+        let y = (position.y.rem_euclid(config.field_size) / cell_width).floor() as usize;
+        (x.min(grid_size - 1), y.min(grid_size - 1))
     }
 
-    fn insert_pellet_into(
-        pellets: &mut HashMap<Uuid, Pellet>,
+    pub(crate) fn grid_cell_index(config: &GameConfig, x: usize, y: usize) -> usize {
+        y * config.pellet_grid_size() + x
+    }
+
+    pub(crate) fn insert_pellet_into(
+        config: &GameConfig,
+        pellets: &mut EntityMap<Pellet>,
         pellet_grid: &mut [Vec<Uuid>],
+        density: &mut DensityGrid,
         id: Uuid,
         mut pellet: Pellet,
         created_at_frame: u32,
     ) {
         pellet.frame_count_offset = created_at_frame;
-        let (x, y) = Self::pellet_cell(&pellet.center);
-        pellet_grid[Self::pellet_cell_index(x, y)].push(id);
+        let (x, y) = Self::grid_cell(config, &pellet.center);
+        pellet_grid[Self::grid_cell_index(config, x, y)].push(id);
+        density.add(&pellet.center);
         pellets.insert(id, pellet);
     }
 
-    fn remove_pellet_from(
-        pellets: &mut HashMap<Uuid, Pellet>,
+    pub(crate) fn remove_pellet_from(
+        config: &GameConfig,
+        pellets: &mut EntityMap<Pellet>,
         pellet_grid: &mut [Vec<Uuid>],
+        density: &mut DensityGrid,
         id: &Uuid,
     ) -> Option<Pellet> {
         let pellet = pellets.remove(id)?;
-        let (x, y) = Self::pellet_cell(&pellet.center);
-        pellet_grid[Self::pellet_cell_index(x, y)].retain(|candidate| candidate != id);
+        let (x, y) = Self::grid_cell(config, &pellet.center);
+        pellet_grid[Self::grid_cell_index(config, x, y)].retain(|candidate| candidate != id);
+        density.remove(&pellet.center);
         Some(pellet)
     }
 
-    pub(crate) fn nearby_pellet_ids(pellet_grid: &[Vec<Uuid>], position: &Coordinate) -> Vec<Uuid> {
-        Self::nearby_pellet_ids_with_radius(pellet_grid, position, 1)
-    }
-
     pub(crate) fn nearby_pellet_ids_with_radius(
+        config: &GameConfig,
         pellet_grid: &[Vec<Uuid>],
         position: &Coordinate,
         radius: isize,
     ) -> Vec<Uuid> {
-        let (center_x, center_y) = Self::pellet_cell(position);
+        let grid_size = config.pellet_grid_size();
+        let (center_x, center_y) = Self::grid_cell(config, position);
+        let x_cells = neighbour_cells(center_x, radius, grid_size);
+        let y_cells = neighbour_cells(center_y, radius, grid_size);
         let mut ids = Vec::new();
 
-        for dx in -radius..=radius {
-            for dy in -radius..=radius {
-                let x = (center_x as isize + dx).rem_euclid(PELLET_GRID_SIZE as isize) as usize;
-                let y = (center_y as isize + dy).rem_euclid(PELLET_GRID_SIZE as isize) as usize;
-                ids.extend_from_slice(&pellet_grid[Self::pellet_cell_index(x, y)]);
+        for &x in &x_cells {
+            for &y in &y_cells {
+                ids.extend_from_slice(&pellet_grid[Self::grid_cell_index(config, x, y)]);
             }
         }
 
@@ -109,24 +219,24 @@ impl GameEngine {
     }
 
     fn pellet_ids_in_rectangle(&self, x0: f32, y0: f32, width: f32, height: f32) -> Vec<Uuid> {
-        let x_cells = axis_cells(x0, width);
-        let y_cells = axis_cells(y0, height);
+        let x_cells = axis_cells(&self.config, x0, width);
+        let y_cells = axis_cells(&self.config, y0, height);
         let mut ids = Vec::new();
 
         for x in x_cells {
             for &y in &y_cells {
-                ids.extend_from_slice(&self.pellet_grid[Self::pellet_cell_index(x, y)]);
+                ids.extend_from_slice(&self.pellet_grid[Self::grid_cell_index(&self.config, x, y)]);
             }
         }
 
         ids
     }
 
-    pub fn get_random_coordinate(&self) -> Coordinate {
-        let rx = rand::rng().random_range(0.0..1.0);
-        let ry = rand::rng().random_range(0.0..1.0);
-        let x = FIELD_SIZE * rx;
-        let y = FIELD_SIZE * ry;
+    pub fn get_random_coordinate(&mut self) -> Coordinate {
+        let rx = self.rng.random_range(0.0..1.0);
+        let ry = self.rng.random_range(0.0..1.0);
+        let x = self.config.field_size * rx;
+        let y = self.config.field_size * ry;
         Coordinate { x, y }
     }
 
@@ -135,68 +245,162 @@ impl GameEngine {
     }
 
     pub fn get_snake_mut(&mut self, id: &Uuid) -> Option<&mut Snake> {
+        //! Moved bodies are picked up by the body grid and the minimap on the
+        //! next `forward()`.
+
+        self.detach_snake_density(id);
         self.snakes.get_mut(id)
     }
 
+    pub fn snake_ids(&self) -> Vec<Uuid> {
+        self.snakes.keys().copied().collect()
+    }
+
     pub fn score(&self, id: &Uuid) -> Option<usize> {
         self.snakes.get(id).map(|snake| snake.bodies.len())
     }
 
     pub fn add_snake(&mut self, id: Uuid) {
-        let snake: Snake = Snake::new(self.get_random_coordinate(), 5.0);
-        self.snakes.insert(id, snake);
+        self.record(Input::AddSnake(id));
+        let position = self.safe_spawn_position();
+        self.insert_snake(id, position);
     }
 
     pub fn add_snake_at(&mut self, id: Uuid, position: Coordinate) {
-        self.snakes.insert(id, Snake::new(position, 5.0));
-    }
-
-    pub fn remove_snake(&mut self, id: &Uuid) {
-        let dropped_pellets = self
-            .snakes
-            .get(id)
-            .map(|snake| {
-                snake
-                    .bodies
-                    .iter()
-                    .filter_map(|body| {
-                        if rand::rng().random_range(0..10) < 5 {
-                            let dx = rand::rng().random_range(-10.0..10.0);
-                            let dy = rand::rng().random_range(-10.0..10.0);
-                            Some(Pellet::new_with_color_and_size(
-                                Coordinate {
-                                    x: body.x + dx,
-                                    y: body.y + dy,
-                                },
-                                snake.color.clone(),
-                                3,
-                            ))
-                        } else {
-                            None
-                        }
-                    })
-                    .collect::<Vec<_>>()
-            })
-            .unwrap_or_default();
-        for pellet in dropped_pellets {
+        self.record(Input::AddSnakeAt(id, position));
+        self.insert_snake(id, position);
+    }
+
+    pub(crate) fn insert_snake(&mut self, id: Uuid, position: Coordinate) {
+        let mut snake = Snake::new(position, self.config.snake_speed, &mut self.rng);
+        snake.size = self.config.snake_size(snake.bodies.len());
+        snake.protection_time_left = self.config.spawn_protection_frames;
+        Self::join_team(&mut snake, self.team(&id));
+        self.detach_snake_density(&id);
+        if let Some(previous) = self.snakes.insert(id, snake) {
+            self.remove_snake_from_body_grid(&id, &previous);
+        }
+        self.insert_snake_into_body_grid(id);
+        self.attach_snake_density(&id);
+    }
+
+    pub fn accelerate(&mut self, id: &Uuid) {
+        self.record(Input::Accelerate(*id));
+        if let Some(snake) = self.snakes.get_mut(id) {
+            snake.accelerate(self.config.boost_frames);
+        }
+    }
+
+    pub fn remove_snake(&mut self, id: &Uuid) -> Option<DeathEvent> {
+        self.record(Input::RemoveSnake(*id));
+        let death = self.death_event(id, None, DeathCause::Disconnect);
+        self.drop_snake(id);
+        death
+    }
+
+    pub(crate) fn death_event(
+        &self,
+        id: &Uuid,
+        killer: Option<Uuid>,
+        cause: DeathCause,
+    ) -> Option<DeathEvent> {
+        let snake = self.snakes.get(id)?;
+        Some(DeathEvent {
+            id: *id,
+            score: snake.bodies.len(),
+            killer,
+            cause,
+            position: *snake.get_head(),
+        })
+    }
+
+    pub(crate) fn drop_snake(&mut self, id: &Uuid) {
+        //! Remove a snake and scatter half its mass along its body as pellets.
+        //! Huge snakes drop fewer, heavier pellets, and so does a snake dying
+        //! on a field close to `pellet_limit()`. Pellets weigh at most
+        //! `u8::MAX`, so the mass that the pellets still fitting below the
+        //! limit cannot carry is lost.
+
+        self.detach_snake_density(id);
+        self.detached_snakes.remove(id);
+        let Some(snake) = self.snakes.remove(id) else {
+            return;
+        };
+        self.remove_snake_from_body_grid(id, &snake);
+        let mut mass = snake.bodies.len() / 2;
+        let room = self
+            .config
+            .pellet_limit()
+            .saturating_sub(self.pellets.len());
+        let count = mass
+            .div_ceil(DEATH_DROP_VALUE)
+            .min(MAX_DEATH_DROP_COUNT)
+            .max(mass.div_ceil(u8::MAX as usize))
+            .min(room);
+        if count == 0 {
+            return;
+        }
+        // Place the pellets first, so that the mass of those that find no
+        // spot outside the obstacles is shared by the others.
+        let mut positions = Vec::with_capacity(count);
+        for index in 0..count {
+            let body = snake.bodies[index * snake.bodies.len() / count];
+            for _ in 0..DEATH_DROP_ATTEMPTS {
+                let dx = self.rng.random_range(-10.0..10.0);
+                let dy = self.rng.random_range(-10.0..10.0);
+                let position = Coordinate {
+                    x: body.x + dx,
+                    y: body.y + dy,
+                };
+                if !self.is_blocked(&position, 0.0) {
+                    positions.push(position);
+                    break;
+                }
+            }
+        }
+        if positions.is_empty() {
+            return;
+        }
+        let value = mass.div_ceil(positions.len()).min(u8::MAX as usize);
+        for position in positions {
+            let size = value.min(mass);
+            if size == 0 {
+                break;
+            }
+            mass -= size;
+            let mut pellet = Pellet::new_with_color_and_size(
+                position,
+                snake.color.clone(),
+                size as u8,
+                &mut self.rng,
+            );
+            pellet.decay_frame = self.config.decay_frame(self.frame_count);
+            let id = Self::random_id(&mut self.rng);
             Self::insert_pellet_into(
+                &self.config,
                 &mut self.pellets,
                 &mut self.pellet_grid,
-                Uuid::new_v4(),
+                &mut self.pellet_density,
+                id,
                 pellet,
                 self.frame_count,
             );
         }
-        self.snakes.remove(id);
     }
 
     fn fill_pellet(&mut self) {
-        while self.pellets.len() < MAX_PELLET_COUNT {
-            let new_pellet = Pellet::new(self.get_random_coordinate());
-            let id = Uuid::new_v4();
+        while self.pellets.len() < self.config.max_pellet_count {
+            // Try again next frame if the field is too crowded with obstacles
+            let Some(position) = self.free_pellet_coordinate() else {
+                break;
+            };
+            let new_pellet = self.random_pellet(position);
+            let id = Self::random_id(&mut self.rng);
             Self::insert_pellet_into(
+                &self.config,
                 &mut self.pellets,
                 &mut self.pellet_grid,
+                &mut self.pellet_density,
                 id,
                 new_pellet,
                 self.frame_count,
@@ -207,17 +411,23 @@ impl GameEngine {
     pub fn forward(&mut self) -> FrameEvents {
         //! Forward one frame of the game.
 
+        let field_size = self.config.field_size;
         let mut events = FrameEvents::default();
-        let mut touched_pellets: HashSet<Uuid> = HashSet::new();
+        let mut touched_pellets = EntitySet::default();
+        self.attach_snake_densities();
 
         // Update snakes
         for (snake_id, snake) in self.snakes.iter_mut() {
             let mut accelerate_factor = 1.;
             snake.turn_towards_target();
 
+            snake.protection_time_left = snake.protection_time_left.saturating_sub(1);
+            snake.power_ups.tick();
             if snake.acceleration_time_left > 0 {
                 snake.acceleration_time_left -= 1;
                 accelerate_factor = 2.;
+            } else if snake.power_ups.speed > 0 {
+                accelerate_factor = 2.;
             }
 
             let head = snake.get_head();
@@ -226,37 +436,58 @@ impl GameEngine {
                 y: head.y + snake.velocity.y * snake.speed * accelerate_factor,
             };
             let new_head = Coordinate {
-                x: new_head.x.rem_euclid(FIELD_SIZE),
-                y: new_head.y.rem_euclid(FIELD_SIZE),
+                x: new_head.x.rem_euclid(self.config.field_size),
+                y: new_head.y.rem_euclid(self.config.field_size),
             };
 
             if snake.acceleration_time_left > 0 && snake.frame_count_offset % 6 == 0 {
-                let id = Uuid::new_v4();
-                let pellet = Pellet::new_with_color_and_size(
-                    snake.bodies.pop_back().unwrap(),
-                    snake.color.clone(),
-                    3,
-                );
-                Self::insert_pellet_into(
-                    &mut self.pellets,
-                    &mut self.pellet_grid,
-                    id,
-                    pellet,
-                    self.frame_count,
-                );
+                let tail = snake.bodies.pop_back().unwrap();
+                self.snake_density.remove(&tail);
+                // Past the pellet limit the shed body point is simply lost
+                if self.pellets.len() < self.config.pellet_limit() {
+                    let id = Self::random_id(&mut self.rng);
+                    let mut pellet = Pellet::new_with_color_and_size(
+                        tail,
+                        snake.color.clone(),
+                        1,
+                        &mut self.rng,
+                    );
+                    pellet.decay_frame = self.config.decay_frame(self.frame_count);
+                    Self::insert_pellet_into(
+                        &self.config,
+                        &mut self.pellets,
+                        &mut self.pellet_grid,
+                        &mut self.pellet_density,
+                        id,
+                        pellet,
+                        self.frame_count,
+                    );
+                }
+            }
+            if let Some(tail) = snake.bodies.pop_back() {
+                self.snake_density.remove(&tail);
             }
-            snake.bodies.pop_back();
             snake.bodies.push_front(new_head);
+            self.snake_density.add(&new_head);
 
             let mut eaten_pellets: Vec<Uuid> = Vec::new();
 
-            let nearby_pellets = Self::nearby_pellet_ids(&self.pellet_grid, &new_head);
+            let attraction_radius = snake.attraction_radius();
+            let cells = (attraction_radius as f32 / self.config.pellet_cell_width()).ceil();
+            let nearby_pellets = Self::nearby_pellet_ids_with_radius(
+                &self.config,
+                &self.pellet_grid,
+                &new_head,
+                (cells as isize).max(1),
+            );
             for id in nearby_pellets {
                 let Some(pellet) = self.pellets.get_mut(&id) else {
                     continue;
                 };
                 // Draw pellets towards the snake
-                if pellet.position.distance2(&new_head) < ((snake.size * 2).pow(2) as f32) {
+                if pellet.position.distance2(&new_head, field_size)
+                    < (attraction_radius.pow(2) as f32)
+                {
                     let nx = pellet.position.x + (new_head.x - pellet.position.x) / 5.;
                     let ny = pellet.position.y + (new_head.y - pellet.position.y) / 5.;
                     pellet.position = Coordinate { x: nx, y: ny };
@@ -264,85 +495,56 @@ impl GameEngine {
                 }
 
                 // Eat pellets
-                if pellet.position.distance2(&new_head) < (snake.size.pow(2) as f32) {
-                    snake.bodies.push_back(snake.get_tail().to_owned());
+                if pellet.position.distance2(&new_head, field_size) < (snake.size.pow(2) as f32) {
+                    let tail = *snake.get_tail();
+                    for _ in 0..pellet.value() {
+                        snake.bodies.push_back(tail);
+                        self.snake_density.add(&tail);
+                    }
+                    snake
+                        .power_ups
+                        .collect(pellet.kind, self.config.power_up_frames);
                     eaten_pellets.push(id);
                 }
             }
 
             for id in eaten_pellets.iter() {
-                Self::remove_pellet_from(&mut self.pellets, &mut self.pellet_grid, id);
+                Self::remove_pellet_from(
+                    &self.config,
+                    &mut self.pellets,
+                    &mut self.pellet_grid,
+                    &mut self.pellet_density,
+                    id,
+                );
             }
             if !eaten_pellets.is_empty() {
                 events.pellets_eaten.insert(*snake_id, eaten_pellets.len());
             }
 
-            snake.size = (15 + snake.bodies.len() / 50).min(40);
+            snake.size = self.config.snake_size(snake.bodies.len());
         }
 
-        // Detect collision
-        let mut dead_snakes: HashSet<Uuid> = HashSet::new();
-
-        let snake_ids: Vec<Uuid> = self.snakes.keys().copied().collect();
-        for (index, id1) in snake_ids.iter().enumerate() {
-            for id2 in snake_ids.iter().skip(index + 1) {
-                let snake1 = self.snakes.get(id1).unwrap();
-                let snake2 = self.snakes.get(id2).unwrap();
-                let head1 = snake1.get_head();
-                let head2 = snake2.get_head();
-
-                // the head to head collision, rules:
-                // 1. the acceleration snake wins
-                // 2. the bigger snake wins
-                // 3. random
-                if head1.distance2(head2) <= ((snake1.size + snake2.size).pow(2) as f32) {
-                    if snake1.acceleration_time_left > 0 && snake2.acceleration_time_left > 0
-                        || snake1.acceleration_time_left == 0 && snake2.acceleration_time_left == 0
-                    {
-                        match snake1.size.cmp(&snake2.size) {
-                            Ordering::Greater => {
-                                dead_snakes.insert(*id2);
-                            }
-                            Ordering::Less => {
-                                dead_snakes.insert(*id1);
-                            }
-                            Ordering::Equal => {
-                                if rand::rng().random_range(0..10) < 5 {
-                                    dead_snakes.insert(*id1);
-                                } else {
-                                    dead_snakes.insert(*id2);
-                                }
-                            }
-                        }
-                    } else if snake2.acceleration_time_left > 0 {
-                        dead_snakes.insert(*id1);
-                    } else {
-                        dead_snakes.insert(*id2);
-                    }
-                    continue;
-                }
+        // Shrink snakes outside the zone and kill those that hit an obstacle
+        let starved = self.shrink_snakes_outside_zone();
+        events.deaths.extend(starved);
+        let crashed = self.crash_into_obstacles();
+        events.deaths.extend(crashed);
 
-                if snake2.bodies.iter().any(|body| {
-                    head1.distance2(body) <= ((snake1.size + snake2.size).pow(2) as f32)
-                }) {
-                    dead_snakes.insert(*id1);
-                }
-                if snake1.bodies.iter().any(|body| {
-                    head2.distance2(body) <= ((snake1.size + snake2.size).pow(2) as f32)
-                }) {
-                    dead_snakes.insert(*id2);
-                }
-            }
-        }
+        // Detect collision
+        self.rebuild_body_grid();
+        let dead_snakes = self.detect_collisions();
 
-        for id in dead_snakes.iter() {
-            if let Some(score) = self.score(id) {
-                events.deaths.push(DeathEvent { id: *id, score });
+        for (id, killer, cause) in dead_snakes {
+            if let Some(death) = self.death_event(&id, Some(killer), cause) {
+                events.deaths.push(death);
             }
-            self.remove_snake(id)
+            self.drop_snake(&id)
         }
+        events.winner = self.match_winner(&events);
 
-        // Refill pellets
+        // Let dropped pellets decay, hold feasts and refill pellets
+        self.decay_pellets();
+        events.feast = self.hold_feast();
         self.fill_pellet();
 
         // Update time to live
@@ -353,8 +555,9 @@ impl GameEngine {
         let mut moved_pellets = Vec::with_capacity(touched_pellets.len());
         for id in touched_pellets {
             if let Some(pellet) = self.pellets.get_mut(&id) {
-                let previous_cell = Self::pellet_cell(&pellet.center);
-                let next_cell = Self::pellet_cell(&pellet.position);
+                let previous_cell = Self::grid_cell(&self.config, &pellet.center);
+                let next_cell = Self::grid_cell(&self.config, &pellet.position);
+                self.pellet_density.shift(&pellet.center, &pellet.position);
                 pellet.center = pellet.position;
                 if previous_cell != next_cell {
                     moved_pellets.push((id, previous_cell, next_cell));
@@ -362,9 +565,9 @@ impl GameEngine {
             }
         }
         for (id, (previous_x, previous_y), (next_x, next_y)) in moved_pellets {
-            self.pellet_grid[Self::pellet_cell_index(previous_x, previous_y)]
+            self.pellet_grid[Self::grid_cell_index(&self.config, previous_x, previous_y)]
                 .retain(|candidate| candidate != &id);
-            self.pellet_grid[Self::pellet_cell_index(next_x, next_y)].push(id);
+            self.pellet_grid[Self::grid_cell_index(&self.config, next_x, next_y)].push(id);
         }
         for (_, snake) in self.snakes.iter_mut() {
             snake.frame_count_offset += 1;
@@ -374,6 +577,7 @@ impl GameEngine {
     }
 
     pub fn change_velocity(&mut self, id: &Uuid, velocity: Coordinate) {
+        self.record(Input::ChangeVelocity(*id, velocity));
         if !velocity.x.is_finite() || !velocity.y.is_finite() {
             return;
         }
@@ -390,112 +594,164 @@ impl GameEngine {
         }
     }
 
-    pub fn map(&self, cx: f32, cy: f32) -> Map {
-        let cell_size = FIELD_SIZE / MAP_SIZE as f32;
-
-        // TODO: `arr` is the same for all users on every frame. Consider caching the value.
-        let mut arr = vec![vec![0; MAP_SIZE]; MAP_SIZE];
-        for (_, snake) in self.snakes.iter() {
-            for body in snake.bodies.iter() {
-                let x = (body.x / cell_size).floor() as usize;
-                let y = (body.y / cell_size).floor() as usize;
-                arr[x.clamp(0, MAP_SIZE - 1)][y.clamp(0, MAP_SIZE - 1)] += 1;
-            }
-        }
-        for pellet in self.pellets.values() {
-            let x = (pellet.position.x / cell_size).floor() as usize;
-            let y = (pellet.position.y / cell_size).floor() as usize;
-            arr[x.clamp(0, MAP_SIZE - 1)][y.clamp(0, MAP_SIZE - 1)] += 1;
-        }
-
-        Map {
-            map: arr,
-            self_coordinate: Self::map_coordinate(cx, cy),
+    pub(crate) fn set_target_velocity(&mut self, id: &Uuid, velocity: Coordinate) {
+        self.record(Input::TargetVelocity(*id, velocity));
+        if let Some(snake) = self.snakes.get_mut(id) {
+            snake.target_velocity = velocity;
         }
     }
 
-    pub fn map_coordinate(cx: f32, cy: f32) -> (usize, usize) {
-        let cell_size = FIELD_SIZE / MAP_SIZE as f32;
-        let x = (cx.rem_euclid(FIELD_SIZE) / cell_size).floor() as usize;
-        let y = (cy.rem_euclid(FIELD_SIZE) / cell_size).floor() as usize;
+    pub fn view(
+        &self,
+        id: &Uuid,
+        cx: f32,
+        cy: f32,
+        width: f32,
+        height: f32,
+        handles: &mut EntityHandles,
+    ) -> View {
+        //! Get the view of the game.
+        //! The view is centered at (cx, cy) with width and height. Entities
+        //! carry handles from `handles`, which belongs to the viewer.
 
-        (x.min(MAP_SIZE - 1), y.min(MAP_SIZE - 1))
+        self.view_state(id, cx, cy, width, height, handles)
+            .to_view()
     }
 
-    pub fn view(&self, id: &Uuid, cx: f32, cy: f32, width: f32, height: f32) -> View {
-        //! Get the view of the game.
-        //! The view is centered at (cx, cy) with width and height.
-
-        let mut snakes: Vec<Snake> = Vec::new();
-        let mut pellets: Vec<Pellet> = Vec::new();
+    pub fn view_state(
+        &self,
+        id: &Uuid,
+        cx: f32,
+        cy: f32,
+        width: f32,
+        height: f32,
+        handles: &mut EntityHandles,
+    ) -> ViewState {
+        //! Get what `view` shows, in world coordinates, for encoding as deltas
+        //! with a `ViewEncoder`. Snake bodies are cut down to the range
+        //! between their first and last point whose circle reaches into the
+        //! view.
+
+        let field_size = self.config.field_size;
+        let mut snakes: Vec<ViewSnake> = Vec::new();
+        let mut pellets: Vec<ViewPellet> = Vec::new();
+        let mut seen: Vec<Uuid> = Vec::new();
 
         let x0 = cx - width / 2.0;
         let y0 = cy - height / 2.0;
 
         // 1. Get snakes in the rectangle
-        for (_, snake) in self.snakes.iter() {
-            let snake = snake.clone();
-            let mut bodies: VecDeque<Coordinate> = VecDeque::new();
-            for body in snake.bodies.iter() {
-                if body.is_in_rectangle(x0, y0, width, height) {
-                    bodies.push_back(Coordinate {
-                        x: (body.x - x0).rem_euclid(FIELD_SIZE),
-                        y: (body.y - y0).rem_euclid(FIELD_SIZE),
-                    });
-                }
-            }
-            let is_visible_head = snake.bodies[0].is_in_rectangle(x0, y0, width, height);
-            if !bodies.is_empty() {
-                snakes.push(Snake {
-                    bodies,
-                    is_visible_head,
-                    ..snake
-                });
+        let margin = self.max_snake_size() as f32;
+        let nearby = self.body_entries_in_rectangle(
+            x0 - margin,
+            y0 - margin,
+            width + 2.0 * margin,
+            height + 2.0 * margin,
+        );
+        for (snake_id, entries) in nearby.iter() {
+            let Some(snake) = self.snakes.get(snake_id) else {
+                continue;
+            };
+            if !entries.iter().any(|entry| {
+                entry
+                    .position
+                    .is_in_rectangle(x0, y0, width, height, field_size)
+            }) {
+                continue;
             }
+            let size = snake.size as f32;
+            let mut reaching = entries.iter().filter(|entry| {
+                entry.position.is_in_rectangle(
+                    x0 - size,
+                    y0 - size,
+                    width + 2.0 * size,
+                    height + 2.0 * size,
+                    field_size,
+                )
+            });
+            let first = reaching.next().map_or(0, |entry| entry.index);
+            let last = reaching.next_back().map_or(first, |entry| entry.index);
+            seen.push(*snake_id);
+            snakes.push(ViewSnake {
+                handle: handles.handle(*snake_id),
+                bodies: snake.bodies.range(first..=last).copied().collect(),
+                with_head: first == 0,
+                acceleration_time_left: snake.acceleration_time_left,
+                protection_time_left: snake.protection_time_left,
+                power_ups: snake.power_ups,
+                color: snake.color.clone(),
+                velocity: snake.velocity,
+                size: snake.size,
+                is_self: snake_id == id,
+            });
         }
 
         // 2. Get pellets in the rectangle
-        for id in self.pellet_ids_in_rectangle(x0, y0, width, height) {
-            let Some(pellet) = self.pellets.get(&id) else {
+        for pellet_id in self.pellet_ids_in_rectangle(x0, y0, width, height) {
+            let Some(pellet) = self.pellets.get(&pellet_id) else {
                 continue;
             };
-            if pellet.position.is_in_rectangle(x0, y0, width, height) {
-                let mut pellet = pellet.clone();
-                pellet.frame_count_offset =
-                    self.frame_count.wrapping_sub(pellet.frame_count_offset);
-                pellet.update();
-                pellets.push(Pellet {
-                    position: Coordinate {
-                        x: (pellet.position.x - x0).rem_euclid(FIELD_SIZE),
-                        y: (pellet.position.y - y0).rem_euclid(FIELD_SIZE),
-                    },
-                    ..pellet
+            if pellet
+                .position
+                .is_in_rectangle(x0, y0, width, height, field_size)
+            {
+                seen.push(pellet_id);
+                pellets.push(ViewPellet {
+                    handle: handles.handle(pellet_id),
+                    center: pellet.center,
+                    radius: pellet.radius,
+                    size: pellet.size,
+                    color: pellet.color.clone(),
+                    kind: pellet.kind,
+                    born: pellet.frame_count_offset,
+                    decay: pellet.decay_frame,
                 });
             }
         }
+        pellets.sort_by_key(|pellet| pellet.handle);
+
+        handles.release_unseen(&seen);
 
-        View {
+        ViewState {
+            frame: self.frame_count,
+            field_size,
             is_alive: self.snakes.contains_key(id),
+            origin: Coordinate { x: x0, y: y0 },
+            width,
+            height,
             snakes,
             pellets,
-            background_offset: Coordinate {
-                x: (-x0).rem_euclid(100.0),
-                y: (-y0).rem_euclid(100.0),
-            },
+            zone: self.zone(),
+            obstacles: self.obstacles_in_rectangle(x0, y0, width, height),
         }
     }
 }
 
-fn axis_cells(start: f32, length: f32) -> Vec<usize> {
-    if length >= FIELD_SIZE {
-        return (0..PELLET_GRID_SIZE).collect();
+pub(crate) fn axis_cells(config: &GameConfig, start: f32, length: f32) -> Vec<usize> {
+    let grid_size = config.pellet_grid_size();
+    let cell_width = config.pellet_cell_width();
+    if length >= config.field_size {
+        return (0..grid_size).collect();
     }
 
-    let first_visible_cell = (start.rem_euclid(FIELD_SIZE) / PELLET_CELL_SIZE).floor() as usize;
-    let start_cell = (first_visible_cell + PELLET_GRID_SIZE - 1) % PELLET_GRID_SIZE;
-    let cell_count = (length.max(0.0) / PELLET_CELL_SIZE).ceil() as usize + 3;
-    (0..cell_count.min(PELLET_GRID_SIZE))
-        .map(|offset| (start_cell + offset) % PELLET_GRID_SIZE)
+    let first_visible_cell =
+        ((start.rem_euclid(config.field_size) / cell_width).floor() as usize).min(grid_size - 1);
+    let start_cell = (first_visible_cell + grid_size - 1) % grid_size;
+    let cell_count = (length.max(0.0) / cell_width).ceil() as usize + 3;
+    (0..cell_count.min(grid_size))
+        .map(|offset| (start_cell + offset) % grid_size)
+        .collect()
+}
+
+pub(crate) fn neighbour_cells(center: usize, radius: isize, grid_size: usize) -> Vec<usize> {
+    // On a small torus the neighbourhood wraps onto itself; visiting a cell
+    // twice would let a snake eat the same pellet twice.
+    if radius.unsigned_abs() * 2 + 1 >= grid_size {
+        return (0..grid_size).collect();
+    }
+
+    (-radius..=radius)
+        .map(|offset| (center as isize + offset).rem_euclid(grid_size as isize) as usize)
         .collect()
 }
 
@@ -506,7 +762,7 @@ mod tests {
     #[test]
     fn ignores_invalid_velocity() {
         let id = Uuid::new_v4();
-        let mut engine = GameEngine::new();
+        let mut engine = GameEngine::default();
         engine.add_snake(id);
 
         engine.change_velocity(&id, Coordinate { x: 0.0, y: 0.0 });
@@ -526,12 +782,15 @@ mod tests {
 
     #[test]
     fn equal_head_collision_removes_exactly_one_snake() {
-        let mut engine = GameEngine::new();
+        let mut engine = GameEngine::default();
         let first = Uuid::new_v4();
         let second = Uuid::new_v4();
         let position = Coordinate { x: 100.0, y: 100.0 };
-        engine.snakes.insert(first, Snake::new(position, 0.0));
-        engine.snakes.insert(second, Snake::new(position, 0.0));
+        let first_snake = Snake::new(position, 0.0, &mut engine.rng);
+        let second_snake = Snake::new(position, 0.0, &mut engine.rng);
+        engine.snakes.insert(first, first_snake);
+        engine.snakes.insert(second, second_snake);
+        engine.rebuild_density();
 
         engine.forward();
 
@@ -539,33 +798,295 @@ mod tests {
     }
 
     #[test]
-    fn default_engine_initializes_the_pellet_grid() {
-        let engine = GameEngine::default();
+    fn head_to_head_deaths_name_the_winner_and_the_cause() {
+        let small = Uuid::from_u128(1);
+        let big = Uuid::from_u128(2);
+        let config = GameConfig {
+            spawn_protection_frames: 0,
+            ..GameConfig::default()
+        };
+        let mut engine = GameEngine::with_seed(config, 1);
+        engine.add_snake_at(small, Coordinate { x: 100.0, y: 100.0 });
+        engine.add_snake_at(big, Coordinate { x: 120.0, y: 100.0 });
+        let snake = engine.get_snake_mut(&big).unwrap();
+        while snake.bodies.len() < 60 {
+            snake.bodies.push_back(Coordinate { x: 120.0, y: 100.0 });
+        }
+
+        let deaths = engine.forward().deaths;
 
         assert_eq!(
-            engine.pellet_grid.len(),
-            PELLET_GRID_SIZE * PELLET_GRID_SIZE
+            deaths,
+            vec![DeathEvent {
+                id: small,
+                score: 10,
+                killer: Some(big),
+                cause: DeathCause::HeadToHeadSize,
+                position: Coordinate { x: 100.0, y: 100.0 },
+            }]
+        );
+    }
+
+    #[test]
+    fn removing_a_snake_reports_a_disconnect() {
+        let id = Uuid::from_u128(1);
+        let mut engine = GameEngine::default();
+        engine.add_snake(id);
+
+        let death = engine.remove_snake(&id).unwrap();
+
+        assert_eq!(death.cause, DeathCause::Disconnect);
+        assert_eq!(death.killer, None);
+        assert!(engine.remove_snake(&id).is_none());
+    }
+
+    #[test]
+    fn engines_with_the_same_seed_produce_identical_frames() {
+        let first = Uuid::from_u128(1);
+        let second = Uuid::from_u128(2);
+        let run = || {
+            let mut engine = GameEngine::with_seed(GameConfig::default(), 42);
+            engine.add_snake(first);
+            engine.add_snake(second);
+            let mut frames = Vec::new();
+            let mut handles = EntityHandles::default();
+            for frame in 0..120 {
+                engine.change_velocity(&first, Coordinate { x: 1.0, y: 0.3 });
+                if frame == 30 {
+                    engine.remove_snake(&second);
+                }
+                engine.forward();
+                let head = *engine.get_snake(&first).unwrap().get_head();
+                frames.push(
+                    engine
+                        .view(&first, head.x, head.y, 800.0, 600.0, &mut handles)
+                        .to_bytes(),
+                );
+            }
+            frames
+        };
+
+        assert_eq!(run(), run());
+    }
+
+    #[test]
+    fn view_marks_the_viewer_and_keeps_entity_handles_across_frames() {
+        let viewer = Uuid::from_u128(1);
+        let other = Uuid::from_u128(2);
+        let mut engine = GameEngine::with_seed(GameConfig::default(), 5);
+        engine.add_snake_at(viewer, Coordinate { x: 500.0, y: 500.0 });
+        engine.add_snake_at(other, Coordinate { x: 700.0, y: 500.0 });
+        let mut handles = EntityHandles::default();
+        let handles_of = |view: &View| {
+            view.snakes
+                .iter()
+                .map(|snake| (snake.handle, snake.is_self))
+                .collect::<Vec<_>>()
+        };
+
+        let first = engine.view(&viewer, 500.0, 500.0, 800.0, 600.0, &mut handles);
+        engine.forward();
+        let second = engine.view(&viewer, 500.0, 500.0, 800.0, 600.0, &mut handles);
+
+        assert_eq!(handles_of(&first), vec![(1, true), (2, false)]);
+        assert_eq!(handles_of(&second), handles_of(&first));
+    }
+
+    #[test]
+    fn different_seeds_spawn_different_worlds() {
+        let id = Uuid::from_u128(1);
+        let mut first = GameEngine::with_seed(GameConfig::default(), 1);
+        let mut second = GameEngine::with_seed(GameConfig::default(), 2);
+        first.add_snake(id);
+        second.add_snake(id);
+
+        assert_ne!(
+            first.get_snake(&id).unwrap().get_head(),
+            second.get_snake(&id).unwrap().get_head()
         );
     }
 
+    #[test]
+    fn small_arena_wraps_heads_and_caps_pellets_at_the_configured_values() {
+        let id = Uuid::new_v4();
+        let config = GameConfig {
+            field_size: 1000.0,
+            max_pellet_count: 50,
+            ..GameConfig::default()
+        };
+        let mut engine = GameEngine::new(config);
+        engine.add_snake_at(id, Coordinate { x: 998.0, y: 500.0 });
+        engine.get_snake_mut(&id).unwrap().velocity = Coordinate { x: 1.0, y: 0.0 };
+        engine.change_velocity(&id, Coordinate { x: 1.0, y: 0.0 });
+
+        engine.forward();
+
+        let head = engine.get_snake(&id).unwrap().get_head();
+        assert!((head.x - 3.0).abs() < 1e-3);
+        assert_eq!(engine.pellets.len(), 50);
+    }
+
+    fn long_snake(engine: &mut GameEngine, id: Uuid, length: usize) {
+        engine.add_snake_at(id, Coordinate { x: 500.0, y: 500.0 });
+        engine.get_snake_mut(&id).unwrap().bodies = (0..length)
+            .map(|index| Coordinate {
+                x: (500.0 - index as f32).rem_euclid(1000.0),
+                y: 500.0,
+            })
+            .collect();
+    }
+
+    #[test]
+    fn bigger_pellets_are_worth_more_body_points() {
+        let config = GameConfig {
+            field_size: 1000.0,
+            max_pellet_count: 0,
+            ..GameConfig::default()
+        };
+        let mut engine = GameEngine::with_seed(config, 1);
+        let id = Uuid::from_u128(1);
+        engine.add_snake_at(id, Coordinate { x: 100.0, y: 100.0 });
+        let length = engine.get_snake(&id).unwrap().bodies.len();
+        let pellet = Pellet::new_with_color_and_size(
+            Coordinate { x: 100.0, y: 100.0 },
+            "0".to_owned(),
+            7,
+            &mut engine.rng,
+        );
+        GameEngine::insert_pellet_into(
+            &engine.config,
+            &mut engine.pellets,
+            &mut engine.pellet_grid,
+            &mut engine.pellet_density,
+            Uuid::from_u128(2),
+            pellet,
+            engine.frame_count,
+        );
+
+        engine.forward();
+
+        assert_eq!(engine.get_snake(&id).unwrap().bodies.len(), length + 7);
+    }
+
+    #[test]
+    fn death_drops_carry_half_the_mass_in_fewer_heavier_pellets_for_huge_snakes() {
+        let config = GameConfig {
+            field_size: 1000.0,
+            max_pellet_count: 0,
+            ..GameConfig::default()
+        };
+        let mut engine = GameEngine::with_seed(config, 2);
+        let (small, huge) = (Uuid::from_u128(1), Uuid::from_u128(2));
+        long_snake(&mut engine, small, 30);
+        long_snake(&mut engine, huge, 3000);
+
+        engine.remove_snake(&small);
+        let mass: usize = engine.pellets.values().map(Pellet::value).sum();
+        assert_eq!((engine.pellets.len(), mass), (5, 15));
+
+        engine.pellets.clear();
+        engine.remove_snake(&huge);
+        let mass: usize = engine.pellets.values().map(Pellet::value).sum();
+        assert_eq!((engine.pellets.len(), mass), (MAX_DEATH_DROP_COUNT, 1500));
+    }
+
+    #[test]
+    fn giant_snakes_drop_their_whole_mass_in_pellets_of_the_largest_size() {
+        let config = GameConfig {
+            field_size: 1000.0,
+            max_pellet_count: 0,
+            ..GameConfig::default()
+        };
+        let mut engine = GameEngine::with_seed(config, 4);
+        let id = Uuid::from_u128(1);
+        long_snake(&mut engine, id, 30_000);
+
+        engine.remove_snake(&id);
+
+        let mass: usize = engine.pellets.values().map(Pellet::value).sum();
+        assert_eq!((engine.pellets.len(), mass), (59, 15_000));
+    }
+
+    #[test]
+    fn mass_that_does_not_fit_below_the_pellet_limit_is_lost() {
+        let config = GameConfig {
+            field_size: 1000.0,
+            max_pellet_count: 0,
+            max_dropped_pellet_count: 2,
+            ..GameConfig::default()
+        };
+        let mut engine = GameEngine::with_seed(config, 5);
+        let (first, second) = (Uuid::from_u128(1), Uuid::from_u128(2));
+        long_snake(&mut engine, first, 2);
+        long_snake(&mut engine, second, 1000);
+        engine.remove_snake(&first);
+        assert_eq!(engine.pellets.len(), engine.config.pellet_limit() - 1);
+
+        engine.remove_snake(&second);
+
+        // Half of the 1000 points would be 500, but the one free place takes
+        // a single pellet of the largest size.
+        let mass: usize = engine.pellets.values().map(Pellet::value).sum();
+        assert_eq!((engine.pellets.len(), mass), (2, 1 + u8::MAX as usize));
+    }
+
+    #[test]
+    fn dropped_pellets_stop_at_the_pellet_limit() {
+        let config = GameConfig {
+            field_size: 1000.0,
+            max_pellet_count: 0,
+            max_dropped_pellet_count: 4,
+            ..GameConfig::default()
+        };
+        let mut engine = GameEngine::with_seed(config, 3);
+        let (first, second) = (Uuid::from_u128(1), Uuid::from_u128(2));
+        long_snake(&mut engine, first, 300);
+        long_snake(&mut engine, second, 300);
+
+        engine.remove_snake(&first);
+        assert_eq!(engine.pellets.len(), 4);
+        let mass: usize = engine.pellets.values().map(Pellet::value).sum();
+        assert_eq!(mass, 150);
+
+        engine.remove_snake(&second);
+        assert_eq!(engine.pellets.len(), 4);
+    }
+
+    #[test]
+    fn default_engine_initializes_the_pellet_grid() {
+        let engine = GameEngine::default();
+
+        let grid_size = engine.config.pellet_grid_size();
+        assert_eq!(engine.pellet_grid.len(), grid_size * grid_size);
+    }
+
     #[test]
     fn spatial_grid_finds_pellets_across_the_field_boundary() {
-        let mut engine = GameEngine::new();
+        let mut engine = GameEngine::default();
         let pellet_id = Uuid::new_v4();
-        let pellet = Pellet::new(Coordinate {
-            x: FIELD_SIZE - 10.0,
-            y: 50.0,
-        });
+        let pellet = Pellet::new(
+            Coordinate {
+                x: engine.config.field_size - 10.0,
+                y: 50.0,
+            },
+            &mut engine.rng,
+        );
         GameEngine::insert_pellet_into(
+            &engine.config,
             &mut engine.pellets,
             &mut engine.pellet_grid,
+            &mut engine.pellet_density,
             pellet_id,
             pellet,
             engine.frame_count,
         );
 
-        let nearby =
-            GameEngine::nearby_pellet_ids(&engine.pellet_grid, &Coordinate { x: 5.0, y: 50.0 });
+        let nearby = GameEngine::nearby_pellet_ids_with_radius(
+            &engine.config,
+            &engine.pellet_grid,
+            &Coordinate { x: 5.0, y: 50.0 },
+            1,
+        );
 
         assert!(nearby.contains(&pellet_id));
     }
@@ -0,0 +1,228 @@
+use rand::{RngExt, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::f32::consts::TAU;
+
+use super::config::GameConfig;
+use super::coordinate::Coordinate;
+use super::engine::{GameEngine, GameRng};
+use super::pellet::Pellet;
+
+/// Number of food patches on the field when pellets spawn in patches.
+const FOOD_PATCH_COUNT: usize = 12;
+/// Radius of a food patch, as a fraction of the field size.
+const FOOD_PATCH_RADIUS: f32 = 0.04;
+/// Random positions compared to find a sparse one.
+const SPARSE_CANDIDATES: usize = 4;
+/// Pellets spawned by a feast, and the radius they are scattered over.
+const FEAST_PELLET_COUNT: usize = 150;
+const FEAST_RADIUS: f32 = 250.0;
+
+/// Where fresh pellets spawn.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PelletDistribution {
+    /// Anywhere on the field with equal odds.
+    #[default]
+    Uniform,
+    /// In a few fixed patches, leaving the rest of the field bare.
+    Patches,
+    /// Preferably where there are few pellets already.
+    Sparse,
+}
+
+impl GameEngine {
+    pub(crate) fn pellet_spawn_coordinate(&mut self) -> Coordinate {
+        match self.config.pellet_distribution {
+            PelletDistribution::Uniform => self.get_random_coordinate(),
+            PelletDistribution::Patches => {
+                let index = self.rng.random_range(0..self.food_patches.len());
+                let patch = self.food_patches[index];
+                let radius = self.config.field_size * FOOD_PATCH_RADIUS;
+                self.random_coordinate_around(&patch, radius)
+            }
+            PelletDistribution::Sparse => {
+                let mut sparsest = self.get_random_coordinate();
+                let mut crowding = self.pellet_density.crowding(&sparsest, 0);
+                for _ in 1..SPARSE_CANDIDATES {
+                    let candidate = self.get_random_coordinate();
+                    let candidate_crowding = self.pellet_density.crowding(&candidate, 0);
+                    if candidate_crowding < crowding {
+                        (sparsest, crowding) = (candidate, candidate_crowding);
+                    }
+                }
+                sparsest
+            }
+        }
+    }
+
+    pub fn food_patches(&self) -> &[Coordinate] {
+        &self.food_patches
+    }
+
+    fn random_coordinate_around(&mut self, center: &Coordinate, radius: f32) -> Coordinate {
+        let angle = self.rng.random_range(0.0..TAU);
+        let distance = radius * self.rng.random::<f32>().sqrt();
+        let field_size = self.config.field_size;
+        Coordinate {
+            x: (center.x + distance * angle.cos()).rem_euclid(field_size),
+            y: (center.y + distance * angle.sin()).rem_euclid(field_size),
+        }
+    }
+
+    pub(crate) fn decay_pellets(&mut self) {
+        //! Remove the dropped pellets whose lifetime is over.
+
+        let frame_count = self.frame_count;
+        let decayed: Vec<_> = self
+            .pellets
+            .iter()
+            .filter(|(_, pellet)| pellet.decay_frame.is_some_and(|frame| frame <= frame_count))
+            .map(|(id, _)| *id)
+            .collect();
+        for id in decayed.iter() {
+            Self::remove_pellet_from(
+                &self.config,
+                &mut self.pellets,
+                &mut self.pellet_grid,
+                &mut self.pellet_density,
+                id,
+            );
+        }
+    }
+
+    pub(crate) fn hold_feast(&mut self) -> Option<Coordinate> {
+        //! Every `feast_interval_frames`, scatter a dense cluster of pellets
+        //! somewhere inside the zone. They decay like dropped pellets.
+
+        let interval = self.config.feast_interval_frames;
+        if interval == 0 || self.frame_count == 0 || !self.frame_count.is_multiple_of(interval) {
+            return None;
+        }
+        let center = self.random_spawn_coordinate();
+        let room = self
+            .config
+            .pellet_limit()
+            .saturating_sub(self.pellets.len());
+        for _ in 0..FEAST_PELLET_COUNT.min(room) {
+            let position = self.random_coordinate_around(&center, FEAST_RADIUS);
+            if self.is_blocked(&position, 0.0) {
+                continue;
+            }
+            let mut pellet = Pellet::new(position, &mut self.rng);
+            pellet.decay_frame = self.config.decay_frame(self.frame_count);
+            let id = Self::random_id(&mut self.rng);
+            Self::insert_pellet_into(
+                &self.config,
+                &mut self.pellets,
+                &mut self.pellet_grid,
+                &mut self.pellet_density,
+                id,
+                pellet,
+                self.frame_count,
+            );
+        }
+        Some(center)
+    }
+}
+
+pub(crate) fn food_patches(config: &GameConfig, seed: u64) -> Vec<Coordinate> {
+    //! The centers of the food patches. They are derived from the seed alone,
+    //! so they stay put for the lifetime of the world.
+
+    let mut rng = GameRng::seed_from_u64(seed);
+    (0..FOOD_PATCH_COUNT)
+        .map(|_| Coordinate {
+            x: rng.random_range(0.0..config.field_size),
+            y: rng.random_range(0.0..config.field_size),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn config(pellet_distribution: PelletDistribution) -> GameConfig {
+        GameConfig {
+            max_pellet_count: 500,
+            pellet_distribution,
+            ..GameConfig::small_test_field()
+        }
+    }
+
+    #[test]
+    fn patches_keep_pellets_near_the_patch_centers() {
+        let mut engine = GameEngine::with_seed(config(PelletDistribution::Patches), 1);
+        engine.forward();
+        let patches = engine.food_patches();
+        let radius = 1000.0 * FOOD_PATCH_RADIUS;
+
+        assert_eq!(engine.pellets.len(), 500);
+        assert!(engine.pellets.values().all(|pellet| {
+            patches
+                .iter()
+                .any(|patch| pellet.center.distance2(patch, 1000.0) <= radius * radius + 1.0)
+        }));
+    }
+
+    #[test]
+    fn sparse_spawns_even_out_the_field() {
+        let spread = |distribution| {
+            let mut engine = GameEngine::with_seed(config(distribution), 2);
+            engine.forward();
+            let counts: Vec<_> = (0..10)
+                .flat_map(|x| (0..10).map(move |y| (x, y)))
+                .map(|(x, y)| {
+                    let position = Coordinate {
+                        x: x as f32 * 100.0 + 50.0,
+                        y: y as f32 * 100.0 + 50.0,
+                    };
+                    engine.pellet_density.crowding(&position, 0)
+                })
+                .collect();
+            counts.iter().max().unwrap() - counts.iter().min().unwrap()
+        };
+
+        assert!(spread(PelletDistribution::Sparse) < spread(PelletDistribution::Uniform));
+    }
+
+    #[test]
+    fn dropped_pellets_decay_after_their_lifetime() {
+        let config = GameConfig {
+            dropped_pellet_frames: 10,
+            ..GameConfig::small_test_field()
+        };
+        let mut engine = GameEngine::with_seed(config, 3);
+        let id = Uuid::from_u128(1);
+        engine.add_snake_at(id, Coordinate { x: 500.0, y: 500.0 });
+        engine.remove_snake(&id);
+        assert!(!engine.pellets.is_empty());
+
+        for _ in 0..10 {
+            engine.forward();
+        }
+        assert!(!engine.pellets.is_empty());
+        engine.forward();
+        assert!(engine.pellets.is_empty());
+    }
+
+    #[test]
+    fn feasts_scatter_a_cluster_now_and_then() {
+        let config = GameConfig {
+            feast_interval_frames: 5,
+            ..GameConfig::small_test_field()
+        };
+        let mut engine = GameEngine::with_seed(config, 4);
+
+        let feasts: Vec<_> = (0..10).map(|_| engine.forward().feast).collect();
+
+        assert!(feasts[..5].iter().all(Option::is_none));
+        let center = feasts[5].unwrap();
+        assert_eq!(feasts.iter().flatten().count(), 1);
+        assert_eq!(engine.pellets.len(), FEAST_PELLET_COUNT);
+        assert!(engine.pellets.values().all(|pellet| {
+            pellet.center.distance2(&center, 1000.0) <= FEAST_RADIUS * FEAST_RADIUS + 1.0
+        }));
+    }
+}
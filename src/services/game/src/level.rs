@@ -0,0 +1,463 @@
+use serde::{Deserialize, Serialize};
+
+use super::config::GameConfig;
+use super::coordinate::{torus_delta, Coordinate};
+use super::engine::{DeathCause, DeathEvent, GameEngine};
+
+/// Random positions tried for a fresh pellet before the engine gives up
+/// filling the field this frame.
+const PELLET_SPAWN_ATTEMPTS: usize = 8;
+
+/// A static obstacle. Snakes whose heads touch one die, and no pellet spawns
+/// inside one.
+///
+/// Level files may spell the shapes and their fields out (`circle`, `center`,
+/// ...); views use the short names.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum Obstacle {
+    #[serde(rename = "c", alias = "circle")]
+    Circle {
+        #[serde(rename = "c", alias = "center")]
+        center: Coordinate,
+        #[serde(rename = "r", alias = "radius")]
+        radius: f32,
+    },
+    /// An axis-aligned rectangle.
+    #[serde(rename = "r", alias = "rectangle")]
+    Rectangle {
+        #[serde(rename = "c", alias = "center")]
+        center: Coordinate,
+        /// Width and height.
+        #[serde(rename = "s", alias = "size")]
+        size: Coordinate,
+    },
+    /// A straight wall between two points, with round ends. It runs the
+    /// short way around the torus, so it must be shorter than half the field.
+    #[serde(rename = "w", alias = "wall")]
+    Wall {
+        #[serde(rename = "a", alias = "from")]
+        from: Coordinate,
+        #[serde(rename = "b", alias = "to")]
+        to: Coordinate,
+        #[serde(rename = "w", alias = "width")]
+        width: f32,
+    },
+}
+
+/// The obstacles of an arena, as stored in a level file.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Level {
+    pub obstacles: Vec<Obstacle>,
+}
+
+impl Level {
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        let level: Self = serde_json::from_str(json)?;
+        if !level.obstacles.iter().all(Obstacle::is_valid) {
+            return Err(serde_json::Error::io(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "obstacles must have finite positions and positive sizes",
+            )));
+        }
+        Ok(level)
+    }
+}
+
+impl Obstacle {
+    pub fn is_valid(&self) -> bool {
+        let is_finite =
+            |coordinate: &Coordinate| coordinate.x.is_finite() && coordinate.y.is_finite();
+        let is_positive = |value: f32| value.is_finite() && value > 0.0;
+        match self {
+            Obstacle::Circle { center, radius } => is_finite(center) && is_positive(*radius),
+            Obstacle::Rectangle { center, size } => {
+                is_finite(center) && is_positive(size.x) && is_positive(size.y)
+            }
+            Obstacle::Wall { from, to, width } => {
+                is_finite(from) && is_finite(to) && is_positive(*width)
+            }
+        }
+    }
+
+    pub fn fits_in(&self, field_size: f32) -> bool {
+        //! Whether the obstacle keeps its shape on a field of `field_size`.
+
+        match self {
+            Obstacle::Wall { from, to, .. } => {
+                let (dx, dy) = (to.x - from.x, to.y - from.y);
+                (dx * dx + dy * dy).sqrt() < field_size / 2.0
+            }
+            Obstacle::Circle { .. } | Obstacle::Rectangle { .. } => true,
+        }
+    }
+
+    pub fn distance(&self, position: &Coordinate, field_size: f32) -> f32 {
+        //! The distance from `position` to the edge of the obstacle, or 0
+        //! inside it.
+
+        let length = |delta: Coordinate| (delta.x * delta.x + delta.y * delta.y).sqrt();
+        let distance = match self {
+            Obstacle::Circle { center, radius } => {
+                length(torus_delta(center, position, field_size)) - radius
+            }
+            Obstacle::Rectangle { center, size } => {
+                let delta = torus_delta(center, position, field_size);
+                length(Coordinate {
+                    x: (delta.x.abs() - size.x / 2.0).max(0.0),
+                    y: (delta.y.abs() - size.y / 2.0).max(0.0),
+                })
+            }
+            Obstacle::Wall { from, to, width } => {
+                let wall = torus_delta(from, to, field_size);
+                let delta = torus_delta(from, position, field_size);
+                let length2 = wall.x * wall.x + wall.y * wall.y;
+                let along = if length2 > 0.0 {
+                    ((delta.x * wall.x + delta.y * wall.y) / length2).clamp(0.0, 1.0)
+                } else {
+                    0.0
+                };
+                length(Coordinate {
+                    x: delta.x - along * wall.x,
+                    y: delta.y - along * wall.y,
+                }) - width / 2.0
+            }
+        };
+        distance.max(0.0)
+    }
+
+    pub fn center(&self, field_size: f32) -> Coordinate {
+        match self {
+            Obstacle::Circle { center, .. } | Obstacle::Rectangle { center, .. } => *center,
+            Obstacle::Wall { from, to, .. } => {
+                let wall = torus_delta(from, to, field_size);
+                Coordinate {
+                    x: from.x + wall.x / 2.0,
+                    y: from.y + wall.y / 2.0,
+                }
+            }
+        }
+    }
+
+    pub fn bounding_radius(&self, field_size: f32) -> f32 {
+        //! The radius of a circle around `center()` that holds the obstacle.
+
+        match self {
+            Obstacle::Circle { radius, .. } => *radius,
+            Obstacle::Rectangle { size, .. } => (size.x * size.x + size.y * size.y).sqrt() / 2.0,
+            Obstacle::Wall { from, to, width } => {
+                let wall = torus_delta(from, to, field_size);
+                ((wall.x * wall.x + wall.y * wall.y).sqrt() + width) / 2.0
+            }
+        }
+    }
+
+    pub fn moved_to(&self, center: Coordinate, field_size: f32) -> Obstacle {
+        //! The same obstacle with its center at `center`. The ends of a wall
+        //! are placed the short way around the torus, without wrapping.
+
+        match *self {
+            Obstacle::Circle { radius, .. } => Obstacle::Circle { center, radius },
+            Obstacle::Rectangle { size, .. } => Obstacle::Rectangle { center, size },
+            Obstacle::Wall { from, to, width } => {
+                let wall = torus_delta(&from, &to, field_size);
+                Obstacle::Wall {
+                    from: Coordinate {
+                        x: center.x - wall.x / 2.0,
+                        y: center.y - wall.y / 2.0,
+                    },
+                    to: Coordinate {
+                        x: center.x + wall.x / 2.0,
+                        y: center.y + wall.y / 2.0,
+                    },
+                    width,
+                }
+            }
+        }
+    }
+}
+
+impl GameEngine {
+    pub(crate) fn is_blocked(&self, position: &Coordinate, clearance: f32) -> bool {
+        //! Whether an obstacle lies within `clearance` of `position`.
+
+        let field_size = self.config.field_size;
+        self.config
+            .obstacles
+            .iter()
+            .any(|obstacle| obstacle.distance(position, field_size) <= clearance)
+    }
+
+    pub(crate) fn free_pellet_coordinate(&mut self) -> Option<Coordinate> {
+        //! A spawn position for a fresh pellet outside every obstacle.
+
+        for _ in 0..PELLET_SPAWN_ATTEMPTS {
+            let position = self.pellet_spawn_coordinate();
+            if !self.is_blocked(&position, 0.0) {
+                return Some(position);
+            }
+        }
+        None
+    }
+
+    pub(crate) fn crash_into_obstacles(&mut self) -> Vec<DeathEvent> {
+        //! Kill every snake whose head touches an obstacle.
+
+        if self.config.obstacles.is_empty() {
+            return Vec::new();
+        }
+        let crashed: Vec<_> = self
+            .snakes
+            .iter()
+            .filter(|(_, snake)| self.is_blocked(snake.get_head(), snake.size as f32))
+            .map(|(id, _)| *id)
+            .collect();
+        crashed
+            .into_iter()
+            .filter_map(|id| {
+                let death = self.death_event(&id, None, DeathCause::Obstacle);
+                self.drop_snake(&id);
+                death
+            })
+            .collect()
+    }
+
+    pub(crate) fn obstacles_in_rectangle(
+        &self,
+        x0: f32,
+        y0: f32,
+        width: f32,
+        height: f32,
+    ) -> Vec<Obstacle> {
+        let field_size = self.config.field_size;
+        self.config
+            .obstacles
+            .iter()
+            .filter(|obstacle| {
+                let margin = obstacle.bounding_radius(field_size);
+                obstacle.center(field_size).is_in_rectangle(
+                    x0 - margin,
+                    y0 - margin,
+                    width + 2.0 * margin,
+                    height + 2.0 * margin,
+                    field_size,
+                )
+            })
+            .copied()
+            .collect()
+    }
+}
+
+pub(crate) fn obstacle_cells(config: &GameConfig) -> Vec<u8> {
+    //! The minimap layer of the obstacles: 1 for every cell whose center is
+    //! within half a cell of one. Obstacles never move, so the engine
+    //! computes it once.
+
+    if config.obstacles.is_empty() {
+        return Vec::new();
+    }
+    let size = config.map_size;
+    let cell_width = config.field_size / size as f32;
+    (0..size)
+        .flat_map(|x| (0..size).map(move |y| (x, y)))
+        .map(|(x, y)| {
+            let center = Coordinate {
+                x: (x as f32 + 0.5) * cell_width,
+                y: (y as f32 + 0.5) * cell_width,
+            };
+            config
+                .obstacles
+                .iter()
+                .any(|obstacle| obstacle.distance(&center, config.field_size) <= cell_width / 2.0)
+                as u8
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn arena() -> GameConfig {
+        GameConfig {
+            max_pellet_count: 300,
+            obstacles: vec![
+                Obstacle::Circle {
+                    center: Coordinate { x: 500.0, y: 500.0 },
+                    radius: 100.0,
+                },
+                Obstacle::Rectangle {
+                    center: Coordinate { x: 0.0, y: 200.0 },
+                    size: Coordinate { x: 200.0, y: 100.0 },
+                },
+                Obstacle::Wall {
+                    from: Coordinate { x: 300.0, y: 800.0 },
+                    to: Coordinate { x: 700.0, y: 800.0 },
+                    width: 20.0,
+                },
+            ],
+            ..GameConfig::small_test_field()
+        }
+    }
+
+    #[test]
+    fn level_files_spell_the_shapes_out() {
+        let level = Level::from_json(
+            r#"{"obstacles": [
+                {"circle": {"center": [500, 500], "radius": 100}},
+                {"wall": {"from": [300, 800], "to": [700, 800], "width": 20}}
+            ]}"#,
+        )
+        .unwrap();
+
+        assert_eq!(level.obstacles[0], arena().obstacles[0]);
+        assert_eq!(level.obstacles[1], arena().obstacles[2]);
+        assert!(Level::from_json(
+            r#"{"obstacles": [{"circle": {"center": [0, 0], "radius": 0}}]}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn walls_must_be_shorter_than_half_the_field() {
+        let wall = |length: f32| Obstacle::Wall {
+            from: Coordinate { x: 100.0, y: 100.0 },
+            to: Coordinate {
+                x: 100.0 + length,
+                y: 100.0,
+            },
+            width: 20.0,
+        };
+        let config = |obstacle| GameConfig {
+            obstacles: vec![obstacle],
+            ..arena()
+        };
+
+        assert!(config(wall(499.0)).validate().is_ok());
+        assert!(config(wall(500.0)).validate().is_err());
+        assert!(arena().validate().is_ok());
+    }
+
+    #[test]
+    fn distances_wrap_around_the_field() {
+        let config = arena();
+        let [circle, rectangle, wall] = [0, 1, 2].map(|index| config.obstacles[index]);
+
+        assert_eq!(
+            circle.distance(&Coordinate { x: 500.0, y: 650.0 }, 1000.0),
+            50.0
+        );
+        assert_eq!(
+            circle.distance(&Coordinate { x: 550.0, y: 500.0 }, 1000.0),
+            0.0
+        );
+        assert_eq!(
+            rectangle.distance(&Coordinate { x: 950.0, y: 200.0 }, 1000.0),
+            0.0
+        );
+        assert_eq!(
+            rectangle.distance(&Coordinate { x: 850.0, y: 200.0 }, 1000.0),
+            50.0
+        );
+        assert_eq!(
+            wall.distance(&Coordinate { x: 500.0, y: 850.0 }, 1000.0),
+            40.0
+        );
+        assert_eq!(
+            wall.distance(&Coordinate { x: 200.0, y: 800.0 }, 1000.0),
+            90.0
+        );
+    }
+
+    #[test]
+    fn heads_touching_an_obstacle_die() {
+        let mut engine = GameEngine::with_seed(arena(), 1);
+        let (crasher, bystander) = (Uuid::from_u128(1), Uuid::from_u128(2));
+        engine.add_snake_at(crasher, Coordinate { x: 500.0, y: 380.0 });
+        engine.add_snake_at(bystander, Coordinate { x: 300.0, y: 300.0 });
+        for id in [crasher, bystander] {
+            let snake = engine.get_snake_mut(&id).unwrap();
+            snake.velocity = Coordinate { x: 0.0, y: 1.0 };
+            snake.target_velocity = snake.velocity;
+        }
+
+        let events = engine.forward();
+
+        assert_eq!(events.deaths.len(), 1);
+        assert_eq!(events.deaths[0].id, crasher);
+        assert_eq!(events.deaths[0].cause, DeathCause::Obstacle);
+        assert!(engine.get_snake(&bystander).is_some());
+    }
+
+    #[test]
+    fn snakes_dying_next_to_an_obstacle_drop_their_whole_mass() {
+        let config = GameConfig {
+            max_pellet_count: 0,
+            ..arena()
+        };
+        let mut engine = GameEngine::with_seed(config, 4);
+        let id = Uuid::from_u128(1);
+        engine.add_snake_at(id, Coordinate { x: 500.0, y: 395.0 });
+        // Hug the circle so that most jittered drops land inside it.
+        engine.get_snake_mut(&id).unwrap().bodies = (0..90)
+            .map(|index| {
+                let angle = index as f32 * 0.03;
+                Coordinate {
+                    x: 500.0 + 102.0 * angle.sin(),
+                    y: 500.0 - 102.0 * angle.cos(),
+                }
+            })
+            .collect();
+
+        engine.remove_snake(&id);
+
+        let dropped: usize = engine
+            .pellets
+            .values()
+            .map(|pellet| pellet.size as usize)
+            .sum();
+        assert_eq!(dropped, 45);
+        assert!(engine
+            .pellets
+            .values()
+            .all(|pellet| !engine.is_blocked(&pellet.center, 0.0)));
+    }
+
+    #[test]
+    fn pellets_and_snakes_do_not_spawn_inside_obstacles() {
+        let mut engine = GameEngine::with_seed(arena(), 2);
+        engine.forward();
+
+        assert_eq!(engine.pellets.len(), 300);
+        assert!(engine
+            .pellets
+            .values()
+            .all(|pellet| !engine.is_blocked(&pellet.center, 0.0)));
+        for number in 0..10 {
+            let id = Uuid::from_u128(number);
+            engine.add_snake(id);
+            let head = *engine.get_snake(&id).unwrap().get_head();
+            assert!(!engine.is_blocked(&head, 50.0));
+        }
+    }
+
+    #[test]
+    fn obstacles_show_up_in_views_the_minimap_and_bot_probes() {
+        let mut engine = GameEngine::with_seed(arena(), 3);
+        let id = Uuid::from_u128(1);
+        engine.add_snake_at(id, Coordinate { x: 500.0, y: 300.0 });
+        let snake = engine.get_snake_mut(&id).unwrap();
+        snake.velocity = Coordinate { x: 0.0, y: 1.0 };
+        snake.target_velocity = snake.velocity;
+
+        let view = engine.view_state(&id, 500.0, 380.0, 200.0, 200.0, &mut Default::default());
+        assert_eq!(view.obstacles, vec![arena().obstacles[0]]);
+
+        let map = engine.map();
+        assert_eq!(map.obstacles_at(5, 5), 1);
+        assert_eq!(map.obstacles_at(5, 2), 0);
+
+        let observation = engine.bot_observation(&id, None).unwrap();
+        assert_ne!(observation.danger_mask, 0);
+    }
+}
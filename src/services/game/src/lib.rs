@@ -1,7 +1,21 @@
+mod body_grid;
 pub mod bot;
+pub mod config;
 pub mod coordinate;
 pub mod engine;
+pub mod food;
+pub mod level;
 pub mod map;
+mod minimap;
 pub mod pellet;
+pub mod power_up;
+pub mod protocol;
+pub mod quantized;
+pub mod replay;
 pub mod snake;
+pub mod snapshot;
+mod spawn;
+pub mod team;
 pub mod view;
+pub mod view_stream;
+pub mod zone;
@@ -5,13 +5,61 @@ use ciborium::{
 use serde::{Deserialize, Serialize};
 use std::io::Error;
 
-#[derive(Serialize, Deserialize)]
+/// How crowded each cell of the minimap is. The same for every player; the
+/// player's own layer is sent as a `MapSelf`.
+///
+/// The layers hold the number of pellets or body points in cell `(x, y)` at
+/// `x * size + y`, saturated at `u8::MAX`. Most cells are empty, so they are
+/// sent run-length encoded.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct Map {
-    pub map: Vec<Vec<u32>>,
-    pub self_coordinate: (usize, usize),
+    /// Cells per side.
+    #[serde(rename = "s")]
+    pub size: usize,
+    #[serde(rename = "p", with = "run_length")]
+    pub pellets: Vec<u8>,
+    #[serde(rename = "n", with = "run_length")]
+    pub snakes: Vec<u8>,
+    /// 1 for the cells covered by an obstacle; empty for levels without.
+    #[serde(rename = "o", with = "run_length", default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub obstacles: Vec<u8>,
+    /// The cells of the heads of the longest snakes, longest first.
+    #[serde(rename = "l")]
+    pub leaders: Vec<(usize, usize)>,
+}
+
+/// The part of the minimap that differs per player.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MapSelf {
+    /// The cell the player's camera is in.
+    #[serde(rename = "p")]
+    pub position: (usize, usize),
+    /// Every cell the player's body covers.
+    #[serde(rename = "b")]
+    pub body: Vec<(usize, usize)>,
 }
 
 impl Map {
+    pub fn pellets_at(&self, x: usize, y: usize) -> u8 {
+        self.cell(&self.pellets, x, y)
+    }
+
+    pub fn snakes_at(&self, x: usize, y: usize) -> u8 {
+        self.cell(&self.snakes, x, y)
+    }
+
+    pub fn obstacles_at(&self, x: usize, y: usize) -> u8 {
+        self.cell(&self.obstacles, x, y)
+    }
+
+    fn cell(&self, layer: &[u8], x: usize, y: usize) -> u8 {
+        if x >= self.size || y >= self.size {
+            return 0;
+        }
+        layer.get(x * self.size + y).copied().unwrap_or(0)
+    }
+
     pub fn from_bytes(bytes: &[u8]) -> Result<Map, CiboriumError<Error>> {
         from_reader(bytes)
     }
@@ -22,3 +70,85 @@ impl Map {
         bytes
     }
 }
+
+mod run_length {
+    //! A CBOR byte string of `(count, value)` pairs.
+
+    use serde::de::{Error, SeqAccess, Visitor};
+    use serde::{Deserializer, Serializer};
+    use std::fmt;
+
+    pub fn serialize<S: Serializer>(cells: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        let mut runs = Vec::new();
+        for chunk in cells.chunk_by(|a, b| a == b) {
+            for run in chunk.chunks(u8::MAX as usize) {
+                runs.push(run.len() as u8);
+                runs.push(run[0]);
+            }
+        }
+        serializer.serialize_bytes(&runs)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        deserializer.deserialize_bytes(RunVisitor)
+    }
+
+    struct RunVisitor;
+
+    impl<'de> Visitor<'de> for RunVisitor {
+        type Value = Vec<u8>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("run-length encoded bytes")
+        }
+
+        fn visit_bytes<E: Error>(self, runs: &[u8]) -> Result<Vec<u8>, E> {
+            if !runs.len().is_multiple_of(2) {
+                return Err(E::custom("run without a value"));
+            }
+            Ok(runs
+                .chunks_exact(2)
+                .flat_map(|run| std::iter::repeat_n(run[1], run[0] as usize))
+                .collect())
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut sequence: A) -> Result<Vec<u8>, A::Error> {
+            let mut runs = Vec::new();
+            while let Some(byte) = sequence.next_element()? {
+                runs.push(byte);
+            }
+            self.visit_bytes(&runs)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sparse_maps_round_trip_in_a_few_bytes() {
+        let size = 100;
+        let mut pellets = vec![0; size * size];
+        pellets[5] = 3;
+        pellets[6] = 3;
+        let mut snakes = vec![0; size * size];
+        snakes[4321] = u8::MAX;
+        let map = Map {
+            size,
+            pellets,
+            snakes,
+            obstacles: Vec::new(),
+            leaders: vec![(43, 21)],
+        };
+
+        let bytes = map.to_bytes();
+        let decoded = Map::from_bytes(&bytes).unwrap();
+
+        assert_eq!(decoded, map);
+        assert_eq!(decoded.snakes_at(43, 21), u8::MAX);
+        assert_eq!(decoded.pellets_at(43, 21), 0);
+        assert_eq!(decoded.snakes_at(100, 0), 0);
+        assert!(bytes.len() < 200, "{} bytes", bytes.len());
+    }
+}
@@ -0,0 +1,289 @@
+use std::collections::BTreeSet;
+use uuid::Uuid;
+
+use super::config::GameConfig;
+use super::coordinate::Coordinate;
+use super::engine::GameEngine;
+use super::map::{Map, MapSelf};
+
+/// Number of snakes whose heads are marked on the minimap.
+const MAP_LEADER_COUNT: usize = 3;
+
+/// The number of body points or pellets in each minimap cell, updated as
+/// they are added, moved and removed so that a `Map` is a plain copy.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct DensityGrid {
+    size: usize,
+    field_size: f32,
+    cell_width: f32,
+    cells: Vec<u32>,
+}
+
+impl DensityGrid {
+    pub(crate) fn new(config: &GameConfig) -> Self {
+        DensityGrid {
+            size: config.map_size,
+            field_size: config.field_size,
+            cell_width: config.field_size / config.map_size as f32,
+            cells: vec![0; config.map_size * config.map_size],
+        }
+    }
+
+    pub(crate) fn cell(&self, position: &Coordinate) -> (usize, usize) {
+        let x = (position.x.rem_euclid(self.field_size) / self.cell_width).floor() as usize;
+        let y = (position.y.rem_euclid(self.field_size) / self.cell_width).floor() as usize;
+        (x.min(self.size - 1), y.min(self.size - 1))
+    }
+
+    fn index(&self, position: &Coordinate) -> usize {
+        let (x, y) = self.cell(position);
+        x * self.size + y
+    }
+
+    pub(crate) fn add(&mut self, position: &Coordinate) {
+        let index = self.index(position);
+        self.cells[index] += 1;
+    }
+
+    pub(crate) fn remove(&mut self, position: &Coordinate) {
+        let index = self.index(position);
+        self.cells[index] -= 1;
+    }
+
+    pub(crate) fn crowding(&self, position: &Coordinate, radius: usize) -> u32 {
+        //! The total count of the cells within `radius` cells of `position`,
+        //! wrapping around the field.
+
+        let radius = radius.min((self.size - 1) / 2);
+        let (x, y) = self.cell(position);
+        let cells = |center: usize| {
+            (0..=2 * radius).map(move |offset| {
+                (center + self.size * (radius + 1) + offset - radius) % self.size
+            })
+        };
+        cells(x)
+            .flat_map(|x| cells(y).map(move |y| x * self.size + y))
+            .map(|index| self.cells[index])
+            .sum()
+    }
+
+    fn saturated(&self) -> Vec<u8> {
+        self.cells
+            .iter()
+            .map(|&count| count.min(u8::MAX as u32) as u8)
+            .collect()
+    }
+
+    pub(crate) fn shift(&mut self, from: &Coordinate, to: &Coordinate) {
+        let (from, to) = (self.index(from), self.index(to));
+        if from != to {
+            self.cells[from] -= 1;
+            self.cells[to] += 1;
+        }
+    }
+}
+
+impl GameEngine {
+    pub fn map(&self) -> Map {
+        //! The minimap shared by every player. Snakes changed through
+        //! `get_snake_mut()` are counted again from the next `forward()`.
+
+        let mut leaders: Vec<_> = self.snakes.iter().collect();
+        // Longest first; ties go to the smaller id so that markers do not flicker.
+        leaders.sort_by(|(left_id, left), (right_id, right)| {
+            right
+                .bodies
+                .len()
+                .cmp(&left.bodies.len())
+                .then_with(|| left_id.cmp(right_id))
+        });
+
+        Map {
+            size: self.pellet_density.size,
+            pellets: self.pellet_density.saturated(),
+            snakes: self.snake_density.saturated(),
+            obstacles: self.obstacle_cells.clone(),
+            leaders: leaders
+                .into_iter()
+                .take(MAP_LEADER_COUNT)
+                .map(|(_, snake)| self.snake_density.cell(snake.get_head()))
+                .collect(),
+        }
+    }
+
+    pub fn map_self(&self, id: &Uuid, cx: f32, cy: f32) -> MapSelf {
+        //! The viewer's own layer of the minimap: where the camera is and the
+        //! cells the viewer's body covers.
+
+        let body: BTreeSet<_> = self
+            .snakes
+            .get(id)
+            .map(|snake| {
+                snake
+                    .bodies
+                    .iter()
+                    .map(|body| self.snake_density.cell(body))
+                    .collect()
+            })
+            .unwrap_or_default();
+        MapSelf {
+            position: self.map_coordinate(cx, cy),
+            body: body.into_iter().collect(),
+        }
+    }
+
+    pub fn map_coordinate(&self, cx: f32, cy: f32) -> (usize, usize) {
+        self.pellet_density.cell(&Coordinate { x: cx, y: cy })
+    }
+
+    pub(crate) fn rebuild_density(&mut self) {
+        self.pellet_density = DensityGrid::new(&self.config);
+        self.snake_density = DensityGrid::new(&self.config);
+        self.detached_snakes.clear();
+        for snake in self.snakes.values() {
+            for body in snake.bodies.iter() {
+                self.snake_density.add(body);
+            }
+        }
+        for pellet in self.pellets.values() {
+            self.pellet_density.add(&pellet.center);
+        }
+    }
+
+    pub(crate) fn detach_snake_density(&mut self, id: &Uuid) {
+        //! Stop counting a snake, because it is about to be removed or its
+        //! bodies may change outside of `forward()`.
+
+        if !self.detached_snakes.insert(*id) {
+            return;
+        }
+        if let Some(snake) = self.snakes.get(id) {
+            for body in snake.bodies.iter() {
+                self.snake_density.remove(body);
+            }
+        }
+    }
+
+    pub(crate) fn attach_snake_density(&mut self, id: &Uuid) {
+        if !self.detached_snakes.remove(id) {
+            return;
+        }
+        if let Some(snake) = self.snakes.get(id) {
+            for body in snake.bodies.iter() {
+                self.snake_density.add(body);
+            }
+        }
+    }
+
+    pub(crate) fn attach_snake_densities(&mut self) {
+        for id in std::mem::take(&mut self.detached_snakes) {
+            if let Some(snake) = self.snakes.get(&id) {
+                for body in snake.bodies.iter() {
+                    self.snake_density.add(body);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recounted(engine: &GameEngine) -> (DensityGrid, DensityGrid) {
+        let mut pellets = DensityGrid::new(&engine.config);
+        let mut snakes = DensityGrid::new(&engine.config);
+        for snake in engine.snakes.values() {
+            for body in snake.bodies.iter() {
+                snakes.add(body);
+            }
+        }
+        for pellet in engine.pellets.values() {
+            pellets.add(&pellet.center);
+        }
+        (pellets, snakes)
+    }
+
+    fn densities(engine: &GameEngine) -> (DensityGrid, DensityGrid) {
+        (engine.pellet_density.clone(), engine.snake_density.clone())
+    }
+
+    #[test]
+    fn density_follows_moving_snakes_and_pellets() {
+        let config = GameConfig {
+            field_size: 1000.0,
+            max_pellet_count: 500,
+            map_size: 20,
+            ..GameConfig::default()
+        };
+        let mut engine = GameEngine::with_seed(config, 9);
+        for number in 0..12 {
+            engine.add_snake(Uuid::from_u128(number));
+        }
+
+        for frame in 0..300 {
+            for number in 0..12 {
+                let id = Uuid::from_u128(number);
+                if engine.get_snake(&id).is_none() {
+                    engine.add_snake(id);
+                }
+                if (frame + number) % 7 == 0 {
+                    engine.accelerate(&id);
+                }
+            }
+            engine.forward();
+            assert_eq!(densities(&engine), recounted(&engine), "frame {frame}");
+        }
+    }
+
+    #[test]
+    fn snakes_changed_from_outside_are_recounted() {
+        let id = Uuid::from_u128(1);
+        let mut engine = GameEngine::with_seed(GameConfig::default(), 3);
+        engine.add_snake_at(id, Coordinate { x: 50.0, y: 50.0 });
+
+        let snake = engine.get_snake_mut(&id).unwrap();
+        snake.bodies.push_back(Coordinate {
+            x: 5000.0,
+            y: 5000.0,
+        });
+        engine.get_snake_mut(&id).unwrap();
+        engine.forward();
+        assert_eq!(densities(&engine), recounted(&engine));
+
+        engine.get_snake_mut(&id).unwrap().bodies.pop_back();
+        engine.remove_snake(&id);
+        assert_eq!(densities(&engine), recounted(&engine));
+    }
+
+    #[test]
+    fn map_layers_separate_pellets_snakes_leaders_and_self() {
+        let config = GameConfig {
+            max_pellet_count: 0,
+            ..GameConfig::default()
+        };
+        let mut engine = GameEngine::with_seed(config, 5);
+        let ids: Vec<_> = (1..=5).map(Uuid::from_u128).collect();
+        for (number, id) in ids.iter().enumerate() {
+            let position = Coordinate {
+                x: 1000.0 * number as f32 + 50.0,
+                y: 50.0,
+            };
+            engine.add_snake_at(*id, position);
+            let snake = engine.get_snake_mut(id).unwrap();
+            snake
+                .bodies
+                .extend(std::iter::repeat_n(position, number * 10));
+        }
+        engine.attach_snake_densities();
+
+        let map = engine.map();
+        let viewer = engine.map_self(&ids[0], 50.0, 50.0);
+
+        assert!(map.pellets.iter().all(|&count| count == 0));
+        assert!(map.snakes_at(40, 0) > 0);
+        assert_eq!(map.leaders, vec![(40, 0), (30, 0), (20, 0)]);
+        assert_eq!(viewer.position, (0, 0));
+        assert_eq!(viewer.body, vec![(0, 0)]);
+    }
+}
@@ -2,6 +2,7 @@ use rand::RngExt;
 use serde::{Deserialize, Serialize};
 
 use super::coordinate::Coordinate;
+use super::engine::GameRng;
 
 const COLORS: [&str; 7] = [
     "8",   // red
@@ -13,6 +14,22 @@ const COLORS: [&str; 7] = [
     "330", // pink
 ];
 
+/// What eating a pellet does besides growing the snake.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PelletKind {
+    #[default]
+    #[serde(rename = "f")]
+    Food,
+    #[serde(rename = "m")]
+    Magnet,
+    #[serde(rename = "s")]
+    Shield,
+    #[serde(rename = "b")]
+    Speed,
+    #[serde(rename = "g")]
+    Ghost,
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Pellet {
     #[serde(skip)]
@@ -25,19 +42,30 @@ pub struct Pellet {
     pub size: u8,
     #[serde(rename = "c")]
     pub color: String,
+    #[serde(rename = "k")]
+    pub kind: PelletKind,
     #[serde(rename = "f")]
     pub frame_count_offset: u32,
+    /// The frame a dropped pellet decays at. In a `View`, the frames it has
+    /// left instead, the way `frame_count_offset` is its age there.
+    #[serde(rename = "d")]
+    pub decay_frame: Option<u32>,
+    #[serde(rename = "i")]
+    pub handle: u16, // for rendering, see `EntityHandles`
 }
 
 impl Pellet {
-    pub fn new(initial_position: Coordinate) -> Pellet {
+    pub fn new(initial_position: Coordinate, rng: &mut GameRng) -> Pellet {
         Pellet {
             center: initial_position,
-            radius: rand::rng().random_range(0.5..5.0),
+            radius: rng.random_range(0.5..5.0),
             position: initial_position,
-            size: rand::rng().random_range(1..4),
-            color: COLORS[rand::rng().random_range(0..COLORS.len())].to_string(),
+            size: rng.random_range(1..4),
+            color: COLORS[rng.random_range(0..COLORS.len())].to_string(),
+            kind: PelletKind::Food,
             frame_count_offset: 0,
+            decay_frame: None,
+            handle: 0,
         }
     }
 
@@ -45,17 +73,27 @@ impl Pellet {
         initial_position: Coordinate,
         color: String,
         size: u8,
+        rng: &mut GameRng,
     ) -> Pellet {
         Pellet {
             center: initial_position,
-            radius: rand::rng().random_range(0.5..5.0),
+            radius: rng.random_range(0.5..5.0),
             position: initial_position,
             size,
             color,
+            kind: PelletKind::Food,
             frame_count_offset: 0,
+            decay_frame: None,
+            handle: 0,
         }
     }
 
+    pub fn value(&self) -> usize {
+        //! Body points a snake grows by when eating this pellet.
+
+        self.size as usize
+    }
+
     pub fn update(&mut self) {
         let theta = self.frame_count_offset % 72 * 5;
         let rad = theta as f32 * std::f32::consts::PI / 180.0;
@@ -0,0 +1,242 @@
+use rand::RngExt;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::coordinate::Coordinate;
+use super::engine::GameEngine;
+use super::pellet::{Pellet, PelletKind};
+use super::snake::Snake;
+
+/// The kinds a fresh pellet can have besides food, drawn with equal odds.
+const POWER_UPS: [PelletKind; 4] = [
+    PelletKind::Magnet,
+    PelletKind::Shield,
+    PelletKind::Speed,
+    PelletKind::Ghost,
+];
+/// The hue of each power-up pellet, in the order of `POWER_UPS`.
+const POWER_UP_COLORS: [&str; 4] = ["180", "210", "45", "270"];
+/// A snake with a magnet draws pellets in from this many times its size,
+/// instead of twice.
+const MAGNET_REACH: usize = 6;
+/// Frames a shield that absorbed a collision keeps its snake protected, so
+/// that it can get clear of the body it hit.
+const SHIELD_GRACE_FRAMES: u32 = 15;
+
+/// Frames left of each power-up a snake holds.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct PowerUps {
+    /// Draws pellets in from further away.
+    #[serde(rename = "m")]
+    pub magnet: u32,
+    /// Survives one collision with a body.
+    #[serde(rename = "s")]
+    pub shield: u32,
+    /// Moves at boost speed without shedding its tail.
+    #[serde(rename = "b")]
+    pub speed: u32,
+    /// Passes through bodies.
+    #[serde(rename = "g")]
+    pub ghost: u32,
+}
+
+impl PowerUps {
+    pub fn collect(&mut self, kind: PelletKind, frames: u32) {
+        let frames_left = match kind {
+            PelletKind::Food => return,
+            PelletKind::Magnet => &mut self.magnet,
+            PelletKind::Shield => &mut self.shield,
+            PelletKind::Speed => &mut self.speed,
+            PelletKind::Ghost => &mut self.ghost,
+        };
+        *frames_left = frames;
+    }
+
+    pub(crate) fn tick(&mut self) {
+        for frames_left in [
+            &mut self.magnet,
+            &mut self.shield,
+            &mut self.speed,
+            &mut self.ghost,
+        ] {
+            *frames_left = frames_left.saturating_sub(1);
+        }
+    }
+}
+
+impl Snake {
+    pub(crate) fn attraction_radius(&self) -> usize {
+        if self.power_ups.magnet > 0 {
+            self.size * MAGNET_REACH
+        } else {
+            self.size * 2
+        }
+    }
+}
+
+impl GameEngine {
+    pub(crate) fn random_pellet(&mut self, position: Coordinate) -> Pellet {
+        //! A fresh pellet, which is a power-up once in `power_up_chance`.
+
+        let mut pellet = Pellet::new(position, &mut self.rng);
+        if self.rng.random::<f32>() < self.config.power_up_chance {
+            let index = self.rng.random_range(0..POWER_UPS.len());
+            pellet.kind = POWER_UPS[index];
+            pellet.color = POWER_UP_COLORS[index].to_owned();
+            pellet.size = 3;
+        }
+        pellet
+    }
+
+    pub(crate) fn absorb_with_shield(&mut self, id: &Uuid) -> bool {
+        //! Spend the snake's shield on a body collision it would die of.
+
+        let Some(snake) = self.snakes.get_mut(id) else {
+            return false;
+        };
+        if snake.power_ups.shield == 0 {
+            return false;
+        }
+        snake.power_ups.shield = 0;
+        snake.protection_time_left = snake.protection_time_left.max(SHIELD_GRACE_FRAMES);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::GameConfig;
+    use crate::engine::DeathCause;
+
+    fn config() -> GameConfig {
+        GameConfig {
+            power_up_frames: 5,
+            ..GameConfig::small_test_field()
+        }
+    }
+
+    fn drop_pellet(engine: &mut GameEngine, position: Coordinate, kind: PelletKind) -> Uuid {
+        let mut pellet =
+            Pellet::new_with_color_and_size(position, "0".to_owned(), 1, &mut engine.rng);
+        pellet.kind = kind;
+        let id = Uuid::new_v4();
+        GameEngine::insert_pellet_into(
+            &engine.config,
+            &mut engine.pellets,
+            &mut engine.pellet_grid,
+            &mut engine.pellet_density,
+            id,
+            pellet,
+            engine.frame_count,
+        );
+        id
+    }
+
+    fn head_on_a_body(power_ups: PowerUps) -> (GameEngine, Uuid) {
+        //! A motionless snake whose head lies on the middle of another
+        //! snake's body.
+
+        let mut engine = GameEngine::with_seed(config(), 1);
+        let (runner, wall) = (Uuid::from_u128(1), Uuid::from_u128(2));
+        engine.add_snake_at(wall, Coordinate { x: 800.0, y: 100.0 });
+        engine.get_snake_mut(&wall).unwrap().bodies = (0..40)
+            .map(|index| Coordinate {
+                x: 800.0 - 20.0 * index as f32,
+                y: 100.0,
+            })
+            .collect();
+        engine.add_snake_at(runner, Coordinate { x: 500.0, y: 105.0 });
+        engine.get_snake_mut(&runner).unwrap().power_ups = power_ups;
+        (engine, runner)
+    }
+
+    #[test]
+    fn eating_a_power_up_collects_it_for_a_while() {
+        let mut engine = GameEngine::with_seed(config(), 2);
+        let id = Uuid::from_u128(1);
+        engine.add_snake_at(id, Coordinate { x: 100.0, y: 100.0 });
+        drop_pellet(
+            &mut engine,
+            Coordinate { x: 100.0, y: 100.0 },
+            PelletKind::Shield,
+        );
+
+        engine.forward();
+        assert_eq!(engine.get_snake(&id).unwrap().power_ups.shield, 5);
+        for _ in 0..5 {
+            engine.forward();
+        }
+        assert_eq!(
+            engine.get_snake(&id).unwrap().power_ups,
+            PowerUps::default()
+        );
+    }
+
+    #[test]
+    fn a_magnet_draws_pellets_from_further_away() {
+        let mut engine = GameEngine::with_seed(config(), 3);
+        let id = Uuid::from_u128(1);
+        engine.add_snake_at(id, Coordinate { x: 100.0, y: 100.0 });
+        let start = Coordinate { x: 160.0, y: 100.0 };
+        let pellet = drop_pellet(&mut engine, start, PelletKind::Food);
+
+        engine.forward();
+        assert_eq!(engine.pellets[&pellet].position, start);
+
+        engine.get_snake_mut(&id).unwrap().power_ups.magnet = 5;
+        engine.forward();
+        assert!(engine.pellets[&pellet].position.x < start.x);
+    }
+
+    #[test]
+    fn speed_moves_at_boost_speed_without_shedding() {
+        let mut engine = GameEngine::with_seed(config(), 4);
+        let id = Uuid::from_u128(1);
+        engine.add_snake_at(id, Coordinate { x: 100.0, y: 100.0 });
+        let snake = engine.get_snake_mut(&id).unwrap();
+        snake.velocity = Coordinate { x: 1.0, y: 0.0 };
+        snake.target_velocity = snake.velocity;
+        snake.power_ups.speed = 5;
+        let length = snake.bodies.len();
+
+        engine.forward();
+
+        let snake = engine.get_snake(&id).unwrap();
+        assert_eq!(snake.get_head().x, 100.0 + 2.0 * engine.config.snake_speed);
+        assert_eq!(snake.bodies.len(), length);
+        assert!(engine.pellets.is_empty());
+    }
+
+    #[test]
+    fn a_shield_survives_one_body_collision() {
+        let (mut engine, runner) = head_on_a_body(PowerUps {
+            shield: 5,
+            ..PowerUps::default()
+        });
+
+        assert!(engine.forward().deaths.is_empty());
+        assert_eq!(engine.get_snake(&runner).unwrap().power_ups.shield, 0);
+
+        let deaths: Vec<_> = (0..SHIELD_GRACE_FRAMES + 1)
+            .flat_map(|_| engine.forward().deaths)
+            .collect();
+        assert_eq!(deaths.len(), 1);
+        assert_eq!((deaths[0].id, deaths[0].cause), (runner, DeathCause::Body));
+    }
+
+    #[test]
+    fn ghosts_pass_through_bodies_until_the_power_up_ends() {
+        let (mut engine, runner) = head_on_a_body(PowerUps {
+            ghost: 5,
+            ..PowerUps::default()
+        });
+
+        for _ in 0..4 {
+            assert!(engine.forward().deaths.is_empty());
+        }
+        let deaths = engine.forward().deaths;
+        assert_eq!(deaths.len(), 1);
+        assert_eq!(deaths[0].id, runner);
+    }
+}
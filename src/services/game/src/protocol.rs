@@ -0,0 +1,435 @@
+use ciborium::{
+    de::{from_reader, Error as CiboriumError},
+    ser::into_writer,
+};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::Error;
+use std::str::FromStr;
+use uuid::Uuid;
+
+use super::coordinate::Coordinate;
+use super::engine::DeathCause;
+use super::map::{Map, MapSelf};
+use super::quantized::{QuantizedPacket, WireFormat};
+use super::view_stream::ViewPacket;
+
+/// Bumped whenever a message changes incompatibly. Adding a new kind of
+/// message does not need a bump: clients drop messages they cannot decode.
+pub const PROTOCOL_VERSION: u16 = 11;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RankingEntry {
+    pub name: String,
+    pub score: usize,
+    pub kills: usize,
+    pub is_bot: bool,
+    pub rank: usize,
+    pub is_self: bool,
+    pub team: Option<u8>,
+}
+
+/// The combined standing of the members of a team.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TeamEntry {
+    pub team: u8,
+    pub score: usize,
+    pub kills: usize,
+    pub members: usize,
+}
+
+/// The phases of a timed match. The world only moves during the round.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchPhase {
+    /// Counting down to the round; players can already start.
+    Lobby,
+    Round,
+    /// The world is frozen and the final standings are shown.
+    Results,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MatchStatus {
+    pub phase: MatchPhase,
+    pub seconds_left: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct KillFeedEntry {
+    pub victim: String,
+    pub killer: Option<String>,
+    pub cause: DeathCause,
+}
+
+/// Sent once when a client connects.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SessionInfo {
+    #[serde(rename = "i")]
+    pub id: Uuid,
+    #[serde(rename = "n")]
+    pub name: String,
+    /// The room the client plays in.
+    #[serde(rename = "r")]
+    pub room: String,
+    #[serde(rename = "z")]
+    pub field_size: f32,
+}
+
+/// Every binary WebSocket message the server sends.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ServerMessage {
+    #[serde(rename = "v")]
+    View(ViewPacket),
+    #[serde(rename = "q")]
+    QuantizedView(QuantizedPacket),
+    #[serde(rename = "m")]
+    Map(Map),
+    #[serde(rename = "p")]
+    MapSelf(MapSelf),
+    #[serde(rename = "l")]
+    Leaderboard(Vec<RankingEntry>),
+    #[serde(rename = "k")]
+    KillFeed(Vec<KillFeedEntry>),
+    /// Only sent in team mode, best team first.
+    #[serde(rename = "t")]
+    TeamLeaderboard(Vec<TeamEntry>),
+    /// Sent in battle-royale mode when a match ends, with the name of the
+    /// last snake alive, or `None` if nobody survived.
+    #[serde(rename = "w")]
+    MatchOver(Option<String>),
+    /// Only sent when the server runs timed matches: on every phase change
+    /// and once a second.
+    #[serde(rename = "c")]
+    MatchStatus(MatchStatus),
+    /// The final standings of a timed round, sent when it ends.
+    #[serde(rename = "f")]
+    MatchResults(Vec<RankingEntry>),
+    /// Where a feast was just laid out.
+    #[serde(rename = "x")]
+    Feast(Coordinate),
+    #[serde(rename = "s")]
+    Session(SessionInfo),
+    #[serde(rename = "e")]
+    Error(String),
+}
+
+#[derive(Debug)]
+pub enum MessageError {
+    /// The message was written for another protocol version; the client has
+    /// to be reloaded to talk to this server.
+    UnsupportedVersion(u16),
+    Malformed(CiboriumError<Error>),
+}
+
+impl fmt::Display for MessageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MessageError::UnsupportedVersion(version) => {
+                write!(f, "unsupported protocol version {version}")
+            }
+            MessageError::Malformed(error) => write!(f, "malformed message: {error}"),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Envelope<'a> {
+    #[serde(rename = "v")]
+    version: u16,
+    #[serde(rename = "m")]
+    message: &'a ServerMessage,
+}
+
+#[derive(Deserialize)]
+struct EnvelopeHeader {
+    #[serde(rename = "v")]
+    version: u16,
+}
+
+#[derive(Deserialize)]
+struct EnvelopeBody {
+    #[serde(rename = "m")]
+    message: ServerMessage,
+}
+
+impl ServerMessage {
+    pub fn view(packet: &ViewPacket, format: WireFormat) -> ServerMessage {
+        match format {
+            WireFormat::Float => ServerMessage::View(packet.clone()),
+            WireFormat::Quantized => ServerMessage::QuantizedView(QuantizedPacket::new(packet)),
+        }
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<ServerMessage, MessageError> {
+        // Check the version first: a message of another version, or of a kind
+        // added later, need not decode as any of the kinds above.
+        let header: EnvelopeHeader = from_reader(bytes).map_err(MessageError::Malformed)?;
+        if header.version != PROTOCOL_VERSION {
+            return Err(MessageError::UnsupportedVersion(header.version));
+        }
+        from_reader(bytes)
+            .map(|body: EnvelopeBody| body.message)
+            .map_err(MessageError::Malformed)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        let envelope = Envelope {
+            version: PROTOCOL_VERSION,
+            message: self,
+        };
+        into_writer(&envelope, &mut bytes).unwrap();
+        bytes
+    }
+}
+
+/// Everything a client can ask of the server.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ClientCommand {
+    #[serde(rename = "s")]
+    Start(Uuid),
+    #[serde(rename = "a")]
+    Accelerate,
+    #[serde(rename = "v")]
+    Velocity(Coordinate),
+    #[serde(rename = "w")]
+    WindowSize { width: u16, height: u16 },
+    #[serde(rename = "k")]
+    Acknowledge(u32),
+    #[serde(rename = "f")]
+    WireFormat(WireFormat),
+    /// Join the party with the given id, or leave the current one. Members
+    /// spawn next to each other; a new party is just a fresh id.
+    #[serde(rename = "p")]
+    Party(Option<Uuid>),
+}
+
+/// A binary WebSocket message from a client.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ClientMessage {
+    /// Increases with every message a client sends, so that the server can
+    /// drop inputs that arrive twice or out of order.
+    #[serde(rename = "q")]
+    pub sequence: u32,
+    #[serde(rename = "c")]
+    pub command: ClientCommand,
+}
+
+impl ClientCommand {
+    fn is_valid(&self) -> bool {
+        match self {
+            ClientCommand::Velocity(velocity) => velocity.x.is_finite() && velocity.y.is_finite(),
+            _ => true,
+        }
+    }
+}
+
+impl FromStr for ClientCommand {
+    type Err = ();
+
+    fn from_str(message: &str) -> Result<Self, Self::Err> {
+        //! Parse the text form of a command, e.g. `v 1 -0.5` or `w 1920 1080`,
+        //! which is kept for debugging from a browser console.
+
+        let mut parts = message.split_whitespace();
+        let mut next = || parts.next().ok_or(());
+        let command = match next()? {
+            "s" => ClientCommand::Start(next()?.parse().map_err(|_| ())?),
+            "a" => ClientCommand::Accelerate,
+            "v" => ClientCommand::Velocity(Coordinate {
+                x: next()?.parse().map_err(|_| ())?,
+                y: next()?.parse().map_err(|_| ())?,
+            }),
+            "w" => ClientCommand::WindowSize {
+                width: next()?.parse().map_err(|_| ())?,
+                height: next()?.parse().map_err(|_| ())?,
+            },
+            "k" => ClientCommand::Acknowledge(next()?.parse().map_err(|_| ())?),
+            "f" => ClientCommand::WireFormat(next()?.parse()?),
+            "p" => ClientCommand::Party(next().ok().map(str::parse).transpose().map_err(|_| ())?),
+            _ => return Err(()),
+        };
+
+        if next().is_ok() || !command.is_valid() {
+            return Err(());
+        }
+        Ok(command)
+    }
+}
+
+impl ClientMessage {
+    pub fn from_bytes(bytes: &[u8]) -> Result<ClientMessage, CiboriumError<Error>> {
+        let message: ClientMessage = from_reader(bytes)?;
+        if !message.command.is_valid() {
+            return Err(CiboriumError::semantic(
+                None,
+                "velocity must be finite".to_owned(),
+            ));
+        }
+        Ok(message)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        into_writer(&self, &mut bytes).unwrap();
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_round_trip_with_their_tag() {
+        let messages = [
+            ServerMessage::Map(Map {
+                size: 2,
+                pellets: vec![0, 1, 2, 3],
+                snakes: vec![1, 0, 0, 0],
+                obstacles: vec![0, 0, 0, 1],
+                leaders: vec![(0, 0)],
+            }),
+            ServerMessage::MapSelf(MapSelf {
+                position: (1, 0),
+                body: vec![(0, 0), (1, 0)],
+            }),
+            ServerMessage::KillFeed(vec![KillFeedEntry {
+                victim: "Alice".to_owned(),
+                killer: None,
+                cause: DeathCause::Body,
+            }]),
+            ServerMessage::Session(SessionInfo {
+                id: Uuid::from_u128(7),
+                name: "Player-0000".to_owned(),
+                room: "public-1".to_owned(),
+                field_size: 10000.0,
+            }),
+            ServerMessage::TeamLeaderboard(vec![TeamEntry {
+                team: 1,
+                score: 120,
+                kills: 3,
+                members: 4,
+            }]),
+            ServerMessage::MatchOver(Some("Alice".to_owned())),
+            ServerMessage::MatchStatus(MatchStatus {
+                phase: MatchPhase::Lobby,
+                seconds_left: 5,
+            }),
+            ServerMessage::MatchResults(vec![RankingEntry {
+                name: "Alice".to_owned(),
+                score: 40,
+                kills: 1,
+                is_bot: false,
+                rank: 1,
+                is_self: true,
+                team: None,
+            }]),
+            ServerMessage::Error("unknown command".to_owned()),
+        ];
+
+        for message in messages {
+            assert_eq!(
+                ServerMessage::from_bytes(&message.to_bytes()).unwrap(),
+                message
+            );
+        }
+    }
+
+    #[test]
+    fn other_protocol_versions_are_reported_as_such() {
+        let message = ServerMessage::Error("reload".to_owned());
+        let mut bytes = vec![];
+        into_writer(
+            &Envelope {
+                version: PROTOCOL_VERSION + 1,
+                message: &message,
+            },
+            &mut bytes,
+        )
+        .unwrap();
+
+        assert!(matches!(
+            ServerMessage::from_bytes(&bytes),
+            Err(MessageError::UnsupportedVersion(version)) if version == PROTOCOL_VERSION + 1
+        ));
+        assert!(matches!(
+            ServerMessage::from_bytes(&[0xff]),
+            Err(MessageError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn parses_the_text_form_of_commands() {
+        let player_token = Uuid::new_v4();
+        assert_eq!(
+            format!("s {player_token}").parse(),
+            Ok(ClientCommand::Start(player_token))
+        );
+        assert_eq!(
+            "v 1 -0.5".parse(),
+            Ok(ClientCommand::Velocity(Coordinate { x: 1.0, y: -0.5 }))
+        );
+        assert_eq!("k 42".parse(), Ok(ClientCommand::Acknowledge(42)));
+        assert_eq!(
+            format!("p {player_token}").parse(),
+            Ok(ClientCommand::Party(Some(player_token)))
+        );
+        assert_eq!("p".parse(), Ok(ClientCommand::Party(None)));
+        assert_eq!(
+            "f quantized".parse(),
+            Ok(ClientCommand::WireFormat(WireFormat::Quantized))
+        );
+        assert_eq!(
+            "w 1920 1080".parse(),
+            Ok(ClientCommand::WindowSize {
+                width: 1920,
+                height: 1080,
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_or_non_finite_text_commands() {
+        for message in [
+            "",
+            "v 1",
+            "v NaN 1",
+            "w large 1080",
+            "s",
+            "k -1",
+            "f half",
+            "p friends",
+            "s Alice",
+            "status",
+            "a a",
+        ] {
+            assert_eq!(message.parse::<ClientCommand>(), Err(()), "{message}");
+        }
+    }
+
+    #[test]
+    fn binary_client_messages_round_trip_and_are_validated() {
+        let message = ClientMessage {
+            sequence: 3,
+            command: ClientCommand::WindowSize {
+                width: 1920,
+                height: 1080,
+            },
+        };
+        assert_eq!(
+            ClientMessage::from_bytes(&message.to_bytes()).unwrap(),
+            message
+        );
+
+        let message = ClientMessage {
+            sequence: 4,
+            command: ClientCommand::Velocity(Coordinate {
+                x: f32::INFINITY,
+                y: 0.0,
+            }),
+        };
+        assert!(ClientMessage::from_bytes(&message.to_bytes()).is_err());
+    }
+}
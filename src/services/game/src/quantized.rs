@@ -0,0 +1,514 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::str::FromStr;
+
+use super::coordinate::Coordinate;
+use super::level::Obstacle;
+use super::pellet::PelletKind;
+use super::power_up::PowerUps;
+use super::view_stream::{
+    SnakeDiff, SnakeUpdate, ViewDelta, ViewPacket, ViewPellet, ViewSnake, ViewState,
+};
+use super::zone::Zone;
+
+/// Fixed-point steps per pixel.
+const SCALE: f32 = 16.0;
+/// Pellet radius steps per pixel. Radii are below 5 pixels.
+const RADIUS_SCALE: f32 = 32.0;
+
+/// How `ViewPacket`s are encoded for a session.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WireFormat {
+    /// Every coordinate as a pair of `f32`s.
+    #[default]
+    Float,
+    /// Coordinates in fixed point, bodies as offsets between consecutive
+    /// points, and records as arrays instead of maps.
+    Quantized,
+}
+
+impl FromStr for WireFormat {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "float" => Ok(WireFormat::Float),
+            "quantized" => Ok(WireFormat::Quantized),
+            _ => Err(()),
+        }
+    }
+}
+
+/// A world coordinate in 1/`SCALE` pixel steps. Quantizing each point on its
+/// own keeps deltas consistent: a point has the same value in every packet.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+struct Fixed(i32, i32);
+
+/// A run of points: the first point, then the offsets to each following one
+/// as a flat `[dx, dy, ...]` list. Offsets are small and CBOR encodes small
+/// integers in one or two bytes.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct Path(Option<Fixed>, Vec<i32>);
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum QuantizedPacket {
+    #[serde(rename = "K")]
+    Keyframe(u32, QuantizedState),
+    #[serde(rename = "D")]
+    Delta(QuantizedDelta),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct QuantizedState(
+    u32,
+    f32,
+    bool,
+    Fixed,
+    f32,
+    f32,
+    Vec<QuantizedSnake>,
+    Vec<QuantizedPellet>,
+    Option<Zone>,
+    Vec<Obstacle>,
+);
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct QuantizedSnake(
+    u16,
+    Path,
+    bool,
+    u32,
+    u32,
+    QuantizedPowerUps,
+    String,
+    Coordinate,
+    usize,
+    bool,
+);
+
+/// `PowerUps` as an array: magnet, shield, speed, ghost.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+struct QuantizedPowerUps(u32, u32, u32, u32);
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct QuantizedPellet(u16, Fixed, u8, u8, String, PelletKind, u32, Option<u32>);
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct QuantizedDelta(
+    u32,
+    u32,
+    u32,
+    f32,
+    bool,
+    Fixed,
+    f32,
+    f32,
+    Vec<u16>,
+    Vec<QuantizedSnakeUpdate>,
+    Vec<QuantizedPellet>,
+    Vec<u16>,
+    Option<Zone>,
+    Option<Vec<Obstacle>>,
+);
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+enum QuantizedSnakeUpdate {
+    #[serde(rename = "s")]
+    Full(QuantizedSnake),
+    #[serde(rename = "d")]
+    Diff(
+        u16,
+        Path,
+        usize,
+        usize,
+        Path,
+        u32,
+        u32,
+        QuantizedPowerUps,
+        Coordinate,
+        usize,
+    ),
+}
+
+impl Fixed {
+    fn new(coordinate: &Coordinate) -> Self {
+        Fixed(quantize(coordinate.x, SCALE), quantize(coordinate.y, SCALE))
+    }
+
+    fn coordinate(self) -> Coordinate {
+        Coordinate {
+            x: self.0 as f32 / SCALE,
+            y: self.1 as f32 / SCALE,
+        }
+    }
+}
+
+impl QuantizedPowerUps {
+    fn new(power_ups: &PowerUps) -> Self {
+        QuantizedPowerUps(
+            power_ups.magnet,
+            power_ups.shield,
+            power_ups.speed,
+            power_ups.ghost,
+        )
+    }
+
+    fn power_ups(self) -> PowerUps {
+        let QuantizedPowerUps(magnet, shield, speed, ghost) = self;
+        PowerUps {
+            magnet,
+            shield,
+            speed,
+            ghost,
+        }
+    }
+}
+
+impl Path {
+    fn new<'a>(points: impl IntoIterator<Item = &'a Coordinate>) -> Self {
+        let mut points = points.into_iter().map(Fixed::new);
+        let Some(first) = points.next() else {
+            return Path(None, Vec::new());
+        };
+        let mut previous = first;
+        let mut offsets = Vec::new();
+        for point in points {
+            offsets.push(point.0.wrapping_sub(previous.0));
+            offsets.push(point.1.wrapping_sub(previous.1));
+            previous = point;
+        }
+        Path(Some(first), offsets)
+    }
+
+    fn points<T: FromIterator<Coordinate>>(&self) -> T {
+        let Some(first) = self.0 else {
+            return std::iter::empty().collect();
+        };
+        let offsets = self.1.chunks_exact(2).scan(first, |point, offset| {
+            *point = Fixed(
+                point.0.wrapping_add(offset[0]),
+                point.1.wrapping_add(offset[1]),
+            );
+            Some(*point)
+        });
+        std::iter::once(first)
+            .chain(offsets)
+            .map(Fixed::coordinate)
+            .collect()
+    }
+}
+
+fn quantize(value: f32, scale: f32) -> i32 {
+    (value * scale).round() as i32
+}
+
+impl QuantizedSnake {
+    fn new(snake: &ViewSnake) -> Self {
+        QuantizedSnake(
+            snake.handle,
+            Path::new(&snake.bodies),
+            snake.with_head,
+            snake.acceleration_time_left,
+            snake.protection_time_left,
+            QuantizedPowerUps::new(&snake.power_ups),
+            snake.color.clone(),
+            snake.velocity,
+            snake.size,
+            snake.is_self,
+        )
+    }
+
+    fn snake(&self) -> ViewSnake {
+        let QuantizedSnake(
+            handle,
+            bodies,
+            with_head,
+            acceleration_time_left,
+            protection_time_left,
+            power_ups,
+            color,
+            velocity,
+            size,
+            is_self,
+        ) = self;
+        ViewSnake {
+            handle: *handle,
+            bodies: bodies.points::<VecDeque<_>>(),
+            with_head: *with_head,
+            acceleration_time_left: *acceleration_time_left,
+            protection_time_left: *protection_time_left,
+            power_ups: power_ups.power_ups(),
+            color: color.clone(),
+            velocity: *velocity,
+            size: *size,
+            is_self: *is_self,
+        }
+    }
+}
+
+impl QuantizedPellet {
+    fn new(pellet: &ViewPellet) -> Self {
+        QuantizedPellet(
+            pellet.handle,
+            Fixed::new(&pellet.center),
+            quantize(pellet.radius, RADIUS_SCALE).clamp(0, u8::MAX as i32) as u8,
+            pellet.size,
+            pellet.color.clone(),
+            pellet.kind,
+            pellet.born,
+            pellet.decay,
+        )
+    }
+
+    fn pellet(&self) -> ViewPellet {
+        let QuantizedPellet(handle, center, radius, size, color, kind, born, decay) = self;
+        ViewPellet {
+            handle: *handle,
+            center: center.coordinate(),
+            radius: *radius as f32 / RADIUS_SCALE,
+            size: *size,
+            color: color.clone(),
+            kind: *kind,
+            born: *born,
+            decay: *decay,
+        }
+    }
+}
+
+impl QuantizedPacket {
+    pub fn new(packet: &ViewPacket) -> Self {
+        match packet {
+            ViewPacket::Keyframe { sequence, state } => QuantizedPacket::Keyframe(
+                *sequence,
+                QuantizedState(
+                    state.frame,
+                    state.field_size,
+                    state.is_alive,
+                    Fixed::new(&state.origin),
+                    state.width,
+                    state.height,
+                    state.snakes.iter().map(QuantizedSnake::new).collect(),
+                    state.pellets.iter().map(QuantizedPellet::new).collect(),
+                    state.zone,
+                    state.obstacles.clone(),
+                ),
+            ),
+            ViewPacket::Delta(delta) => QuantizedPacket::Delta(QuantizedDelta(
+                delta.sequence,
+                delta.base,
+                delta.frame,
+                delta.field_size,
+                delta.is_alive,
+                Fixed::new(&delta.origin),
+                delta.width,
+                delta.height,
+                delta.order.clone(),
+                delta
+                    .snakes
+                    .iter()
+                    .map(|update| match update {
+                        SnakeUpdate::Full(snake) => {
+                            QuantizedSnakeUpdate::Full(QuantizedSnake::new(snake))
+                        }
+                        SnakeUpdate::Diff(diff) => QuantizedSnakeUpdate::Diff(
+                            diff.handle,
+                            Path::new(&diff.heads),
+                            diff.skip,
+                            diff.pop,
+                            Path::new(&diff.tail),
+                            diff.acceleration_time_left,
+                            diff.protection_time_left,
+                            QuantizedPowerUps::new(&diff.power_ups),
+                            diff.velocity,
+                            diff.size,
+                        ),
+                    })
+                    .collect(),
+                delta.pellets.iter().map(QuantizedPellet::new).collect(),
+                delta.removed_pellets.clone(),
+                delta.zone,
+                delta.obstacles.clone(),
+            )),
+        }
+    }
+
+    pub fn packet(&self) -> ViewPacket {
+        match self {
+            QuantizedPacket::Keyframe(sequence, state) => {
+                let QuantizedState(
+                    frame,
+                    field_size,
+                    is_alive,
+                    origin,
+                    width,
+                    height,
+                    snakes,
+                    pellets,
+                    zone,
+                    obstacles,
+                ) = state;
+                ViewPacket::Keyframe {
+                    sequence: *sequence,
+                    state: ViewState {
+                        frame: *frame,
+                        field_size: *field_size,
+                        is_alive: *is_alive,
+                        origin: origin.coordinate(),
+                        width: *width,
+                        height: *height,
+                        snakes: snakes.iter().map(QuantizedSnake::snake).collect(),
+                        pellets: pellets.iter().map(QuantizedPellet::pellet).collect(),
+                        zone: *zone,
+                        obstacles: obstacles.clone(),
+                    },
+                }
+            }
+            QuantizedPacket::Delta(QuantizedDelta(
+                sequence,
+                base,
+                frame,
+                field_size,
+                is_alive,
+                origin,
+                width,
+                height,
+                order,
+                snakes,
+                pellets,
+                removed_pellets,
+                zone,
+                obstacles,
+            )) => ViewPacket::Delta(ViewDelta {
+                sequence: *sequence,
+                base: *base,
+                frame: *frame,
+                field_size: *field_size,
+                is_alive: *is_alive,
+                origin: origin.coordinate(),
+                width: *width,
+                height: *height,
+                order: order.clone(),
+                snakes: snakes
+                    .iter()
+                    .map(|update| match update {
+                        QuantizedSnakeUpdate::Full(snake) => SnakeUpdate::Full(snake.snake()),
+                        QuantizedSnakeUpdate::Diff(
+                            handle,
+                            heads,
+                            skip,
+                            pop,
+                            tail,
+                            acceleration_time_left,
+                            protection_time_left,
+                            power_ups,
+                            velocity,
+                            size,
+                        ) => SnakeUpdate::Diff(SnakeDiff {
+                            handle: *handle,
+                            heads: heads.points(),
+                            skip: *skip,
+                            pop: *pop,
+                            tail: tail.points(),
+                            acceleration_time_left: *acceleration_time_left,
+                            protection_time_left: *protection_time_left,
+                            power_ups: power_ups.power_ups(),
+                            velocity: *velocity,
+                            size: *size,
+                        }),
+                    })
+                    .collect(),
+                pellets: pellets.iter().map(QuantizedPellet::pellet).collect(),
+                removed_pellets: removed_pellets.clone(),
+                zone: *zone,
+                obstacles: obstacles.clone(),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::GameConfig;
+    use crate::engine::GameEngine;
+    use crate::view::EntityHandles;
+    use crate::view_stream::ViewEncoder;
+    use uuid::Uuid;
+
+    fn crowded_view() -> (GameEngine, Uuid) {
+        let viewer = Uuid::from_u128(1);
+        let mut engine = GameEngine::with_seed(GameConfig::default(), 4);
+        engine.add_snake_at(viewer, Coordinate { x: 500.0, y: 500.0 });
+        engine.add_snake_at(Uuid::from_u128(2), Coordinate { x: 900.0, y: 700.0 });
+        for _ in 0..60 {
+            engine.change_velocity(&viewer, Coordinate { x: 1.0, y: 0.3 });
+            engine.forward();
+        }
+        (engine, viewer)
+    }
+
+    #[test]
+    fn paths_round_trip_to_the_nearest_step() {
+        let points = [
+            Coordinate {
+                x: 9999.97,
+                y: 0.01,
+            },
+            Coordinate { x: 3.3, y: 5.55 },
+            Coordinate { x: -1.0, y: 5.0 },
+        ];
+
+        let decoded: Vec<Coordinate> = Path::new(&points).points();
+
+        assert_eq!(decoded.len(), points.len());
+        for (decoded, original) in decoded.iter().zip(points.iter()) {
+            assert!((decoded.x - original.x).abs() <= 0.5 / SCALE);
+            assert!((decoded.y - original.y).abs() <= 0.5 / SCALE);
+        }
+        assert_eq!(Path::new(&decoded), Path::new(&points));
+        assert!(Path::new(&[]).points::<Vec<_>>().is_empty());
+    }
+
+    #[test]
+    fn quantized_packets_round_trip() {
+        let (engine, viewer) = crowded_view();
+        let head = *engine.get_snake(&viewer).unwrap().get_head();
+        let state = engine.view_state(
+            &viewer,
+            head.x,
+            head.y,
+            1920.0,
+            1080.0,
+            &mut EntityHandles::default(),
+        );
+        let mut encoder = ViewEncoder::default();
+        let keyframe = encoder.encode(state.clone());
+        encoder.acknowledge(keyframe.sequence());
+
+        for packet in [keyframe, encoder.encode(state)] {
+            let bytes = packet.to_bytes_as(WireFormat::Quantized);
+            let decoded = ViewPacket::from_bytes(&bytes).unwrap();
+
+            assert_eq!(decoded.sequence(), packet.sequence());
+            assert_eq!(decoded.to_bytes_as(WireFormat::Quantized), bytes);
+        }
+    }
+
+    #[test]
+    fn quantized_keyframes_are_much_smaller_than_a_full_view() {
+        let (engine, viewer) = crowded_view();
+        let head = *engine.get_snake(&viewer).unwrap().get_head();
+        let mut handles = EntityHandles::default();
+        let view = engine.view(&viewer, head.x, head.y, 1920.0, 1080.0, &mut handles);
+        let state = engine.view_state(&viewer, head.x, head.y, 1920.0, 1080.0, &mut handles);
+        let keyframe = ViewEncoder::default().encode(state);
+
+        let full = view.to_bytes().len();
+        let float = keyframe.to_bytes_as(WireFormat::Float).len();
+        let quantized = keyframe.to_bytes_as(WireFormat::Quantized).len();
+
+        assert!(quantized * 5 < full * 3, "{quantized} vs {full}");
+        assert!(quantized * 5 < float * 3, "{quantized} vs {float}");
+    }
+}
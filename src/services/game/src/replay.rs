@@ -0,0 +1,233 @@
+use ciborium::{
+    de::{from_reader, Error as CiboriumError},
+    ser::into_writer,
+};
+use serde::{Deserialize, Serialize};
+use std::io::Error;
+use uuid::Uuid;
+
+use super::coordinate::Coordinate;
+use super::engine::{FrameEvents, GameEngine};
+use super::snapshot::WorldSnapshot;
+
+/// Bumped whenever the layout of `Replay` changes incompatibly.
+pub const REPLAY_VERSION: u32 = 1;
+
+/// An external input to the engine. Everything else the engine does is
+/// derived from its seeded random number generator, so the initial world
+/// plus the inputs reproduce a run exactly.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum Input {
+    #[serde(rename = "s")]
+    AddSnake(Uuid),
+    #[serde(rename = "p")]
+    AddSnakeAt(Uuid, Coordinate),
+    /// A snake spawned next to the second one.
+    #[serde(rename = "n")]
+    AddSnakeNear(Uuid, Uuid),
+    #[serde(rename = "v")]
+    ChangeVelocity(Uuid, Coordinate),
+    /// A target velocity set verbatim by the bot helpers.
+    #[serde(rename = "t")]
+    TargetVelocity(Uuid, Coordinate),
+    #[serde(rename = "a")]
+    Accelerate(Uuid),
+    #[serde(rename = "r")]
+    RemoveSnake(Uuid),
+    #[serde(rename = "g")]
+    SetTeam(Uuid, Option<u8>),
+    #[serde(rename = "z")]
+    RestartZone,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RecordedInput {
+    /// `frame_count` of the engine when the input arrived. The input is
+    /// applied before the `forward()` that advances past this frame.
+    #[serde(rename = "f")]
+    pub frame: u32,
+    #[serde(rename = "i")]
+    pub input: Input,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Replay {
+    pub version: u32,
+    /// The world when the recording started. For a fresh engine this is
+    /// little more than the config and the seed.
+    pub start: WorldSnapshot,
+    pub inputs: Vec<RecordedInput>,
+    /// The frame the recording stopped at.
+    pub end_frame: u32,
+}
+
+#[derive(Deserialize)]
+struct ReplayHeader {
+    version: u32,
+}
+
+impl Replay {
+    pub fn new(start: WorldSnapshot) -> Replay {
+        Replay {
+            version: REPLAY_VERSION,
+            end_frame: start.frame_count,
+            start,
+            inputs: Vec::new(),
+        }
+    }
+
+    pub fn start_frame(&self) -> u32 {
+        self.start.frame_count
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Replay, CiboriumError<Error>> {
+        let header: ReplayHeader = from_reader(bytes)?;
+        if header.version != REPLAY_VERSION {
+            return Err(CiboriumError::semantic(
+                None,
+                format!("unsupported replay version {}", header.version),
+            ));
+        }
+        from_reader(bytes)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        into_writer(&self, &mut bytes).unwrap();
+        bytes
+    }
+
+    pub fn player(&self) -> ReplayPlayer<'_> {
+        ReplayPlayer {
+            engine: GameEngine::restore(self.start.clone()),
+            inputs: &self.inputs,
+            next_input: 0,
+        }
+    }
+}
+
+/// Re-runs a `Replay` one frame at a time.
+pub struct ReplayPlayer<'a> {
+    engine: GameEngine,
+    inputs: &'a [RecordedInput],
+    next_input: usize,
+}
+
+impl ReplayPlayer<'_> {
+    pub fn engine(&self) -> &GameEngine {
+        &self.engine
+    }
+
+    pub fn frame(&self) -> u32 {
+        self.engine.frame_count
+    }
+
+    pub fn step(&mut self) -> FrameEvents {
+        //! Apply the inputs recorded at the current frame and advance by one.
+
+        while let Some(recorded) = self.inputs.get(self.next_input) {
+            if recorded.frame != self.engine.frame_count {
+                break;
+            }
+            self.engine.apply_input(&recorded.input);
+            self.next_input += 1;
+        }
+        self.engine.forward()
+    }
+}
+
+impl GameEngine {
+    pub fn start_recording(&mut self) {
+        //! Start recording every external input. Inputs made through
+        //! `get_snake_mut` bypass the recorder and are not reproducible.
+
+        self.recording = Some(Replay::new(self.snapshot()));
+    }
+
+    pub fn stop_recording(&mut self) -> Option<Replay> {
+        let mut replay = self.recording.take()?;
+        replay.end_frame = self.frame_count;
+        Some(replay)
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    pub(crate) fn record(&mut self, input: Input) {
+        if let Some(replay) = self.recording.as_mut() {
+            replay.inputs.push(RecordedInput {
+                frame: self.frame_count,
+                input,
+            });
+        }
+    }
+
+    pub fn apply_input(&mut self, input: &Input) {
+        match *input {
+            Input::AddSnake(id) => self.add_snake(id),
+            Input::AddSnakeAt(id, position) => self.add_snake_at(id, position),
+            Input::AddSnakeNear(id, friend) => self.add_snake_near(id, &friend),
+            Input::ChangeVelocity(id, velocity) => self.change_velocity(&id, velocity),
+            Input::TargetVelocity(id, velocity) => self.set_target_velocity(&id, velocity),
+            Input::Accelerate(id) => self.accelerate(&id),
+            Input::SetTeam(id, team) => self.set_team(id, team),
+            Input::RestartZone => self.restart_zone(),
+            Input::RemoveSnake(id) => {
+                self.remove_snake(&id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::GameConfig;
+
+    #[test]
+    fn replaying_the_inputs_reproduces_the_run() {
+        let player = Uuid::from_u128(1);
+        let bot = Uuid::from_u128(2);
+        let mut engine = GameEngine::with_seed(GameConfig::default(), 3);
+        engine.add_snake(bot);
+        engine.forward();
+        engine.start_recording();
+        engine.add_snake_at(player, Coordinate { x: 500.0, y: 500.0 });
+        for frame in 0..90 {
+            engine.change_velocity(&player, Coordinate { x: 1.0, y: 0.5 });
+            if let Some(observation) = engine.bot_observation(&bot, None) {
+                engine.apply_bot_action(&bot, observation, frame % 7);
+            }
+            engine.accelerate(&player);
+            engine.forward();
+        }
+        let replay = Replay::from_bytes(&engine.stop_recording().unwrap().to_bytes()).unwrap();
+
+        let mut replayer = replay.player();
+        while replayer.frame() < replay.end_frame {
+            replayer.step();
+        }
+
+        assert_eq!(replayer.engine().snapshot(), engine.snapshot());
+    }
+
+    #[test]
+    fn deaths_inside_forward_are_not_recorded_as_inputs() {
+        let first = Uuid::from_u128(1);
+        let second = Uuid::from_u128(2);
+        let config = GameConfig {
+            spawn_protection_frames: 0,
+            ..GameConfig::default()
+        };
+        let mut engine = GameEngine::with_seed(config, 3);
+        engine.start_recording();
+        engine.add_snake_at(first, Coordinate { x: 100.0, y: 100.0 });
+        engine.add_snake_at(second, Coordinate { x: 100.0, y: 100.0 });
+
+        let events = engine.forward();
+
+        assert_eq!(events.deaths.len(), 1);
+        assert_eq!(engine.stop_recording().unwrap().inputs.len(), 2);
+    }
+}
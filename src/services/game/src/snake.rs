@@ -3,6 +3,8 @@ use serde::{Deserialize, Serialize};
 use std::collections::VecDeque;
 
 use super::coordinate::Coordinate;
+use super::engine::GameRng;
+use super::power_up::PowerUps;
 
 const COLORS: [&str; 7] = [
     "8",   // red
@@ -15,6 +17,8 @@ const COLORS: [&str; 7] = [
 ];
 const MAX_TURN_ANGLE: f32 = 6.0 * std::f32::consts::PI / 180.0;
 const BASE_SNAKE_SIZE: f32 = 15.0;
+/// Number of body points of a freshly spawned snake.
+pub(crate) const SPAWN_LENGTH: usize = 10;
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Snake {
@@ -22,6 +26,11 @@ pub struct Snake {
     pub bodies: VecDeque<Coordinate>, // head, ..., tail
     #[serde(rename = "a")]
     pub acceleration_time_left: u32,
+    /// Frames left of spawn protection.
+    #[serde(rename = "g")]
+    pub protection_time_left: u32,
+    #[serde(rename = "u")]
+    pub power_ups: PowerUps,
     #[serde(skip)]
     pub speed: f32,
     #[serde(rename = "c")]
@@ -36,24 +45,48 @@ pub struct Snake {
     pub frame_count_offset: u32,
     #[serde(rename = "h")]
     pub is_visible_head: bool, // for rendering
+    #[serde(rename = "i")]
+    pub handle: u16, // for rendering, see `EntityHandles`
+    #[serde(rename = "m")]
+    pub is_self: bool, // for rendering
+    #[serde(skip)]
+    pub team: Option<u8>,
+    /// The color the snake spawned with, worn again when it leaves its team.
+    #[serde(skip)]
+    pub own_color: String,
 }
 
 impl Snake {
-    pub fn new(initial_position: Coordinate, initial_speed: f32) -> Snake {
+    pub fn new(initial_position: Coordinate, initial_speed: f32, rng: &mut GameRng) -> Snake {
+        let color = COLORS[rng.random_range(0..COLORS.len())].to_string();
+        Self::new_with_color(initial_position, initial_speed, color)
+    }
+
+    pub fn new_with_color(
+        initial_position: Coordinate,
+        initial_speed: f32,
+        color: String,
+    ) -> Snake {
         let mut bodies = VecDeque::new();
-        for _ in 0..10 {
+        for _ in 0..SPAWN_LENGTH {
             bodies.push_back(initial_position);
         }
         Snake {
             bodies,
             acceleration_time_left: 0,
+            protection_time_left: 0,
+            power_ups: PowerUps::default(),
             speed: initial_speed,
             size: 15,
-            color: COLORS[rand::rng().random_range(0..COLORS.len())].to_string(),
+            own_color: color.clone(),
+            color,
             velocity: Coordinate { x: 0., y: 0. },
             target_velocity: Coordinate { x: 0., y: 0. },
             frame_count_offset: 0,
             is_visible_head: true,
+            handle: 0,
+            is_self: false,
+            team: None,
         }
     }
 
@@ -65,11 +98,11 @@ impl Snake {
         &self.bodies[self.bodies.len() - 1]
     }
 
-    pub fn accelerate(&mut self) {
+    pub fn accelerate(&mut self, duration: u32) {
         if self.bodies.len() < 20 {
             return;
         }
-        self.acceleration_time_left = 60;
+        self.acceleration_time_left = duration;
     }
 
     pub fn turn_towards_target(&mut self) {
@@ -106,7 +139,7 @@ mod tests {
 
     #[test]
     fn limits_turning_to_six_degrees_per_frame() {
-        let mut snake = Snake::new(Coordinate::default(), 5.0);
+        let mut snake = Snake::new_with_color(Coordinate::default(), 5.0, "120".to_string());
         snake.velocity = Coordinate { x: 1.0, y: 0.0 };
         snake.target_velocity = Coordinate { x: 0.0, y: 1.0 };
 
@@ -118,7 +151,7 @@ mod tests {
 
     #[test]
     fn takes_the_short_path_across_the_angle_boundary() {
-        let mut snake = Snake::new(Coordinate::default(), 5.0);
+        let mut snake = Snake::new_with_color(Coordinate::default(), 5.0, "120".to_string());
         let current_angle = 179.0_f32.to_radians();
         let target_angle = -179.0_f32.to_radians();
         snake.velocity = Coordinate {
@@ -141,7 +174,7 @@ mod tests {
 
     #[test]
     fn larger_snakes_turn_more_slowly() {
-        let mut small = Snake::new(Coordinate::default(), 5.0);
+        let mut small = Snake::new_with_color(Coordinate::default(), 5.0, "120".to_string());
         small.velocity = Coordinate { x: 1.0, y: 0.0 };
         small.target_velocity = Coordinate { x: 0.0, y: 1.0 };
         let mut large = small.clone();
@@ -0,0 +1,315 @@
+use ciborium::{
+    de::{from_reader, Error as CiboriumError},
+    ser::into_writer,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::io::Error;
+use uuid::Uuid;
+
+use super::config::GameConfig;
+use super::coordinate::Coordinate;
+use super::engine::{EntityMap, GameEngine, GameRng};
+use super::pellet::{Pellet, PelletKind};
+use super::power_up::PowerUps;
+use super::snake::Snake;
+
+/// Bumped whenever the layout of `WorldSnapshot` changes incompatibly.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// The complete state of a `GameEngine`.
+///
+/// Unlike `View`, which only carries what a client needs for rendering, a
+/// snapshot keeps every field so that a restored engine continues exactly
+/// where the original one stopped.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct WorldSnapshot {
+    pub version: u32,
+    pub config: GameConfig,
+    pub seed: u64,
+    pub rng: GameRng,
+    pub frame_count: u32,
+    pub snakes: Vec<SnakeState>,
+    /// Pellets in the order of the pellet grid cells, so that restoring
+    /// rebuilds an identical grid.
+    pub pellets: Vec<PelletState>,
+    #[serde(default)]
+    pub teams: Vec<(Uuid, u8)>,
+    #[serde(default)]
+    pub zone_start_frame: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SnakeState {
+    pub id: Uuid,
+    pub bodies: VecDeque<Coordinate>,
+    pub acceleration_time_left: u32,
+    #[serde(default)]
+    pub protection_time_left: u32,
+    #[serde(default)]
+    pub power_ups: PowerUps,
+    pub speed: f32,
+    pub color: String,
+    /// Only differs from `color` while the snake is in a team.
+    #[serde(default)]
+    pub own_color: Option<String>,
+    pub velocity: Coordinate,
+    pub target_velocity: Coordinate,
+    pub size: usize,
+    pub frame_count_offset: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PelletState {
+    pub id: Uuid,
+    pub center: Coordinate,
+    pub radius: f32,
+    pub position: Coordinate,
+    pub size: u8,
+    pub color: String,
+    #[serde(default)]
+    pub kind: PelletKind,
+    pub frame_count_offset: u32,
+    #[serde(default)]
+    pub decay_frame: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct SnapshotHeader {
+    version: u32,
+}
+
+impl WorldSnapshot {
+    pub fn from_bytes(bytes: &[u8]) -> Result<WorldSnapshot, CiboriumError<Error>> {
+        // Check the version before decoding the body: a snapshot written by
+        // another version may not even have the same shape.
+        let header: SnapshotHeader = from_reader(bytes)?;
+        if header.version != SNAPSHOT_VERSION {
+            return Err(CiboriumError::semantic(
+                None,
+                format!("unsupported snapshot version {}", header.version),
+            ));
+        }
+        let snapshot: WorldSnapshot = from_reader(bytes)?;
+        snapshot
+            .config
+            .validate()
+            .map_err(|message| CiboriumError::semantic(None, message))?;
+        Ok(snapshot)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        into_writer(&self, &mut bytes).unwrap();
+        bytes
+    }
+}
+
+impl SnakeState {
+    fn new(id: Uuid, snake: &Snake) -> Self {
+        Self {
+            id,
+            bodies: snake.bodies.clone(),
+            acceleration_time_left: snake.acceleration_time_left,
+            protection_time_left: snake.protection_time_left,
+            power_ups: snake.power_ups,
+            speed: snake.speed,
+            color: snake.color.clone(),
+            own_color: Some(snake.own_color.clone()),
+            velocity: snake.velocity,
+            target_velocity: snake.target_velocity,
+            size: snake.size,
+            frame_count_offset: snake.frame_count_offset,
+        }
+    }
+
+    fn into_snake(self) -> Snake {
+        Snake {
+            bodies: self.bodies,
+            acceleration_time_left: self.acceleration_time_left,
+            protection_time_left: self.protection_time_left,
+            power_ups: self.power_ups,
+            speed: self.speed,
+            own_color: self.own_color.unwrap_or_else(|| self.color.clone()),
+            color: self.color,
+            velocity: self.velocity,
+            target_velocity: self.target_velocity,
+            size: self.size,
+            frame_count_offset: self.frame_count_offset,
+            is_visible_head: true,
+            handle: 0,
+            is_self: false,
+            team: None,
+        }
+    }
+}
+
+impl PelletState {
+    fn new(id: Uuid, pellet: &Pellet) -> Self {
+        Self {
+            id,
+            center: pellet.center,
+            radius: pellet.radius,
+            position: pellet.position,
+            size: pellet.size,
+            color: pellet.color.clone(),
+            kind: pellet.kind,
+            frame_count_offset: pellet.frame_count_offset,
+            decay_frame: pellet.decay_frame,
+        }
+    }
+
+    fn into_pellet(self) -> Pellet {
+        Pellet {
+            center: self.center,
+            radius: self.radius,
+            position: self.position,
+            size: self.size,
+            color: self.color,
+            kind: self.kind,
+            frame_count_offset: self.frame_count_offset,
+            decay_frame: self.decay_frame,
+            handle: 0,
+        }
+    }
+}
+
+impl GameEngine {
+    pub fn snapshot(&self) -> WorldSnapshot {
+        let snakes = self
+            .snakes
+            .iter()
+            .map(|(id, snake)| SnakeState::new(*id, snake))
+            .collect();
+        let pellets = self
+            .pellet_grid
+            .iter()
+            .flatten()
+            .filter_map(|id| {
+                self.pellets
+                    .get(id)
+                    .map(|pellet| PelletState::new(*id, pellet))
+            })
+            .collect();
+
+        WorldSnapshot {
+            version: SNAPSHOT_VERSION,
+            config: self.config.clone(),
+            seed: self.seed,
+            rng: self.rng.clone(),
+            frame_count: self.frame_count,
+            snakes,
+            pellets,
+            teams: self.teams.iter().map(|(id, team)| (*id, *team)).collect(),
+            zone_start_frame: self.zone_start_frame,
+        }
+    }
+
+    pub fn restore(snapshot: WorldSnapshot) -> GameEngine {
+        //! Rebuild an engine from a snapshot. The pellet and body grids and the
+        //! minimap are derived from the positions, so they are not stored in
+        //! the snapshot.
+
+        let mut engine = GameEngine::with_seed(snapshot.config, snapshot.seed);
+        engine.rng = snapshot.rng;
+        engine.frame_count = snapshot.frame_count;
+        engine.zone_start_frame = snapshot.zone_start_frame;
+        engine.snakes = snapshot
+            .snakes
+            .into_iter()
+            .map(|state| (state.id, state.into_snake()))
+            .collect::<BTreeMap<_, _>>();
+        engine.teams = snapshot.teams.into_iter().collect();
+        for (id, snake) in engine.snakes.iter_mut() {
+            snake.team = engine.teams.get(id).copied();
+        }
+        engine.pellets = EntityMap::default();
+        for state in snapshot.pellets {
+            let id = state.id;
+            let frame_count_offset = state.frame_count_offset;
+            Self::insert_pellet_into(
+                &engine.config,
+                &mut engine.pellets,
+                &mut engine.pellet_grid,
+                &mut engine.pellet_density,
+                id,
+                state.into_pellet(),
+                frame_count_offset,
+            );
+        }
+        engine.rebuild_body_grid();
+        engine.rebuild_density();
+        engine
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn busy_engine() -> (GameEngine, Uuid) {
+        let id = Uuid::from_u128(1);
+        let mut engine = GameEngine::with_seed(GameConfig::default(), 7);
+        engine.add_snake(id);
+        engine.add_snake(Uuid::from_u128(2));
+        for _ in 0..30 {
+            engine.change_velocity(&id, Coordinate { x: 0.2, y: 1.0 });
+            engine.forward();
+        }
+        (engine, id)
+    }
+
+    #[test]
+    fn snapshot_round_trips_through_cbor() {
+        let (engine, _) = busy_engine();
+        let snapshot = engine.snapshot();
+
+        let decoded = WorldSnapshot::from_bytes(&snapshot.to_bytes()).unwrap();
+
+        assert_eq!(decoded, snapshot);
+        assert_eq!(GameEngine::restore(decoded).snapshot(), snapshot);
+    }
+
+    #[test]
+    fn restored_engine_continues_identically() {
+        let (mut original, id) = busy_engine();
+        let mut restored = GameEngine::restore(original.snapshot());
+
+        for _ in 0..60 {
+            original.change_velocity(&id, Coordinate { x: 1.0, y: -0.4 });
+            restored.change_velocity(&id, Coordinate { x: 1.0, y: -0.4 });
+            original.forward();
+            restored.forward();
+        }
+
+        assert_eq!(restored.snapshot(), original.snapshot());
+    }
+
+    #[test]
+    fn keeps_fields_that_the_view_does_not_send() {
+        let (engine, id) = busy_engine();
+
+        let restored = GameEngine::restore(engine.snapshot());
+
+        let snake = restored.get_snake(&id).unwrap();
+        assert_eq!(
+            snake.target_velocity,
+            engine.get_snake(&id).unwrap().target_velocity
+        );
+        assert_eq!(snake.speed, engine.get_snake(&id).unwrap().speed);
+        for (pellet_id, pellet) in engine.pellets.iter() {
+            let restored_pellet = restored.pellets.get(pellet_id).unwrap();
+            assert_eq!(restored_pellet.center, pellet.center);
+            assert_eq!(restored_pellet.radius, pellet.radius);
+        }
+    }
+
+    #[test]
+    fn rejects_snapshots_of_another_version() {
+        let (engine, _) = busy_engine();
+        let mut snapshot = engine.snapshot();
+        snapshot.version = SNAPSHOT_VERSION + 1;
+
+        assert!(WorldSnapshot::from_bytes(&snapshot.to_bytes()).is_err());
+    }
+}
@@ -0,0 +1,248 @@
+use rand::RngExt;
+use std::f32::consts::TAU;
+use uuid::Uuid;
+
+use super::coordinate::Coordinate;
+use super::engine::GameEngine;
+use super::replay::Input;
+
+/// Party members spawn at least this far from the friend they join, so that
+/// they do not start inside each other.
+const PARTY_SPAWN_MIN_DISTANCE: f32 = 150.0;
+/// ... and at most this far, so that they start on each other's screen.
+const PARTY_SPAWN_MAX_DISTANCE: f32 = 400.0;
+const PARTY_SPAWN_ATTEMPTS: usize = 16;
+/// Random positions a snake spawning on its own chooses from.
+const SPAWN_CANDIDATES: usize = 16;
+/// Heads within this distance of a spawn position count as a threat.
+const SPAWN_HEAD_DISTANCE: f32 = 800.0;
+/// Minimap cells around a spawn position counted towards its crowding.
+const SPAWN_CROWDING_RADIUS: usize = 1;
+/// A spawn position is clear when no body point lies within this distance.
+const SPAWN_CLEARANCE: f32 = 100.0;
+
+impl GameEngine {
+    pub fn add_snake_near(&mut self, id: Uuid, friend: &Uuid) {
+        //! Spawn a snake a short distance from a friend's head, or anywhere if
+        //! the friend has no snake.
+
+        self.record(Input::AddSnakeNear(id, *friend));
+        let position = match self.snakes.get(friend) {
+            Some(snake) => {
+                let head = *snake.get_head();
+                self.spawn_position_near(&head)
+            }
+            None => self.random_spawn_coordinate(),
+        };
+        self.insert_snake(id, position);
+    }
+
+    pub(crate) fn safe_spawn_position(&mut self) -> Coordinate {
+        //! The least dangerous of a few random positions inside the zone:
+        //! clear of bodies first, then with the fewest heads nearby, then in the least
+        //! crowded part of the minimap.
+
+        let candidates: Vec<_> = (0..SPAWN_CANDIDATES)
+            .map(|_| self.random_spawn_coordinate())
+            .collect();
+        candidates
+            .into_iter()
+            .min_by_key(|position| {
+                let (is_blocked, heads) = self.spawn_danger(position);
+                (
+                    is_blocked,
+                    heads,
+                    self.snake_density.crowding(position, SPAWN_CROWDING_RADIUS),
+                )
+            })
+            .expect("there is at least one spawn candidate")
+    }
+
+    fn spawn_position_near(&mut self, center: &Coordinate) -> Coordinate {
+        //! The least dangerous of a few random positions around `center`, by
+        //! the same measure as `safe_spawn_position`. If none of them is
+        //! clear of bodies, the snake spawns on its own instead.
+
+        let field_size = self.config.field_size;
+        let candidates: Vec<_> = (0..PARTY_SPAWN_ATTEMPTS)
+            .map(|_| {
+                let angle = self.rng.random_range(0.0..TAU);
+                let distance = self
+                    .rng
+                    .random_range(PARTY_SPAWN_MIN_DISTANCE..PARTY_SPAWN_MAX_DISTANCE);
+                Coordinate {
+                    x: (center.x + distance * angle.cos()).rem_euclid(field_size),
+                    y: (center.y + distance * angle.sin()).rem_euclid(field_size),
+                }
+            })
+            .collect();
+        let (position, (is_blocked, _)) = candidates
+            .into_iter()
+            .map(|position| (position, self.spawn_danger(&position)))
+            .min_by_key(|(_, danger)| *danger)
+            .expect("there is at least one spawn candidate");
+        if is_blocked {
+            self.safe_spawn_position()
+        } else {
+            position
+        }
+    }
+
+    fn spawn_danger(&self, position: &Coordinate) -> (bool, usize) {
+        //! Whether `position` is blocked by a body or an obstacle, and how many
+        //! heads are near it.
+
+        let field_size = self.config.field_size;
+        let heads = self
+            .snakes
+            .values()
+            .filter(|snake| {
+                snake.get_head().distance2(position, field_size)
+                    < SPAWN_HEAD_DISTANCE * SPAWN_HEAD_DISTANCE
+            })
+            .count();
+        (!self.is_clear_for_spawn(position), heads)
+    }
+
+    pub(crate) fn is_clear_for_spawn(&self, position: &Coordinate) -> bool {
+        let field_size = self.config.field_size;
+        !self.is_blocked(position, SPAWN_CLEARANCE)
+            && self
+                .nearby_body_entries(position, SPAWN_CLEARANCE)
+                .iter()
+                .all(|entry| {
+                    entry.position.distance2(position, field_size)
+                        > SPAWN_CLEARANCE * SPAWN_CLEARANCE
+                })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::GameConfig;
+
+    #[test]
+    fn party_members_spawn_close_to_their_friend_but_clear_of_bodies() {
+        let config = GameConfig::default();
+        let field_size = config.field_size;
+        let mut engine = GameEngine::with_seed(config, 11);
+        let friend = Uuid::from_u128(1);
+        engine.add_snake_at(friend, Coordinate { x: 10.0, y: 10.0 });
+
+        for number in 2..10 {
+            let id = Uuid::from_u128(number);
+            engine.add_snake_near(id, &friend);
+            let head = engine.get_snake(&id).unwrap().get_head();
+            let friend_head = engine.get_snake(&friend).unwrap().get_head();
+            let distance = head.distance2(friend_head, field_size).sqrt();
+
+            assert!(
+                (PARTY_SPAWN_MIN_DISTANCE..=PARTY_SPAWN_MAX_DISTANCE).contains(&distance),
+                "{distance}"
+            );
+            for (other_id, other) in engine
+                .snakes
+                .iter()
+                .filter(|(other_id, _)| **other_id != id)
+            {
+                for body in other.bodies.iter() {
+                    assert!(
+                        body.distance2(head, field_size) > SPAWN_CLEARANCE * SPAWN_CLEARANCE,
+                        "{id} spawned on {other_id}"
+                    );
+                }
+            }
+            assert!(head.x >= 0.0 && head.x < field_size);
+            assert!(head.y >= 0.0 && head.y < field_size);
+        }
+    }
+
+    #[test]
+    fn party_members_spawn_elsewhere_when_their_friend_is_surrounded() {
+        let config = GameConfig {
+            spawn_protection_frames: 0,
+            ..GameConfig::default()
+        };
+        let field_size = config.field_size;
+        let mut engine = GameEngine::with_seed(config, 5);
+        let (friend, crowd, id) = (Uuid::from_u128(1), Uuid::from_u128(2), Uuid::from_u128(3));
+        engine.add_snake_at(friend, Coordinate { x: 500.0, y: 500.0 });
+        engine.add_snake_at(crowd, Coordinate { x: 0.0, y: 0.0 });
+        engine.get_snake_mut(&crowd).unwrap().bodies = (0..400)
+            .map(|index| Coordinate {
+                x: (index % 20) as f32 * 60.0 - 100.0,
+                y: (index / 20) as f32 * 60.0 - 100.0,
+            })
+            .collect();
+        engine.rebuild_body_grid();
+
+        engine.add_snake_near(id, &friend);
+
+        let head = engine.get_snake(&id).unwrap().get_head();
+        assert!(engine.snakes[&crowd]
+            .bodies
+            .iter()
+            .all(|body| body.distance2(head, field_size) > SPAWN_CLEARANCE * SPAWN_CLEARANCE));
+    }
+
+    #[test]
+    fn lone_snakes_spawn_clear_of_a_crowded_field() {
+        let config = GameConfig {
+            field_size: 3000.0,
+            max_pellet_count: 0,
+            map_size: 30,
+            ..GameConfig::default()
+        };
+        let field_size = config.field_size;
+        let mut engine = GameEngine::with_seed(config, 8);
+        for number in 0..40 {
+            engine.add_snake_at(
+                Uuid::from_u128(number),
+                Coordinate {
+                    x: 37.0 * number as f32,
+                    y: 71.0 * number as f32,
+                },
+            );
+        }
+
+        for number in 100..110 {
+            let id = Uuid::from_u128(number);
+            engine.add_snake(id);
+            let head = *engine.get_snake(&id).unwrap().get_head();
+            assert!(engine
+                .snakes
+                .iter()
+                .filter(|(other, _)| **other != id)
+                .flat_map(|(_, snake)| snake.bodies.iter())
+                .all(|body| body.distance2(&head, field_size) > SPAWN_CLEARANCE * SPAWN_CLEARANCE));
+        }
+    }
+
+    #[test]
+    fn protected_snakes_neither_die_nor_kill() {
+        let config = GameConfig {
+            spawn_protection_frames: 5,
+            ..GameConfig::default()
+        };
+        let mut engine = GameEngine::with_seed(config, 2);
+        engine.add_snake_at(Uuid::from_u128(1), Coordinate { x: 100.0, y: 100.0 });
+        engine.add_snake_at(Uuid::from_u128(2), Coordinate { x: 110.0, y: 100.0 });
+
+        for _ in 0..4 {
+            assert!(engine.forward().deaths.is_empty());
+        }
+        assert_eq!(engine.snake_ids().len(), 2);
+        assert_eq!(engine.forward().deaths.len(), 1);
+    }
+
+    #[test]
+    fn joining_a_missing_friend_spawns_anywhere() {
+        let mut engine = GameEngine::with_seed(GameConfig::default(), 4);
+        let id = Uuid::from_u128(1);
+
+        engine.add_snake_near(id, &Uuid::from_u128(2));
+
+        assert!(engine.get_snake(&id).is_some());
+    }
+}
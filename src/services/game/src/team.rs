@@ -0,0 +1,120 @@
+use uuid::Uuid;
+
+use super::engine::GameEngine;
+use super::replay::Input;
+use super::snake::Snake;
+
+/// The hue of every snake of a team: red, blue, green and yellow.
+pub const TEAM_COLORS: [&str; 4] = ["8", "240", "120", "60"];
+
+impl GameEngine {
+    pub fn set_team(&mut self, id: Uuid, team: Option<u8>) {
+        //! Put a player in a team, or take them out of theirs. The team sticks
+        //! to the id, so snakes spawned later under it join the team too.
+
+        self.record(Input::SetTeam(id, team));
+        let team = team.filter(|team| (*team as usize) < self.config.team_count);
+        match team {
+            Some(team) => self.teams.insert(id, team),
+            None => self.teams.remove(&id),
+        };
+        if let Some(snake) = self.snakes.get_mut(&id) {
+            Self::join_team(snake, team);
+        }
+    }
+
+    pub fn team(&self, id: &Uuid) -> Option<u8> {
+        self.teams.get(id).copied()
+    }
+
+    pub(crate) fn join_team(snake: &mut Snake, team: Option<u8>) {
+        snake.team = team;
+        snake.color = match team {
+            Some(team) => TEAM_COLORS[team as usize].to_owned(),
+            None => snake.own_color.clone(),
+        };
+    }
+}
+
+impl Snake {
+    pub fn is_teammate_of(&self, other: &Snake) -> bool {
+        self.team.is_some() && self.team == other.team
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::GameConfig;
+    use crate::coordinate::Coordinate;
+
+    #[test]
+    fn teammates_pass_through_each_other() {
+        let config = GameConfig {
+            spawn_protection_frames: 0,
+            team_count: 2,
+            ..GameConfig::default()
+        };
+        let mut engine = GameEngine::with_seed(config, 2);
+        let (first, second, third) = (Uuid::from_u128(1), Uuid::from_u128(2), Uuid::from_u128(3));
+        engine.set_team(first, Some(0));
+        engine.set_team(second, Some(0));
+        engine.add_snake_at(first, Coordinate { x: 100.0, y: 100.0 });
+        engine.add_snake_at(second, Coordinate { x: 110.0, y: 100.0 });
+
+        assert!(engine.forward().deaths.is_empty());
+        assert_eq!(engine.get_snake(&first).unwrap().color, TEAM_COLORS[0]);
+
+        engine.set_team(third, Some(1));
+        engine.add_snake_at(third, Coordinate { x: 105.0, y: 100.0 });
+        let deaths = engine.forward().deaths;
+        assert!(!deaths.is_empty());
+        assert!(deaths
+            .iter()
+            .all(|death| death.killer.and_then(|killer| engine.team(&killer))
+                != engine.team(&death.id)));
+    }
+
+    #[test]
+    fn teams_stick_to_the_id_across_lives() {
+        let config = GameConfig {
+            team_count: 2,
+            ..GameConfig::default()
+        };
+        let mut engine = GameEngine::with_seed(config, 4);
+        let id = Uuid::from_u128(1);
+        engine.set_team(id, Some(1));
+        engine.add_snake(id);
+        engine.remove_snake(&id);
+        engine.add_snake(id);
+
+        let snake = engine.get_snake(&id).unwrap();
+        assert_eq!(snake.team, Some(1));
+        assert_eq!(snake.color, TEAM_COLORS[1]);
+        let own_color = snake.own_color.clone();
+        assert_ne!(own_color, TEAM_COLORS[1]);
+
+        engine.set_team(id, None);
+        assert_eq!(engine.team(&id), None);
+        let snake = engine.get_snake(&id).unwrap();
+        assert_eq!(snake.team, None);
+        assert_eq!(snake.color, own_color);
+    }
+
+    #[test]
+    fn teams_beyond_the_team_count_are_ignored() {
+        let config = GameConfig {
+            team_count: 2,
+            ..GameConfig::default()
+        };
+        let mut engine = GameEngine::with_seed(config, 4);
+        let id = Uuid::from_u128(1);
+        engine.add_snake(id);
+        let own_color = engine.get_snake(&id).unwrap().color.clone();
+
+        engine.set_team(id, Some(2));
+
+        assert_eq!(engine.team(&id), None);
+        assert_eq!(engine.get_snake(&id).unwrap().color, own_color);
+    }
+}
@@ -3,11 +3,15 @@ use ciborium::{
     ser::into_writer,
 };
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::io::Error;
+use uuid::Uuid;
 
 use super::coordinate::Coordinate;
+use super::level::Obstacle;
 use super::pellet::Pellet;
 use super::snake::Snake;
+use super::zone::Zone;
 
 #[derive(Serialize, Deserialize)]
 pub struct View {
@@ -15,6 +19,60 @@ pub struct View {
     pub snakes: Vec<Snake>,
     pub pellets: Vec<Pellet>,
     pub background_offset: Coordinate,
+    /// The battle-royale zone, relative to the view like the other entities.
+    pub zone: Option<Zone>,
+    /// Obstacles reaching into the view, relative to it as well.
+    pub obstacles: Vec<Obstacle>,
+}
+
+/// Compact handles for the entities a single viewer has seen.
+///
+/// A handle stays attached to its entity for as long as the entity remains
+/// in the viewer's views, so that clients can match entities across frames.
+/// Handles of entities that left the view are reused, oldest first, only
+/// after every fresh handle has been used.
+#[derive(Default)]
+pub struct EntityHandles {
+    handles: HashMap<Uuid, u16>,
+    released: VecDeque<u16>,
+    next: u16,
+}
+
+impl EntityHandles {
+    pub fn handle(&mut self, id: Uuid) -> u16 {
+        //! Return the handle of `id`, assigning one if it has none. Handle 0
+        //! is never assigned; it is returned once every handle is in use.
+
+        if let Some(handle) = self.handles.get(&id) {
+            return *handle;
+        }
+        let handle = if self.next < u16::MAX {
+            self.next += 1;
+            self.next
+        } else if let Some(handle) = self.released.pop_front() {
+            handle
+        } else {
+            return 0;
+        };
+        self.handles.insert(id, handle);
+        handle
+    }
+
+    pub fn release_unseen(&mut self, seen: &[Uuid]) {
+        //! Release the handles of every entity that is not in `seen`.
+
+        let seen: HashSet<&Uuid> = seen.iter().collect();
+        let mut released: Vec<u16> = Vec::new();
+        self.handles.retain(|id, handle| {
+            let keep = seen.contains(id);
+            if !keep {
+                released.push(*handle);
+            }
+            keep
+        });
+        released.sort_unstable();
+        self.released.extend(released);
+    }
 }
 
 impl View {
@@ -28,3 +86,35 @@ impl View {
         bytes
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handles_are_stable_while_the_entity_stays_in_view() {
+        let mut handles = EntityHandles::default();
+        let first = Uuid::from_u128(1);
+        let second = Uuid::from_u128(2);
+
+        let handle = handles.handle(first);
+        handles.release_unseen(&[first]);
+
+        assert_eq!(handles.handle(first), handle);
+        assert_ne!(handles.handle(second), handle);
+    }
+
+    #[test]
+    fn released_handles_are_reused_only_after_the_fresh_ones() {
+        let mut handles = EntityHandles::default();
+        let first = Uuid::from_u128(1);
+        let released = handles.handle(first);
+        handles.release_unseen(&[]);
+
+        assert_ne!(handles.handle(Uuid::from_u128(2)), released);
+
+        handles.next = u16::MAX;
+        assert_eq!(handles.handle(Uuid::from_u128(3)), released);
+        assert_eq!(handles.handle(Uuid::from_u128(4)), 0);
+    }
+}
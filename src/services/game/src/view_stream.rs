@@ -0,0 +1,748 @@
+use ciborium::{
+    de::{from_reader, Error as CiboriumError},
+    ser::into_writer,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::io::Error;
+
+use super::coordinate::Coordinate;
+use super::level::Obstacle;
+use super::pellet::{Pellet, PelletKind};
+use super::power_up::PowerUps;
+use super::quantized::{QuantizedPacket, WireFormat};
+use super::snake::Snake;
+use super::view::View;
+use super::zone::Zone;
+
+/// A keyframe is sent at least this often so that a client that lost its
+/// base state recovers quickly.
+const KEYFRAME_INTERVAL: u32 = 90;
+/// Sent states kept for deltas while the client has not acknowledged them.
+const MAX_UNACKNOWLEDGED: usize = 32;
+/// A body that gained more heads than this since the base is resent whole.
+const MAX_HEAD_PUSHES: usize = 16;
+
+/// Everything a viewer can see, in world coordinates.
+///
+/// Unlike `View`, whose coordinates are relative to the moving camera, the
+/// positions in a `ViewState` only change when the entities move, so that two
+/// consecutive states differ in little more than the snake heads and tails.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ViewState {
+    #[serde(rename = "f")]
+    pub frame: u32,
+    #[serde(rename = "z")]
+    pub field_size: f32,
+    #[serde(rename = "l")]
+    pub is_alive: bool,
+    /// The top-left corner of the view rectangle.
+    #[serde(rename = "o")]
+    pub origin: Coordinate,
+    #[serde(rename = "w")]
+    pub width: f32,
+    #[serde(rename = "h")]
+    pub height: f32,
+    /// Snakes with at least one visible body, with the part of their bodies
+    /// that is near the view.
+    #[serde(rename = "n")]
+    pub snakes: Vec<ViewSnake>,
+    /// Visible pellets, sorted by handle.
+    #[serde(rename = "p")]
+    pub pellets: Vec<ViewPellet>,
+    /// The battle-royale zone, if the mode is on.
+    #[serde(rename = "y")]
+    pub zone: Option<Zone>,
+    /// Obstacles that reach into the view.
+    #[serde(rename = "t")]
+    pub obstacles: Vec<Obstacle>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ViewSnake {
+    #[serde(rename = "i")]
+    pub handle: u16,
+    #[serde(rename = "b")]
+    pub bodies: VecDeque<Coordinate>,
+    /// Whether `bodies` starts at the head rather than somewhere behind it.
+    #[serde(rename = "e")]
+    pub with_head: bool,
+    #[serde(rename = "a")]
+    pub acceleration_time_left: u32,
+    #[serde(rename = "g")]
+    pub protection_time_left: u32,
+    #[serde(rename = "u")]
+    pub power_ups: PowerUps,
+    #[serde(rename = "c")]
+    pub color: String,
+    #[serde(rename = "v")]
+    pub velocity: Coordinate,
+    #[serde(rename = "s")]
+    pub size: usize,
+    #[serde(rename = "m")]
+    pub is_self: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ViewPellet {
+    #[serde(rename = "i")]
+    pub handle: u16,
+    #[serde(rename = "p")]
+    pub center: Coordinate,
+    #[serde(rename = "r")]
+    pub radius: f32,
+    #[serde(rename = "s")]
+    pub size: u8,
+    #[serde(rename = "c")]
+    pub color: String,
+    #[serde(rename = "k")]
+    pub kind: PelletKind,
+    /// The frame the pellet was created at; drives its orbit.
+    #[serde(rename = "f")]
+    pub born: u32,
+    /// The frame a dropped pellet decays at.
+    #[serde(rename = "d")]
+    pub decay: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum ViewPacket {
+    #[serde(rename = "k")]
+    Keyframe {
+        #[serde(rename = "q")]
+        sequence: u32,
+        #[serde(rename = "s")]
+        state: ViewState,
+    },
+    #[serde(rename = "d")]
+    Delta(ViewDelta),
+}
+
+/// The difference between a state the client acknowledged and a new one.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ViewDelta {
+    #[serde(rename = "q")]
+    pub sequence: u32,
+    /// The sequence number of the state this delta applies to.
+    #[serde(rename = "b")]
+    pub base: u32,
+    #[serde(rename = "f")]
+    pub frame: u32,
+    #[serde(rename = "z")]
+    pub field_size: f32,
+    #[serde(rename = "l")]
+    pub is_alive: bool,
+    #[serde(rename = "o")]
+    pub origin: Coordinate,
+    #[serde(rename = "w")]
+    pub width: f32,
+    #[serde(rename = "h")]
+    pub height: f32,
+    /// Handles of every snake in the new state, in drawing order. Snakes of
+    /// the base that are missing here were removed.
+    #[serde(rename = "r")]
+    pub order: Vec<u16>,
+    /// Snakes that are new or changed. The others are unchanged.
+    #[serde(rename = "n")]
+    pub snakes: Vec<SnakeUpdate>,
+    /// Pellets that are new or moved.
+    #[serde(rename = "p")]
+    pub pellets: Vec<ViewPellet>,
+    #[serde(rename = "x")]
+    pub removed_pellets: Vec<u16>,
+    #[serde(rename = "y")]
+    pub zone: Option<Zone>,
+    /// The obstacles of the new state, or `None` if they are the base's.
+    #[serde(rename = "t")]
+    pub obstacles: Option<Vec<Obstacle>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum SnakeUpdate {
+    #[serde(rename = "s")]
+    Full(ViewSnake),
+    #[serde(rename = "d")]
+    Diff(SnakeDiff),
+}
+
+/// The new body is `heads`, then the base body without its first `skip` and
+/// last `pop` points, then `tail`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SnakeDiff {
+    #[serde(rename = "i")]
+    pub handle: u16,
+    #[serde(rename = "h")]
+    pub heads: Vec<Coordinate>,
+    #[serde(rename = "k")]
+    pub skip: usize,
+    #[serde(rename = "p")]
+    pub pop: usize,
+    #[serde(rename = "t")]
+    pub tail: Vec<Coordinate>,
+    #[serde(rename = "a")]
+    pub acceleration_time_left: u32,
+    #[serde(rename = "g")]
+    pub protection_time_left: u32,
+    #[serde(rename = "u")]
+    pub power_ups: PowerUps,
+    #[serde(rename = "v")]
+    pub velocity: Coordinate,
+    #[serde(rename = "s")]
+    pub size: usize,
+}
+
+impl ViewPacket {
+    pub fn sequence(&self) -> u32 {
+        match self {
+            ViewPacket::Keyframe { sequence, .. } => *sequence,
+            ViewPacket::Delta(delta) => delta.sequence,
+        }
+    }
+
+    /// Decodes a packet in either `WireFormat`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<ViewPacket, CiboriumError<Error>> {
+        from_reader(bytes).or_else(|error| {
+            from_reader::<QuantizedPacket, _>(bytes)
+                .map(|packet| packet.packet())
+                .map_err(|_| error)
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.to_bytes_as(WireFormat::Float)
+    }
+
+    pub fn to_bytes_as(&self, format: WireFormat) -> Vec<u8> {
+        let mut bytes = vec![];
+        match format {
+            WireFormat::Float => into_writer(&self, &mut bytes).unwrap(),
+            WireFormat::Quantized => into_writer(&QuantizedPacket::new(self), &mut bytes).unwrap(),
+        }
+        bytes
+    }
+}
+
+impl ViewState {
+    pub fn to_view(&self) -> View {
+        //! Cull the state to the view rectangle and make the coordinates
+        //! relative to it, as `GameEngine::view` sends them.
+
+        let (x0, y0) = (self.origin.x, self.origin.y);
+        let field_size = self.field_size;
+        let is_visible = |position: &Coordinate| {
+            position.is_in_rectangle(x0, y0, self.width, self.height, field_size)
+        };
+        let relative = |position: &Coordinate| Coordinate {
+            x: (position.x - x0).rem_euclid(field_size),
+            y: (position.y - y0).rem_euclid(field_size),
+        };
+        // The image of a position nearest to the middle of the view, for
+        // shapes that may reach into it from outside
+        let nearest_image = |position: &Coordinate| {
+            let position = relative(position);
+            let nearest = |value: f32, middle: f32| {
+                if value - middle > field_size / 2.0 {
+                    value - field_size
+                } else {
+                    value
+                }
+            };
+            Coordinate {
+                x: nearest(position.x, self.width / 2.0),
+                y: nearest(position.y, self.height / 2.0),
+            }
+        };
+
+        let snakes = self
+            .snakes
+            .iter()
+            .map(|snake| Snake {
+                bodies: snake
+                    .bodies
+                    .iter()
+                    .filter(|body| is_visible(body))
+                    .map(relative)
+                    .collect(),
+                acceleration_time_left: snake.acceleration_time_left,
+                protection_time_left: snake.protection_time_left,
+                power_ups: snake.power_ups,
+                speed: 0.0,
+                color: snake.color.clone(),
+                velocity: snake.velocity,
+                target_velocity: Coordinate::default(),
+                size: snake.size,
+                frame_count_offset: 0,
+                is_visible_head: snake.with_head && snake.bodies.front().is_some_and(is_visible),
+                handle: snake.handle,
+                is_self: snake.is_self,
+                team: None,
+                own_color: snake.color.clone(),
+            })
+            .collect();
+        let pellets = self
+            .pellets
+            .iter()
+            .map(|pellet| {
+                let mut pellet = Pellet {
+                    center: pellet.center,
+                    radius: pellet.radius,
+                    position: pellet.center,
+                    size: pellet.size,
+                    color: pellet.color.clone(),
+                    kind: pellet.kind,
+                    frame_count_offset: self.frame.wrapping_sub(pellet.born),
+                    decay_frame: pellet.decay.map(|frame| frame.saturating_sub(self.frame)),
+                    handle: pellet.handle,
+                };
+                pellet.update();
+                pellet.position = relative(&pellet.position);
+                pellet
+            })
+            .collect();
+
+        View {
+            is_alive: self.is_alive,
+            snakes,
+            pellets,
+            background_offset: Coordinate {
+                x: (-x0).rem_euclid(100.0),
+                y: (-y0).rem_euclid(100.0),
+            },
+            zone: self.zone.map(|zone| Zone {
+                center: nearest_image(&zone.center),
+                radius: zone.radius,
+            }),
+            obstacles: self
+                .obstacles
+                .iter()
+                .map(|obstacle| {
+                    obstacle.moved_to(nearest_image(&obstacle.center(field_size)), field_size)
+                })
+                .collect(),
+        }
+    }
+
+    pub fn delta_from(&self, base_sequence: u32, base: &ViewState, sequence: u32) -> ViewDelta {
+        let base_snakes: BTreeMap<u16, &ViewSnake> = base
+            .snakes
+            .iter()
+            .map(|snake| (snake.handle, snake))
+            .collect();
+        let snakes = self
+            .snakes
+            .iter()
+            .filter_map(|snake| match base_snakes.get(&snake.handle) {
+                Some(previous) if *previous == snake => None,
+                Some(previous) => Some(snake_update(previous, snake)),
+                None => Some(SnakeUpdate::Full(snake.clone())),
+            })
+            .collect();
+
+        let base_pellets: BTreeMap<u16, &ViewPellet> = base
+            .pellets
+            .iter()
+            .map(|pellet| (pellet.handle, pellet))
+            .collect();
+        let current_pellets: BTreeMap<u16, &ViewPellet> = self
+            .pellets
+            .iter()
+            .map(|pellet| (pellet.handle, pellet))
+            .collect();
+        let pellets = self
+            .pellets
+            .iter()
+            .filter(|pellet| base_pellets.get(&pellet.handle) != Some(pellet))
+            .cloned()
+            .collect();
+        let removed_pellets = base_pellets
+            .keys()
+            .filter(|handle| !current_pellets.contains_key(handle))
+            .copied()
+            .collect();
+
+        ViewDelta {
+            sequence,
+            base: base_sequence,
+            frame: self.frame,
+            field_size: self.field_size,
+            is_alive: self.is_alive,
+            origin: self.origin,
+            width: self.width,
+            height: self.height,
+            order: self.snakes.iter().map(|snake| snake.handle).collect(),
+            snakes,
+            pellets,
+            removed_pellets,
+            zone: self.zone,
+            obstacles: (self.obstacles != base.obstacles).then(|| self.obstacles.clone()),
+        }
+    }
+
+    pub fn apply(&self, delta: &ViewDelta) -> Option<ViewState> {
+        //! Rebuild the state `delta` was computed from, with `self` as its
+        //! base. Returns `None` if the delta does not fit the base.
+
+        let mut snakes: BTreeMap<u16, ViewSnake> = self
+            .snakes
+            .iter()
+            .map(|snake| (snake.handle, snake.clone()))
+            .collect();
+        for update in &delta.snakes {
+            match update {
+                SnakeUpdate::Full(snake) => {
+                    snakes.insert(snake.handle, snake.clone());
+                }
+                SnakeUpdate::Diff(diff) => {
+                    let snake = snakes.get_mut(&diff.handle)?;
+                    let kept = snake.bodies.len().checked_sub(diff.skip + diff.pop)?;
+                    snake.bodies.drain(..diff.skip);
+                    snake.bodies.truncate(kept);
+                    for head in diff.heads.iter().rev() {
+                        snake.bodies.push_front(*head);
+                    }
+                    snake.bodies.extend(diff.tail.iter().copied());
+                    snake.acceleration_time_left = diff.acceleration_time_left;
+                    snake.protection_time_left = diff.protection_time_left;
+                    snake.power_ups = diff.power_ups;
+                    snake.velocity = diff.velocity;
+                    snake.size = diff.size;
+                }
+            }
+        }
+
+        let mut pellets: BTreeMap<u16, ViewPellet> = self
+            .pellets
+            .iter()
+            .map(|pellet| (pellet.handle, pellet.clone()))
+            .collect();
+        for handle in &delta.removed_pellets {
+            pellets.remove(handle);
+        }
+        for pellet in &delta.pellets {
+            pellets.insert(pellet.handle, pellet.clone());
+        }
+
+        Some(ViewState {
+            frame: delta.frame,
+            field_size: delta.field_size,
+            is_alive: delta.is_alive,
+            origin: delta.origin,
+            width: delta.width,
+            height: delta.height,
+            snakes: delta
+                .order
+                .iter()
+                .map(|handle| snakes.remove(handle))
+                .collect::<Option<_>>()?,
+            pellets: pellets.into_values().collect(),
+            zone: delta.zone,
+            obstacles: delta
+                .obstacles
+                .clone()
+                .unwrap_or_else(|| self.obstacles.clone()),
+        })
+    }
+}
+
+fn snake_update(previous: &ViewSnake, current: &ViewSnake) -> SnakeUpdate {
+    if previous.color != current.color
+        || previous.is_self != current.is_self
+        || previous.with_head != current.with_head
+    {
+        return SnakeUpdate::Full(current.clone());
+    }
+    match body_diff(&previous.bodies, &current.bodies) {
+        Some((heads, skip, pop, tail)) => SnakeUpdate::Diff(SnakeDiff {
+            handle: current.handle,
+            heads,
+            skip,
+            pop,
+            tail,
+            acceleration_time_left: current.acceleration_time_left,
+            protection_time_left: current.protection_time_left,
+            power_ups: current.power_ups,
+            velocity: current.velocity,
+            size: current.size,
+        }),
+        None => SnakeUpdate::Full(current.clone()),
+    }
+}
+
+fn body_diff(
+    previous: &VecDeque<Coordinate>,
+    current: &VecDeque<Coordinate>,
+) -> Option<(Vec<Coordinate>, usize, usize, Vec<Coordinate>)> {
+    //! Find new heads, skipped front points and a tail change that turn
+    //! `previous` into `current`. Moving snakes push heads and pop or
+    //! duplicate their tail, and culled bodies lose points at the front when
+    //! the view moves away from the head, so the middle of the body is
+    //! shared.
+
+    for heads in 0..=MAX_HEAD_PUSHES.min(current.len()) {
+        for skip in 0..=MAX_HEAD_PUSHES.min(previous.len()) {
+            let shared = (previous.len() - skip).min(current.len() - heads);
+            if shared == 0 {
+                continue;
+            }
+            if !current
+                .range(heads..heads + shared)
+                .eq(previous.range(skip..skip + shared))
+            {
+                continue;
+            }
+            return Some((
+                current.range(..heads).copied().collect(),
+                skip,
+                previous.len() - skip - shared,
+                current.range(heads + shared..).copied().collect(),
+            ));
+        }
+    }
+    None
+}
+
+/// Turns the per-frame states of one viewer into packets.
+///
+/// Deltas are computed against the newest state the client acknowledged, so
+/// a lost or late packet never corrupts the client's state; until an
+/// acknowledgement arrives, and every `KEYFRAME_INTERVAL` frames, the whole
+/// state is sent instead.
+#[derive(Default)]
+pub struct ViewEncoder {
+    next_sequence: u32,
+    sent: VecDeque<(u32, ViewState)>,
+    acknowledged: Option<u32>,
+    frames_since_keyframe: u32,
+}
+
+impl ViewEncoder {
+    pub fn acknowledge(&mut self, sequence: u32) {
+        //! Record that the client decoded the packet `sequence`. Unknown and
+        //! outdated acknowledgements are ignored.
+
+        if self.acknowledged.is_some_and(|current| current >= sequence) {
+            return;
+        }
+        if self.sent.iter().any(|(sent, _)| *sent == sequence) {
+            self.acknowledged = Some(sequence);
+            self.sent.retain(|(sent, _)| *sent >= sequence);
+        }
+    }
+
+    pub fn encode(&mut self, state: ViewState) -> ViewPacket {
+        let sequence = self.next_sequence;
+        self.next_sequence += 1;
+
+        let base = self
+            .acknowledged
+            .filter(|_| self.frames_since_keyframe < KEYFRAME_INTERVAL)
+            .and_then(|acknowledged| self.sent.iter().find(|(sent, _)| *sent == acknowledged));
+        let packet = match base {
+            Some((base_sequence, base)) => {
+                self.frames_since_keyframe += 1;
+                ViewPacket::Delta(state.delta_from(*base_sequence, base, sequence))
+            }
+            None => {
+                self.frames_since_keyframe = 0;
+                ViewPacket::Keyframe {
+                    sequence,
+                    state: state.clone(),
+                }
+            }
+        };
+
+        self.sent.push_back((sequence, state));
+        if self.sent.len() > MAX_UNACKNOWLEDGED {
+            self.sent.pop_front();
+        }
+        packet
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::GameConfig;
+    use crate::engine::GameEngine;
+    use crate::view::EntityHandles;
+    use uuid::Uuid;
+
+    fn decode(states: &mut BTreeMap<u32, ViewState>, packet: &ViewPacket) -> Option<ViewState> {
+        let state = match packet {
+            ViewPacket::Keyframe { state, .. } => state.clone(),
+            ViewPacket::Delta(delta) => states.get(&delta.base)?.apply(delta)?,
+        };
+        states.insert(packet.sequence(), state.clone());
+        Some(state)
+    }
+
+    #[test]
+    fn decoded_deltas_reproduce_the_full_view() {
+        let viewer = Uuid::from_u128(1);
+        let mut engine = GameEngine::with_seed(GameConfig::default(), 9);
+        engine.add_snake_at(viewer, Coordinate { x: 500.0, y: 500.0 });
+        for number in 2..6 {
+            engine.add_snake_at(
+                Uuid::from_u128(number),
+                Coordinate {
+                    x: 300.0 + 100.0 * number as f32,
+                    y: 700.0,
+                },
+            );
+        }
+        let mut handles = EntityHandles::default();
+        let mut encoder = ViewEncoder::default();
+        let mut states = BTreeMap::new();
+        let mut delta_count = 0;
+
+        for frame in 0..200 {
+            engine.change_velocity(&viewer, Coordinate { x: 1.0, y: 0.4 });
+            if frame % 40 == 10 {
+                engine.accelerate(&viewer);
+            }
+            engine.forward();
+            let center = engine
+                .get_snake(&viewer)
+                .map(|snake| *snake.get_head())
+                .unwrap_or_default();
+            let state = engine.view_state(&viewer, center.x, center.y, 800.0, 600.0, &mut handles);
+            let expected = state.to_view().to_bytes();
+
+            let packet = ViewPacket::from_bytes(&encoder.encode(state).to_bytes()).unwrap();
+            delta_count += matches!(packet, ViewPacket::Delta(_)) as usize;
+            let decoded = decode(&mut states, &packet).unwrap();
+            assert_eq!(decoded.to_view().to_bytes(), expected, "frame {frame}");
+
+            // Acknowledge with a few frames of latency.
+            if let Some(sequence) = packet.sequence().checked_sub(3) {
+                encoder.acknowledge(sequence);
+            }
+        }
+
+        assert!(delta_count > 150);
+    }
+
+    #[test]
+    fn deltas_are_much_smaller_than_keyframes() {
+        let viewer = Uuid::from_u128(1);
+        let mut engine = GameEngine::with_seed(GameConfig::default(), 9);
+        engine.add_snake_at(viewer, Coordinate { x: 500.0, y: 500.0 });
+        let mut handles = EntityHandles::default();
+        let mut encoder = ViewEncoder::default();
+        let mut sizes = Vec::new();
+        for _ in 0..2 {
+            engine.change_velocity(&viewer, Coordinate { x: 1.0, y: 0.0 });
+            engine.forward();
+            let head = *engine.get_snake(&viewer).unwrap().get_head();
+            let state = engine.view_state(&viewer, head.x, head.y, 1920.0, 1080.0, &mut handles);
+            let packet = encoder.encode(state);
+            encoder.acknowledge(packet.sequence());
+            sizes.push(packet.to_bytes().len());
+        }
+
+        assert!(sizes[1] * 10 < sizes[0], "{sizes:?}");
+    }
+
+    #[test]
+    fn keyframes_only_carry_the_bodies_near_the_view() {
+        let viewer = Uuid::from_u128(1);
+        let other = Uuid::from_u128(2);
+        let config = GameConfig {
+            field_size: 10000.0,
+            max_pellet_count: 0,
+            ..GameConfig::default()
+        };
+        let mut engine = GameEngine::with_seed(config, 9);
+        engine.add_snake_at(viewer, Coordinate { x: 500.0, y: 500.0 });
+        engine.add_snake_at(
+            other,
+            Coordinate {
+                x: 5000.0,
+                y: 600.0,
+            },
+        );
+        engine.get_snake_mut(&other).unwrap().bodies = (0..1500)
+            .map(|index| Coordinate {
+                x: 5000.0 - 5.0 * index as f32,
+                y: 600.0,
+            })
+            .collect();
+        engine.rebuild_body_grid();
+        let mut handles = EntityHandles::default();
+
+        let view = engine.view(&viewer, 500.0, 500.0, 800.0, 600.0, &mut handles);
+        let state = engine.view_state(&viewer, 500.0, 500.0, 800.0, 600.0, &mut handles);
+        let culled = state.snakes.iter().find(|snake| !snake.is_self).unwrap();
+        assert!(!culled.with_head);
+        assert!(culled.bodies.len() < 200, "{}", culled.bodies.len());
+        assert_eq!(state.to_view().to_bytes(), view.to_bytes());
+        // The renderer asks for the quantized format.
+        let keyframe = ViewEncoder::default()
+            .encode(state)
+            .to_bytes_as(WireFormat::Quantized);
+        assert!(keyframe.len() <= view.to_bytes().len());
+    }
+
+    #[test]
+    fn keyframes_are_sent_until_acknowledged_and_periodically() {
+        let mut encoder = ViewEncoder::default();
+        let state = ViewState {
+            frame: 0,
+            field_size: 1000.0,
+            is_alive: true,
+            origin: Coordinate::default(),
+            width: 100.0,
+            height: 100.0,
+            snakes: Vec::new(),
+            pellets: Vec::new(),
+            zone: None,
+            obstacles: Vec::new(),
+        };
+
+        assert!(matches!(
+            encoder.encode(state.clone()),
+            ViewPacket::Keyframe { .. }
+        ));
+        assert!(matches!(
+            encoder.encode(state.clone()),
+            ViewPacket::Keyframe { .. }
+        ));
+        encoder.acknowledge(1);
+        encoder.acknowledge(42);
+        let kinds: Vec<bool> = (0..=KEYFRAME_INTERVAL)
+            .map(|_| {
+                let packet = encoder.encode(state.clone());
+                encoder.acknowledge(packet.sequence());
+                matches!(packet, ViewPacket::Keyframe { .. })
+            })
+            .collect();
+
+        assert!(kinds[..KEYFRAME_INTERVAL as usize]
+            .iter()
+            .all(|keyframe| !keyframe));
+        assert!(kinds[KEYFRAME_INTERVAL as usize]);
+    }
+
+    #[test]
+    fn body_diff_tracks_head_pushes_and_tail_changes() {
+        let point = |x: f32| Coordinate { x, y: 0.0 };
+        let previous: VecDeque<_> = [3.0, 2.0, 1.0].map(point).into();
+        let moved: VecDeque<_> = [5.0, 4.0, 3.0].map(point).into();
+        let grown: VecDeque<_> = [4.0, 3.0, 2.0, 1.0, 1.0].map(point).into();
+
+        let culled: VecDeque<_> = [2.0, 1.0, 0.0].map(point).into();
+
+        assert_eq!(
+            body_diff(&previous, &moved),
+            Some((vec![point(5.0), point(4.0)], 0, 2, vec![]))
+        );
+        assert_eq!(
+            body_diff(&previous, &grown),
+            Some((vec![point(4.0)], 0, 0, vec![point(1.0)]))
+        );
+        assert_eq!(
+            body_diff(&previous, &culled),
+            Some((vec![], 1, 0, vec![point(0.0)]))
+        );
+    }
+}
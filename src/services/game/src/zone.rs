@@ -0,0 +1,237 @@
+use rand::RngExt;
+use serde::{Deserialize, Serialize};
+use std::f32::consts::{FRAC_1_SQRT_2, TAU};
+use uuid::Uuid;
+
+use super::coordinate::Coordinate;
+use super::engine::{DeathCause, DeathEvent, FrameEvents, GameEngine};
+use super::pellet::Pellet;
+use super::replay::Input;
+use super::snake::SPAWN_LENGTH;
+
+/// The circle snakes must stay inside in battle-royale mode.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Zone {
+    #[serde(rename = "c")]
+    pub center: Coordinate,
+    #[serde(rename = "r")]
+    pub radius: f32,
+}
+
+impl Zone {
+    pub fn contains(&self, position: &Coordinate, field_size: f32) -> bool {
+        position.distance2(&self.center, field_size) <= self.radius * self.radius
+    }
+}
+
+impl GameEngine {
+    pub fn zone(&self) -> Option<Zone> {
+        //! The safe zone of the current match, or `None` when the mode is off.
+        //! It starts around the whole field and shrinks linearly to nothing
+        //! over `zone_shrink_frames`.
+
+        let shrink_frames = self.config.zone_shrink_frames;
+        if shrink_frames == 0 {
+            return None;
+        }
+        let field_size = self.config.field_size;
+        let elapsed = self
+            .frame_count
+            .saturating_sub(self.zone_start_frame)
+            .min(shrink_frames);
+        Some(Zone {
+            center: Coordinate {
+                x: field_size / 2.0,
+                y: field_size / 2.0,
+            },
+            radius: field_size * FRAC_1_SQRT_2 * (1.0 - elapsed as f32 / shrink_frames as f32),
+        })
+    }
+
+    pub fn restart_zone(&mut self) {
+        //! Start a new match, with the zone around the whole field again.
+
+        self.record(Input::RestartZone);
+        self.zone_start_frame = self.frame_count;
+    }
+
+    pub(crate) fn random_spawn_coordinate(&mut self) -> Coordinate {
+        //! A random position, moved into the zone if it fell outside.
+
+        let position = self.get_random_coordinate();
+        let field_size = self.config.field_size;
+        match self.zone() {
+            Some(zone) if !zone.contains(&position, field_size) => {
+                let angle = self.rng.random_range(0.0..TAU);
+                let distance = zone.radius * self.rng.random::<f32>().sqrt();
+                Coordinate {
+                    x: (zone.center.x + distance * angle.cos()).rem_euclid(field_size),
+                    y: (zone.center.y + distance * angle.sin()).rem_euclid(field_size),
+                }
+            }
+            _ => position,
+        }
+    }
+
+    pub(crate) fn is_inside_zone(&self, position: &Coordinate) -> bool {
+        self.zone()
+            .is_none_or(|zone| zone.contains(position, self.config.field_size))
+    }
+
+    pub(crate) fn shrink_snakes_outside_zone(&mut self) -> Vec<DeathEvent> {
+        //! Take the tail of every snake whose head is outside the zone and
+        //! drop it as a pellet. Snakes already down to their spawn length die.
+
+        let Some(zone) = self.zone() else {
+            return Vec::new();
+        };
+        let field_size = self.config.field_size;
+        let mut starved = Vec::new();
+        for (id, snake) in self.snakes.iter_mut() {
+            if zone.contains(snake.get_head(), field_size) {
+                continue;
+            }
+            if snake.bodies.len() <= SPAWN_LENGTH {
+                starved.push(*id);
+                continue;
+            }
+            let tail = snake.bodies.pop_back().unwrap();
+            self.snake_density.remove(&tail);
+            if self.pellets.len() < self.config.pellet_limit() {
+                let mut pellet =
+                    Pellet::new_with_color_and_size(tail, snake.color.clone(), 1, &mut self.rng);
+                pellet.decay_frame = self.config.decay_frame(self.frame_count);
+                let pellet_id = Self::random_id(&mut self.rng);
+                Self::insert_pellet_into(
+                    &self.config,
+                    &mut self.pellets,
+                    &mut self.pellet_grid,
+                    &mut self.pellet_density,
+                    pellet_id,
+                    pellet,
+                    self.frame_count,
+                );
+            }
+            snake.size = self.config.snake_size(snake.bodies.len());
+        }
+
+        starved
+            .into_iter()
+            .filter_map(|id| {
+                let death = self.death_event(&id, None, DeathCause::Zone);
+                self.drop_snake(&id);
+                death
+            })
+            .collect()
+    }
+
+    pub(crate) fn match_winner(&self, events: &FrameEvents) -> Option<Uuid> {
+        //! The last snake alive, on the frame the others died.
+
+        if self.zone().is_none() || events.deaths.is_empty() || self.snakes.len() != 1 {
+            return None;
+        }
+        self.snakes.keys().next().copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::GameConfig;
+    use std::f32::consts::FRAC_PI_4;
+
+    fn battle_royale() -> GameConfig {
+        GameConfig {
+            zone_shrink_frames: 100,
+            ..GameConfig::small_test_field()
+        }
+    }
+
+    #[test]
+    fn the_zone_shrinks_to_nothing_and_restarts() {
+        let mut engine = GameEngine::with_seed(battle_royale(), 1);
+        let full = engine.zone().unwrap();
+        assert!(full.contains(&Coordinate { x: 10.0, y: 10.0 }, 1000.0));
+
+        for _ in 0..50 {
+            engine.forward();
+        }
+        assert!((engine.zone().unwrap().radius - full.radius / 2.0).abs() < 0.01);
+        for _ in 0..60 {
+            engine.forward();
+        }
+        assert_eq!(engine.zone().unwrap().radius, 0.0);
+
+        engine.restart_zone();
+        assert_eq!(engine.zone(), Some(full));
+        assert_eq!(GameEngine::default().zone(), None);
+    }
+
+    #[test]
+    fn snakes_outside_the_zone_shrink_and_the_last_one_alive_wins() {
+        let mut engine = GameEngine::with_seed(battle_royale(), 2);
+        for _ in 0..50 {
+            engine.forward();
+        }
+        let (inside, outside) = (Uuid::from_u128(1), Uuid::from_u128(2));
+        engine.add_snake_at(inside, Coordinate { x: 500.0, y: 500.0 });
+        engine.add_snake_at(outside, Coordinate { x: 300.0, y: 10.0 });
+        engine.get_snake_mut(&outside).unwrap().bodies = (0..SPAWN_LENGTH + 3)
+            .map(|index| Coordinate {
+                x: 300.0 - 20.0 * index as f32,
+                y: 10.0,
+            })
+            .collect();
+
+        for _ in 0..3 {
+            let events = engine.forward();
+            assert!(events.deaths.is_empty());
+            assert_eq!(events.winner, None);
+        }
+        assert_eq!(
+            engine.get_snake(&outside).unwrap().bodies.len(),
+            SPAWN_LENGTH
+        );
+        assert_eq!(engine.pellets.len(), 3);
+
+        let events = engine.forward();
+        assert_eq!(events.deaths.len(), 1);
+        assert_eq!(events.deaths[0].id, outside);
+        assert_eq!(events.deaths[0].cause, DeathCause::Zone);
+        assert_eq!(events.winner, Some(inside));
+        assert_eq!(
+            engine.get_snake(&inside).unwrap().bodies.len(),
+            SPAWN_LENGTH
+        );
+    }
+
+    #[test]
+    fn bots_outside_the_zone_head_back_in() {
+        let mut engine = GameEngine::with_seed(battle_royale(), 4);
+        for _ in 0..50 {
+            engine.forward();
+        }
+        let bot = Uuid::from_u128(1);
+        engine.add_snake_at(bot, Coordinate { x: 100.0, y: 100.0 });
+
+        let observation = engine.bot_observation(&bot, None).unwrap();
+        assert!(!observation.has_pellet);
+        assert!((observation.target_heading - FRAC_PI_4).abs() < 0.01);
+    }
+
+    #[test]
+    fn snakes_spawn_inside_the_zone() {
+        let mut engine = GameEngine::with_seed(battle_royale(), 3);
+        for _ in 0..80 {
+            engine.forward();
+        }
+
+        for number in 0..10 {
+            let id = Uuid::from_u128(number);
+            engine.add_snake(id);
+            let head = *engine.get_snake(&id).unwrap().get_head();
+            assert!(engine.is_inside_zone(&head));
+        }
+    }
+}
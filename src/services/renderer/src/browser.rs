@@ -2,10 +2,11 @@
 
 use anyhow::{anyhow, Result};
 use game::coordinate::Coordinate;
+use game::protocol::{ClientCommand, ClientMessage};
 use std::cell::Cell;
 use std::rc::Rc;
 use wasm_bindgen::{closure::Closure, JsCast};
-use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement, MouseEvent, Window};
+use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement, MouseEvent, WebSocket, Window};
 
 pub fn window() -> Result<Window> {
     web_sys::window().ok_or_else(|| anyhow!("No Window Found"))
@@ -45,6 +46,30 @@ pub fn get_context(canvas: &HtmlCanvasElement) -> CanvasRenderingContext2d {
         .unwrap()
 }
 
+/// Sends commands to the server as binary messages, numbered in the order
+/// they are sent.
+#[derive(Clone)]
+pub struct CommandSender {
+    socket: WebSocket,
+    next_sequence: Rc<Cell<u32>>,
+}
+
+impl CommandSender {
+    pub fn new(socket: WebSocket) -> Self {
+        Self {
+            socket,
+            next_sequence: Rc::new(Cell::new(0)),
+        }
+    }
+
+    pub fn send(&self, command: ClientCommand) {
+        let sequence = self.next_sequence.get();
+        self.next_sequence.set(sequence.wrapping_add(1));
+        let message = ClientMessage { sequence, command };
+        self.socket.send_with_u8_array(&message.to_bytes()).ok();
+    }
+}
+
 pub struct MousePositionTracker {
     pub position: Rc<Cell<Coordinate>>,
     pub handler: Closure<dyn FnMut(MouseEvent)>,
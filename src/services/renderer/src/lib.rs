@@ -1,16 +1,28 @@
-use game::{coordinate::Coordinate, map::Map, pellet::Pellet, snake::Snake, view::View as Message};
+use game::{
+    coordinate::Coordinate,
+    level::Obstacle,
+    map::{Map, MapSelf},
+    pellet::{Pellet, PelletKind},
+    protocol::{ClientCommand, MatchPhase, MatchStatus, MessageError, RankingEntry, ServerMessage},
+    quantized::WireFormat,
+    snake::Snake,
+    view::View as Message,
+    zone::Zone,
+};
 
 #[macro_use]
 mod browser;
+mod view_decoder;
 use browser::{
     canvas, create_mouse_position_tracker, get_center_coordinate, get_context, get_height,
-    get_width, now, window,
+    get_width, now, window, CommandSender,
 };
 use std::rc::Rc;
 use std::{
     cell::{Cell, RefCell},
     collections::{HashMap, VecDeque},
 };
+use view_decoder::ViewDecoder;
 use wasm_bindgen::{
     prelude::{wasm_bindgen, Closure, JsValue},
     Clamped, JsCast,
@@ -31,6 +43,8 @@ const MAX_BUFFERED_SNAPSHOTS: usize = 16;
 const BODY_SPRITE_CACHE_CAPACITY: usize = 256;
 const RADIUS_STEPS_PER_PIXEL: f64 = 4.0;
 const BLUR_STEPS_PER_PIXEL: f64 = 2.0;
+const BANNER_DURATION_MS: f64 = 4000.0;
+const MATCH_RESULT_COUNT: usize = 10;
 type AnimationFrameCallback = Rc<RefCell<Option<Closure<dyn FnMut(f64)>>>>;
 
 struct Snapshot {
@@ -43,6 +57,51 @@ struct Snapshot {
 struct RenderState {
     snapshots: VecDeque<Snapshot>,
     next_sequence: u64,
+    hud: MatchHud,
+}
+
+/// What is drawn about the match on top of the game.
+#[derive(Default)]
+struct MatchHud {
+    status: Option<MatchStatus>,
+    results: Vec<RankingEntry>,
+    /// A short announcement and the time it disappears at.
+    banner: Option<(String, f64)>,
+}
+
+impl MatchHud {
+    fn update_status(&mut self, status: MatchStatus) {
+        // The results of a round stay up until the next one is announced.
+        if status.phase != MatchPhase::Results {
+            self.results.clear();
+        }
+        self.status = Some(status);
+    }
+
+    fn announce(&mut self, text: String, now: f64) {
+        self.banner = Some((text, now + BANNER_DURATION_MS));
+    }
+
+    fn banner(&self, now: f64) -> Option<&str> {
+        self.banner
+            .as_ref()
+            .filter(|(_, until)| *until > now)
+            .map(|(text, _)| text.as_str())
+    }
+
+    fn result_lines(&self) -> Vec<String> {
+        self.results
+            .iter()
+            .filter(|entry| entry.rank <= MATCH_RESULT_COUNT || entry.is_self)
+            .map(|entry| {
+                let name = if entry.is_self { "You" } else { &entry.name };
+                format!(
+                    "{}. {}  {} ({} kills)",
+                    entry.rank, name, entry.score, entry.kills
+                )
+            })
+            .collect()
+    }
 }
 
 #[derive(Clone)]
@@ -119,6 +178,7 @@ extern "C" {}
 pub struct RenderEngine {
     canvas: HtmlCanvasElement,
     socket: WebSocket,
+    commands: CommandSender,
     callback: Function,
     player_token: String,
     on_resize: Option<Closure<dyn FnMut()>>,
@@ -143,6 +203,7 @@ impl RenderEngine {
     ) -> Self {
         Self {
             canvas,
+            commands: CommandSender::new(socket.clone()),
             socket,
             callback,
             player_token,
@@ -171,7 +232,7 @@ impl RenderEngine {
 
         // 2. Add a resize event handler to the window so that the canvas dynamically resizes and sends it to the server.
         {
-            let socket = self.socket.clone();
+            let commands = self.commands.clone();
             let canvas = self.canvas.clone();
             let on_resize = Closure::wrap(Box::new(move || {
                 canvas.set_height(get_height());
@@ -179,9 +240,10 @@ impl RenderEngine {
                 get_context(&canvas)
                     .translate(-GLOBAL_MARGIN, -GLOBAL_MARGIN)
                     .unwrap();
-                socket
-                    .send_with_str(format!("w {} {}", get_width(), get_height()).as_str())
-                    .ok();
+                commands.send(ClientCommand::WindowSize {
+                    width: get_width() as u16,
+                    height: get_height() as u16,
+                });
             }) as Box<dyn FnMut()>);
             window()
                 .unwrap()
@@ -208,16 +270,83 @@ impl RenderEngine {
             let is_alive = Cell::new(true);
             let frame_after_death = Cell::new(0);
             let socket = self.socket.clone();
+            let commands = self.commands.clone();
             let callback = self.callback.clone();
             let mouse_position = mouse_position.clone();
             let render_state = render_state.clone();
             let minimap_context = minimap_context.clone();
+            let mut minimap: Option<Map> = None;
+            let mut decoder = ViewDecoder::default();
             let on_message = Closure::wrap(Box::new(move |e: MessageEvent| {
                 let array_buffer = e.data().dyn_into::<ArrayBuffer>().unwrap();
                 let array = Uint8Array::new(&array_buffer);
                 let vec = array.to_vec();
 
-                if let Ok(message) = Message::from_bytes(&vec) {
+                let packet = match ServerMessage::from_bytes(&vec) {
+                    Ok(ServerMessage::View(packet)) => Some(packet),
+                    Ok(ServerMessage::QuantizedView(packet)) => Some(packet.packet()),
+                    Ok(ServerMessage::Map(map)) => {
+                        minimap = Some(map);
+                        None
+                    }
+                    Ok(ServerMessage::MapSelf(map_self)) => {
+                        // The server follows every map with the player's own layer.
+                        if let Some(map) = minimap.as_ref() {
+                            update_minimap(&minimap_context, map, &map_self);
+                        }
+                        None
+                    }
+                    Ok(ServerMessage::Feast(_)) => {
+                        render_state
+                            .borrow_mut()
+                            .hud
+                            .announce("A feast was laid out!".to_owned(), now().unwrap());
+                        None
+                    }
+                    Ok(ServerMessage::MatchOver(winner)) => {
+                        let text = match winner {
+                            Some(winner) => format!("{winner} won the match"),
+                            None => "Nobody survived the match".to_owned(),
+                        };
+                        render_state.borrow_mut().hud.announce(text, now().unwrap());
+                        None
+                    }
+                    Ok(ServerMessage::MatchStatus(status)) => {
+                        render_state.borrow_mut().hud.update_status(status);
+                        None
+                    }
+                    Ok(ServerMessage::MatchResults(results)) => {
+                        render_state.borrow_mut().hud.results = results;
+                        None
+                    }
+                    Ok(ServerMessage::Error(error)) => {
+                        log!("server error: {error}");
+                        None
+                    }
+                    Ok(_) => None,
+                    Err(MessageError::UnsupportedVersion(version)) => {
+                        // The server was updated under us; nothing it sends
+                        // can be trusted to decode any more.
+                        log!("server speaks protocol version {version}");
+                        socket.close().ok();
+                        window()
+                            .unwrap()
+                            .alert_with_message(
+                                "The game has been updated. Please reload the page.",
+                            )
+                            .ok();
+                        callback.call0(&JsValue::NULL).unwrap();
+                        return;
+                    }
+                    Err(MessageError::Malformed(_)) => None,
+                };
+
+                if let Some((sequence, message)) = packet.and_then(|packet| decoder.decode(packet))
+                {
+                    // Acknowledge the view so that the server sends the next
+                    // ones as deltas against it.
+                    commands.send(ClientCommand::Acknowledge(sequence));
+
                     // if the snake is dead, gradually darken the screen and call the callback function when the screen is completely dark.
                     if !message.is_alive && is_alive.get() {
                         is_alive.set(false);
@@ -242,16 +371,10 @@ impl RenderEngine {
                     }
                 }
 
-                if let Ok(map) = Map::from_bytes(&vec) {
-                    update_minimap(&minimap_context, &map);
-                }
-
                 // Send the normalized mouse direction to the server.
                 if is_alive.get() {
                     let dir = vector(&get_center_coordinate(), &mouse_position.get());
-                    socket
-                        .send_with_str(format!("v {} {}", dir.x, dir.y).as_str())
-                        .ok();
+                    commands.send(ClientCommand::Velocity(dir));
                 }
             }) as Box<dyn FnMut(MessageEvent)>);
             self.socket
@@ -323,6 +446,7 @@ impl RenderEngine {
                                 &camera,
                                 &mouse_position.get(),
                             );
+                            render_match_hud(&context, &state.hud, timestamp);
                         }
                         drop(state);
 
@@ -363,13 +487,13 @@ impl RenderEngine {
 
         // 5. Add a mousedown handler to the window so that the snake can accelerate when the window is clicked.
         {
-            let socket = self.socket.clone();
+            let commands = self.commands.clone();
             let is_mousedown = Rc::new(Cell::new(false));
             let is_mousedown_for_mousedown = is_mousedown.clone();
             let is_mousedown_for_mouseup = is_mousedown.clone();
             let interval_callback = Closure::wrap(Box::new(move || {
                 if is_mousedown.get() {
-                    socket.send_with_str("a").ok();
+                    commands.send(ClientCommand::Accelerate);
                 }
             }) as Box<dyn FnMut()>);
             let interval_id = window()
@@ -399,12 +523,15 @@ impl RenderEngine {
         }
 
         // 6. Finally, send a start message to the server, and start the game.
-        self.socket
-            .send_with_str(format!("s {}", self.player_token).as_str())
-            .ok();
-        self.socket
-            .send_with_str(format!("w {} {}", self.canvas.width(), self.canvas.height()).as_str())
-            .ok();
+        self.commands
+            .send(ClientCommand::WireFormat(WireFormat::Quantized));
+        if let Ok(player_token) = self.player_token.parse() {
+            self.commands.send(ClientCommand::Start(player_token));
+        }
+        self.commands.send(ClientCommand::WindowSize {
+            width: self.canvas.width() as u16,
+            height: self.canvas.height() as u16,
+        });
     }
 
     pub fn destroy(&mut self) {
@@ -457,6 +584,10 @@ fn render(
         .translate(camera.1.x as f64, camera.1.y as f64)
         .unwrap();
     render_pellets(context, &current.pellets);
+    render_obstacles(context, &current.obstacles);
+    if let Some(zone) = current.zone.as_ref() {
+        render_zone(context, zone);
+    }
     context.restore();
     render_snakes(
         context,
@@ -468,6 +599,45 @@ fn render(
     render_minimap(context, minimap_context);
 }
 
+fn render_match_hud(context: &CanvasRenderingContext2d, hud: &MatchHud, now: f64) {
+    let center = get_width() as f64 / 2. + GLOBAL_MARGIN;
+    let mut y = GLOBAL_MARGIN + 36.;
+    context.save();
+    context.set_shadow_blur(0.);
+    context.set_fill_style_str("#eee");
+    context.set_text_align("center");
+    context.set_font("bold 20px sans-serif");
+    if let Some(status) = hud.status.as_ref() {
+        context
+            .fill_text(&match_status_text(status), center, y)
+            .unwrap();
+        y += 32.;
+    }
+    if let Some(banner) = hud.banner(now) {
+        context.fill_text(banner, center, y).unwrap();
+        y += 32.;
+    }
+    context.set_font("16px sans-serif");
+    for line in hud.result_lines() {
+        context.fill_text(&line, center, y).unwrap();
+        y += 22.;
+    }
+    context.restore();
+}
+
+fn match_status_text(status: &MatchStatus) -> String {
+    let time = format!(
+        "{}:{:02}",
+        status.seconds_left / 60,
+        status.seconds_left % 60
+    );
+    match status.phase {
+        MatchPhase::Lobby => format!("Round starts in {time}"),
+        MatchPhase::Round => format!("Round ends in {time}"),
+        MatchPhase::Results => format!("Next round in {time}"),
+    }
+}
+
 fn snapshot_pair(
     snapshots: &VecDeque<Snapshot>,
     position: f64,
@@ -552,6 +722,12 @@ fn render_pellets(context: &CanvasRenderingContext2d, pellets: &Vec<Pellet>) {
             )
             .unwrap();
         context.fill();
+        // Power-ups stand out with a ring
+        if pellet.kind != PelletKind::Food {
+            context.set_stroke_style_str("#fff");
+            context.set_line_width(1.5);
+            context.stroke();
+        }
     }
 }
 
@@ -563,25 +739,18 @@ fn render_snakes(
     mouse_position: &Coordinate,
 ) {
     context.set_shadow_blur(0.0);
-    let screen_center = get_center_coordinate();
-    let self_head_position = Coordinate {
-        x: screen_center.x + GLOBAL_MARGIN as f32,
-        y: screen_center.y + GLOBAL_MARGIN as f32,
-    };
-    let cursor_direction = vector(&screen_center, mouse_position);
-    for (snake_index, snake) in snakes.iter().enumerate() {
+    let cursor_direction = vector(&get_center_coordinate(), mouse_position);
+    for snake in snakes {
         // Draw the body
-        let previous_snake = matching_previous_snake(previous_snakes, snake_index, snake);
+        let previous_snake = matching_previous_snake(previous_snakes, snake);
         let snake_size = interpolated_snake_size(previous_snake, snake, amount);
         let hsl = snake_rendering_helper::to_hsl(snake);
         let bodies: Vec<_> = (0..snake.bodies.len())
             .map(|body_index| interpolated_body(previous_snake, snake, body_index, amount))
             .collect();
         let head = snake.is_visible_head.then(|| bodies[0]);
-        let is_self = head.is_some_and(|head| {
-            (head.x - self_head_position.x).abs() < 1.0
-                && (head.y - self_head_position.y).abs() < 1.0
-        });
+        let is_self = snake.is_self;
+        context.set_global_alpha(snake_alpha(snake));
 
         let shadow_sprite = cached_body_sprite("rgba(0, 0, 0, 0.3)", snake_size, 10.0);
         let glow_sprite = cached_body_sprite(&hsl, snake_size, snake_glow_blur(snake));
@@ -592,6 +761,21 @@ fn render_snakes(
 
         // Draw the face
         if let Some(head) = head {
+            if snake.power_ups.shield > 0 {
+                context.set_stroke_style_str("rgba(120, 200, 255, 0.8)");
+                context.set_line_width(3.0);
+                context.begin_path();
+                context
+                    .arc(
+                        head.x as f64,
+                        head.y as f64,
+                        snake_size * 1.4,
+                        0.,
+                        std::f64::consts::PI * 2.,
+                    )
+                    .unwrap();
+                context.stroke();
+            }
             let theta = interpolated_heading(previous_snake, snake, amount);
             let eye_distance = snake_size * 0.6;
             let left_eye = Coordinate {
@@ -645,16 +829,16 @@ fn render_snakes(
             context.fill();
         }
     }
+    context.set_global_alpha(1.0);
 }
 
 fn matching_previous_snake<'a>(
     previous_snakes: Option<&'a [Snake]>,
-    snake_index: usize,
     current: &Snake,
 ) -> Option<&'a Snake> {
-    previous_snakes
-        .and_then(|previous| previous.get(snake_index))
-        .filter(|previous| previous.color == current.color)
+    previous_snakes?
+        .iter()
+        .find(|previous| previous.handle == current.handle)
 }
 
 fn interpolated_snake_size(previous: Option<&Snake>, current: &Snake, amount: f32) -> f64 {
@@ -666,6 +850,19 @@ fn interpolated_snake_size(previous: Option<&Snake>, current: &Snake, amount: f3
     previous_size + (current_size - previous_size) * amount as f64
 }
 
+fn snake_alpha(snake: &Snake) -> f64 {
+    //! Snakes with spawn protection flicker translucently, ghosts are
+    //! steadily translucent.
+
+    if snake.protection_time_left > 0 {
+        0.35 + (snake.protection_time_left as f64 / 4.0).sin().abs() * 0.3
+    } else if snake.power_ups.ghost > 0 {
+        0.45
+    } else {
+        1.0
+    }
+}
+
 fn snake_glow_blur(snake: &Snake) -> f64 {
     if snake.acceleration_time_left == 0 {
         3.0
@@ -785,22 +982,39 @@ fn eye_gaze_direction(
     }
 }
 
-fn update_minimap(minimap_context: &CanvasRenderingContext2d, map: &Map) {
-    // NOTE: Based on the assumption that map is a 100*100 two-dimensional array
+fn update_minimap(minimap_context: &CanvasRenderingContext2d, map: &Map, map_self: &MapSelf) {
+    // The server's map size is configurable, so every minimap pixel samples
+    // the map cell that covers it.
 
     minimap_context.clear_rect(0.0, 0.0, MINIMAP_SIZE, MINIMAP_SIZE);
+    let map_size = map.size;
+    if map_size == 0 {
+        return;
+    }
+    let scale = MINIMAP_SIZE / map_size as f64;
 
     // Draw all map cells with a single browser API call. Calling fillRect for
     // every cell caused a visible frame drop whenever the minimap updated.
     let size = MINIMAP_SIZE as usize;
+    let mut is_self = vec![false; map_size * map_size];
+    for &(x, y) in map_self
+        .body
+        .iter()
+        .filter(|(x, y)| *x < map_size && *y < map_size)
+    {
+        is_self[x * map_size + y] = true;
+    }
     let mut pixels = vec![0; size * size * 4];
     for x in 0..MINIMAP_SIZE as usize {
         for y in 0..MINIMAP_SIZE as usize {
+            let (cell_x, cell_y) = (x * map_size / size, y * map_size / size);
             let index = (y * size + x) * 4;
-            pixels[index] = 255;
-            pixels[index + 1] = 255;
-            pixels[index + 2] = 255;
-            pixels[index + 3] = (map.map[x][y].min(10) * 25) as u8;
+            pixels[index..index + 4].copy_from_slice(&minimap_pixel(
+                map.pellets_at(cell_x, cell_y),
+                map.snakes_at(cell_x, cell_y),
+                map.obstacles_at(cell_x, cell_y) > 0,
+                is_self[cell_x * map_size + cell_y],
+            ));
         }
     }
     let image_data = ImageData::new_with_u8_clamped_array_and_sh(
@@ -823,13 +1037,29 @@ fn update_minimap(minimap_context: &CanvasRenderingContext2d, map: &Map) {
     minimap_context.line_to(MINIMAP_SIZE, MINIMAP_SIZE / 2.);
     minimap_context.stroke();
 
+    // Mark the heads of the leading snakes
+    minimap_context.set_fill_style_str("gold");
+    for &(x, y) in &map.leaders {
+        minimap_context.begin_path();
+        minimap_context
+            .arc(
+                (x as f64 + 0.5) * scale,
+                (y as f64 + 0.5) * scale,
+                2.,
+                0.,
+                std::f64::consts::PI * 2.,
+            )
+            .unwrap();
+        minimap_context.fill();
+    }
+
     // Draw the self coordinate
     minimap_context.set_fill_style_str("green");
     minimap_context.begin_path();
     minimap_context
         .arc(
-            map.self_coordinate.0 as f64,
-            map.self_coordinate.1 as f64,
+            map_self.position.0 as f64 * scale,
+            map_self.position.1 as f64 * scale,
             3.,
             0.,
             std::f64::consts::PI * 2.,
@@ -838,6 +1068,22 @@ fn update_minimap(minimap_context: &CanvasRenderingContext2d, map: &Map) {
     minimap_context.fill();
 }
 
+fn minimap_pixel(pellets: u8, snakes: u8, is_obstacle: bool, is_self: bool) -> [u8; 4] {
+    //! The RGBA color of a minimap cell. The player's own body is drawn over
+    //! other snakes, which are drawn over obstacles, then pellets.
+
+    if is_self {
+        return [80, 220, 120, 255];
+    }
+    if snakes > 0 {
+        return [255, 255, 255, 80 + snakes.min(7) * 25];
+    }
+    if is_obstacle {
+        return [136, 136, 153, 200];
+    }
+    [255, 200, 80, pellets.min(10) * 15]
+}
+
 fn render_minimap(context: &CanvasRenderingContext2d, minimap_context: &CanvasRenderingContext2d) {
     // Paste the sub canvas to the main canvas
     let responsive_size = (get_width() as f64 / 20.).clamp(70., 100.);
@@ -876,6 +1122,74 @@ fn render_background(context: &CanvasRenderingContext2d, offset: &Coordinate) {
     context.fill();
 }
 
+fn render_obstacles(context: &CanvasRenderingContext2d, obstacles: &[Obstacle]) {
+    context.set_shadow_blur(0.0);
+    context.set_fill_style_str("#445");
+    context.set_stroke_style_str("#889");
+    for obstacle in obstacles {
+        match *obstacle {
+            Obstacle::Circle { center, radius } => {
+                context.begin_path();
+                context
+                    .arc(
+                        center.x as f64,
+                        center.y as f64,
+                        radius as f64,
+                        0.0,
+                        std::f64::consts::PI * 2.0,
+                    )
+                    .unwrap();
+                context.fill();
+                context.set_line_width(3.0);
+                context.stroke();
+            }
+            Obstacle::Rectangle { center, size } => {
+                let (x, y) = (center.x - size.x / 2.0, center.y - size.y / 2.0);
+                context.fill_rect(x as f64, y as f64, size.x as f64, size.y as f64);
+                context.set_line_width(3.0);
+                context.stroke_rect(x as f64, y as f64, size.x as f64, size.y as f64);
+            }
+            Obstacle::Wall { from, to, width } => {
+                context.begin_path();
+                context.move_to(from.x as f64, from.y as f64);
+                context.line_to(to.x as f64, to.y as f64);
+                context.set_line_cap("round");
+                context.set_line_width(width as f64);
+                context.stroke();
+                context.set_line_cap("butt");
+            }
+        }
+    }
+}
+
+fn render_zone(context: &CanvasRenderingContext2d, zone: &Zone) {
+    //! Tint everything outside the battle-royale zone and outline its edge.
+    //! The circle is traced against the rectangle so that it cuts a hole.
+
+    let width = (get_width() + 100) as f64;
+    let height = (get_height() + 100) as f64;
+    let (x, y, radius) = (
+        zone.center.x as f64,
+        zone.center.y as f64,
+        zone.radius as f64,
+    );
+    context.begin_path();
+    context.rect(-100.0, -100.0, width + 200.0, height + 200.0);
+    context.move_to(x + radius, y);
+    context
+        .arc_with_anticlockwise(x, y, radius, 0.0, std::f64::consts::PI * 2.0, true)
+        .unwrap();
+    context.set_fill_style_str("rgba(255, 40, 40, 0.15)");
+    context.fill();
+    context.begin_path();
+    context
+        .arc(x, y, radius, 0.0, std::f64::consts::PI * 2.0)
+        .unwrap();
+    context.set_stroke_style_str("rgba(255, 40, 40, 0.8)");
+    context.set_line_width(4.0);
+    context.stroke();
+}
+
 fn vector(a: &Coordinate, b: &Coordinate) -> Coordinate {
     //! Returns the normalized vector from a to b.
     let x = b.x - a.x;
@@ -893,16 +1207,37 @@ fn vector(a: &Coordinate, b: &Coordinate) -> Coordinate {
 mod pellet_rendering_helper {
     use super::Pellet;
 
+    /// Frames over which a decaying pellet fades out.
+    const FADE_FRAMES: f64 = 90.;
+
     pub fn to_hsl(pellet: &Pellet) -> String {
         format!(
-            "hsl({}, 100%, {}%)",
+            "hsla({}, 100%, {}%, {})",
             pellet.color,
-            (30. * (pellet.frame_count_offset as f64 / 7.).sin()).abs() + 50.
+            (30. * (pellet.frame_count_offset as f64 / 7.).sin()).abs() + 50.,
+            to_alpha(pellet)
         )
     }
 
+    pub fn to_alpha(pellet: &Pellet) -> f64 {
+        //! Dropped pellets fade out over their last frames.
+
+        pellet
+            .decay_frame
+            .map_or(1., |frames_left| (frames_left as f64 / FADE_FRAMES).min(1.))
+    }
+
     pub fn to_radius(pellet: &Pellet) -> f64 {
-        (pellet.size as f64 * 2.).min(pellet.frame_count_offset as f64)
+        //! Heavy death drops grow with the square root of their size, so that
+        //! their area follows their value.
+
+        let size = pellet.size as f64;
+        let radius = if size <= 3. {
+            size * 2.
+        } else {
+            6. * (size / 3.).sqrt()
+        };
+        radius.min(pellet.frame_count_offset as f64)
     }
 }
 
@@ -924,9 +1259,21 @@ mod tests {
             snakes: Vec::new(),
             pellets: Vec::new(),
             background_offset: Coordinate::default(),
+            zone: None,
+            obstacles: Vec::new(),
         }
     }
 
+    #[test]
+    fn minimap_draws_self_over_snakes_over_pellets() {
+        assert_eq!(minimap_pixel(0, 0, false, false), [255, 200, 80, 0]);
+        assert_eq!(minimap_pixel(3, 0, false, false)[3], 45);
+        assert_eq!(minimap_pixel(3, 1, false, false), [255, 255, 255, 105]);
+        assert_eq!(minimap_pixel(3, 0, true, false), [136, 136, 153, 200]);
+        assert_eq!(minimap_pixel(3, 1, true, false), [255, 255, 255, 105]);
+        assert_eq!(minimap_pixel(3, u8::MAX, false, true), [80, 220, 120, 255]);
+    }
+
     #[test]
     fn wrapped_interpolation_takes_the_short_path() {
         assert!((lerp_wrapped(2.0, 98.0, 0.5, 100.0) - 0.0).abs() < f32::EPSILON);
@@ -965,7 +1312,8 @@ mod tests {
 
     #[test]
     fn snake_bodies_are_interpolated_between_snapshots() {
-        let previous = Snake::new(Coordinate { x: 10.0, y: 20.0 }, 5.0);
+        let previous =
+            Snake::new_with_color(Coordinate { x: 10.0, y: 20.0 }, 5.0, "120".to_string());
         let mut current = previous.clone();
         current.bodies[0] = Coordinate { x: 20.0, y: 30.0 };
 
@@ -976,21 +1324,35 @@ mod tests {
 
     #[test]
     fn snake_bodies_remain_interpolated_when_length_changes() {
-        let previous = Snake::new(Coordinate { x: 10.0, y: 20.0 }, 5.0);
+        let previous =
+            Snake::new_with_color(Coordinate { x: 10.0, y: 20.0 }, 5.0, "120".to_string());
         let mut current = previous.clone();
         current.bodies[0] = Coordinate { x: 20.0, y: 30.0 };
         current.bodies.push_back(Coordinate { x: 10.0, y: 20.0 });
         let previous_snakes = [previous];
-        let previous_snake = matching_previous_snake(Some(&previous_snakes), 0, &current);
+        let previous_snake = matching_previous_snake(Some(&previous_snakes), &current);
 
         let body = interpolated_body(previous_snake, &current, 0, 0.5);
 
         assert_eq!(body, Coordinate { x: 15.0, y: 25.0 });
     }
 
+    #[test]
+    fn previous_snakes_are_matched_by_handle_when_the_order_changes() {
+        let mut first = Snake::new_with_color(Coordinate::default(), 5.0, "120".to_string());
+        first.handle = 1;
+        let mut second = first.clone();
+        second.handle = 2;
+        let previous_snakes = [first, second.clone()];
+
+        let previous_snake = matching_previous_snake(Some(&previous_snakes), &second);
+
+        assert_eq!(previous_snake.map(|snake| snake.handle), Some(2));
+    }
+
     #[test]
     fn snake_size_is_interpolated_between_snapshots() {
-        let mut previous = Snake::new(Coordinate::default(), 5.0);
+        let mut previous = Snake::new_with_color(Coordinate::default(), 5.0, "120".to_string());
         previous.size = 15;
         let mut current = previous.clone();
         current.size = 17;
@@ -1002,14 +1364,14 @@ mod tests {
 
     #[test]
     fn snake_has_a_soft_glow_at_normal_speed() {
-        let snake = Snake::new(Coordinate::default(), 5.0);
+        let snake = Snake::new_with_color(Coordinate::default(), 5.0, "120".to_string());
 
         assert!((snake_glow_blur(&snake) - 3.0).abs() < f64::EPSILON);
     }
 
     #[test]
     fn snake_glow_pulses_while_accelerating() {
-        let mut snake = Snake::new(Coordinate::default(), 5.0);
+        let mut snake = Snake::new_with_color(Coordinate::default(), 5.0, "120".to_string());
         snake.acceleration_time_left = 11;
 
         let expected = (11.0_f64 / 7.0).sin().abs() * 15.0;
@@ -1017,6 +1379,98 @@ mod tests {
         assert!((snake_glow_blur(&snake) - expected).abs() < f64::EPSILON);
     }
 
+    #[test]
+    fn decaying_pellets_fade_out() {
+        let mut pellet = Pellet {
+            center: Coordinate::default(),
+            radius: 1.0,
+            position: Coordinate::default(),
+            size: 1,
+            color: "0".to_owned(),
+            kind: PelletKind::Food,
+            frame_count_offset: 0,
+            decay_frame: None,
+            handle: 0,
+        };
+        assert_eq!(pellet_rendering_helper::to_alpha(&pellet), 1.0);
+
+        pellet.decay_frame = Some(1000);
+        assert_eq!(pellet_rendering_helper::to_alpha(&pellet), 1.0);
+        pellet.decay_frame = Some(30);
+        assert!(pellet_rendering_helper::to_alpha(&pellet) < 0.5);
+    }
+
+    #[test]
+    fn protected_snakes_are_translucent() {
+        let mut snake = Snake::new_with_color(Coordinate::default(), 5.0, "120".to_string());
+        assert!((snake_alpha(&snake) - 1.0).abs() < f64::EPSILON);
+
+        snake.protection_time_left = 30;
+        assert!(snake_alpha(&snake) < 0.7);
+
+        snake.protection_time_left = 0;
+        snake.power_ups.ghost = 30;
+        assert!(snake_alpha(&snake) < 0.5);
+    }
+
+    #[test]
+    fn match_status_shows_the_time_left_in_the_phase() {
+        let status = |phase, seconds_left| MatchStatus {
+            phase,
+            seconds_left,
+        };
+
+        assert_eq!(
+            match_status_text(&status(MatchPhase::Lobby, 9)),
+            "Round starts in 0:09"
+        );
+        assert_eq!(
+            match_status_text(&status(MatchPhase::Round, 125)),
+            "Round ends in 2:05"
+        );
+    }
+
+    #[test]
+    fn match_results_are_shown_until_the_next_round() {
+        let entry = |rank, is_self| RankingEntry {
+            name: format!("player {rank}"),
+            score: 100 - rank,
+            kills: 1,
+            is_bot: false,
+            rank,
+            is_self,
+            team: None,
+        };
+        let mut hud = MatchHud {
+            results: (1..=12).map(|rank| entry(rank, rank == 12)).collect(),
+            ..MatchHud::default()
+        };
+        hud.update_status(MatchStatus {
+            phase: MatchPhase::Results,
+            seconds_left: 10,
+        });
+
+        let lines = hud.result_lines();
+        assert_eq!(lines.len(), 11);
+        assert_eq!(lines[0], "1. player 1  99 (1 kills)");
+        assert_eq!(lines[10], "12. You  88 (1 kills)");
+
+        hud.update_status(MatchStatus {
+            phase: MatchPhase::Lobby,
+            seconds_left: 30,
+        });
+        assert!(hud.result_lines().is_empty());
+    }
+
+    #[test]
+    fn banners_disappear_after_a_while() {
+        let mut hud = MatchHud::default();
+        hud.announce("Alice won the match".to_owned(), 1000.);
+
+        assert_eq!(hud.banner(2000.), Some("Alice won the match"));
+        assert_eq!(hud.banner(1000. + BANNER_DURATION_MS), None);
+    }
+
     #[test]
     fn body_sprite_keys_quantize_subpixel_changes() {
         let first = BodySpriteKey::new("blue", 15.11, 3.1);
@@ -1031,7 +1485,7 @@ mod tests {
 
     #[test]
     fn snake_heading_is_interpolated_between_snapshots() {
-        let mut previous = Snake::new(Coordinate::default(), 5.0);
+        let mut previous = Snake::new_with_color(Coordinate::default(), 5.0, "120".to_string());
         previous.velocity = Coordinate { x: 1.0, y: 0.0 };
         let mut current = previous.clone();
         current.velocity = Coordinate { x: 0.0, y: 1.0 };
@@ -0,0 +1,89 @@
+use game::view::View;
+use game::view_stream::{ViewPacket, ViewState};
+use std::collections::VecDeque;
+
+const MAX_DECODED_STATES: usize = 64;
+
+/// Rebuilds the server's views from keyframes and deltas.
+#[derive(Default)]
+pub struct ViewDecoder {
+    states: VecDeque<(u32, ViewState)>,
+}
+
+impl ViewDecoder {
+    pub fn decode(&mut self, packet: ViewPacket) -> Option<(u32, View)> {
+        //! Turn a packet into the view it stands for and the sequence number
+        //! to acknowledge. A delta whose base is unknown is dropped; the server
+        //! keeps sending deltas against the last acknowledged state instead.
+
+        let state = match &packet {
+            ViewPacket::Keyframe { state, .. } => state.clone(),
+            ViewPacket::Delta(delta) => {
+                let (_, base) = self
+                    .states
+                    .iter()
+                    .find(|(sequence, _)| *sequence == delta.base)?;
+                let state = base.apply(delta)?;
+                // The server never refers to states older than an acknowledged one.
+                self.states.retain(|(sequence, _)| *sequence >= delta.base);
+                state
+            }
+        };
+
+        let sequence = packet.sequence();
+        let view = state.to_view();
+        self.states.push_back((sequence, state));
+        if self.states.len() > MAX_DECODED_STATES {
+            self.states.pop_front();
+        }
+        Some((sequence, view))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use game::coordinate::Coordinate;
+    use game::view_stream::ViewEncoder;
+
+    fn state(frame: u32) -> ViewState {
+        ViewState {
+            frame,
+            field_size: 1000.0,
+            is_alive: true,
+            origin: Coordinate {
+                x: frame as f32,
+                y: 0.0,
+            },
+            width: 100.0,
+            height: 100.0,
+            snakes: Vec::new(),
+            pellets: Vec::new(),
+            zone: None,
+            obstacles: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn decodes_keyframes_and_acknowledged_deltas() {
+        let mut encoder = ViewEncoder::default();
+        let mut decoder = ViewDecoder::default();
+
+        let (sequence, _) = decoder.decode(encoder.encode(state(0))).unwrap();
+        encoder.acknowledge(sequence);
+        let (_, view) = decoder.decode(encoder.encode(state(30))).unwrap();
+
+        assert_eq!(view.background_offset, Coordinate { x: 70.0, y: 0.0 });
+    }
+
+    #[test]
+    fn drops_deltas_against_unknown_states() {
+        let mut encoder = ViewEncoder::default();
+        let packet = encoder.encode(state(0));
+        encoder.acknowledge(packet.sequence());
+
+        let delta = encoder.encode(state(1));
+
+        assert!(ViewDecoder::default().decode(delta).is_none());
+    }
+}
@@ -0,0 +1,51 @@
+use game::config::GameConfig;
+use game::level::Level;
+use std::env;
+use std::fs;
+use std::str::FromStr;
+
+pub fn load_game_config() -> GameConfig {
+    //! Load the world parameters for the engine.
+    //!
+    //! `GAME_CONFIG_FILE` points to a JSON file with any subset of the
+    //! `GameConfig` fields. Individual environment variables take precedence
+    //! over the file so that one-off experiments do not need a new file.
+    //! `LEVEL_FILE` points to a JSON level whose obstacles replace the
+    //! config's.
+
+    let mut config = match env::var("GAME_CONFIG_FILE") {
+        Ok(path) => {
+            let json = fs::read_to_string(&path)
+                .unwrap_or_else(|error| panic!("failed to read {path}: {error}"));
+            GameConfig::from_json(&json)
+                .unwrap_or_else(|error| panic!("invalid game config in {path}: {error}"))
+        }
+        Err(_) => GameConfig::default(),
+    };
+
+    override_from_env("FIELD_SIZE", &mut config.field_size);
+    override_from_env("MAX_PELLET_COUNT", &mut config.max_pellet_count);
+    override_from_env("SNAKE_SPEED", &mut config.snake_speed);
+    override_from_env("BOOST_FRAMES", &mut config.boost_frames);
+    override_from_env("TEAM_COUNT", &mut config.team_count);
+    override_from_env("ZONE_SHRINK_FRAMES", &mut config.zone_shrink_frames);
+    override_from_env("FEAST_INTERVAL_FRAMES", &mut config.feast_interval_frames);
+    if let Ok(path) = env::var("LEVEL_FILE") {
+        let json = fs::read_to_string(&path)
+            .unwrap_or_else(|error| panic!("failed to read {path}: {error}"));
+        let level = Level::from_json(&json)
+            .unwrap_or_else(|error| panic!("invalid level in {path}: {error}"));
+        config.obstacles = level.obstacles;
+    }
+
+    if let Err(message) = config.validate() {
+        panic!("invalid game config: {message}");
+    }
+    config
+}
+
+fn override_from_env<T: FromStr>(name: &str, value: &mut T) {
+    if let Some(parsed) = env::var(name).ok().and_then(|raw| raw.parse().ok()) {
+        *value = parsed;
+    }
+}
@@ -1,31 +1,108 @@
+mod game_config;
+mod match_clock;
 mod messages;
 mod ranking;
+mod rooms;
 mod websocket_actor;
 mod websocket_session;
-use actix::{Actor, Addr};
 use actix_web::{
-    get,
-    web::{Data, Payload, Query},
+    get, post,
+    web::{Data, Json, Payload, Query},
     App, Error, HttpRequest, HttpResponse, HttpServer,
 };
 use actix_web_actors::ws;
+use game::config::GameConfig;
 use openssl::ssl::{SslAcceptor, SslFiletype, SslMethod};
-use ranking::{RankingStore, SharedRanking};
-use serde::Deserialize;
+use ranking::{RankingOrder, SharedRanking};
+use rooms::{CreateError, JoinError, RoomRegistry, SharedRooms};
+use serde::{Deserialize, Serialize};
 use std::env;
 use std::sync::{Arc, RwLock};
-use websocket_actor::WebsocketActor;
 use websocket_session::WebsocketSession;
 
 #[get("/")]
 pub async fn handle_connection(
     req: HttpRequest,
     stream: Payload,
-    srv: Data<Addr<WebsocketActor>>,
+    rooms: Data<SharedRooms>,
+    query: Query<RoomQuery>,
 ) -> Result<HttpResponse, Error> {
-    let session = WebsocketSession::new(srv.get_ref().clone());
-    let response = ws::start(session, &req, stream)?;
-    Ok(response)
+    let joined = rooms
+        .write()
+        .map_err(|_| actix_web::error::ErrorInternalServerError("room registry unavailable"))?
+        .join(query.room.as_deref());
+    let (room, addr) = match joined {
+        Ok(joined) => joined,
+        Err(JoinError::NotFound) => return Ok(HttpResponse::NotFound().body("unknown room")),
+        Err(JoinError::Full) => return Ok(HttpResponse::Conflict().body("room is full")),
+    };
+
+    let session = WebsocketSession::new(addr, room.clone(), rooms.get_ref().clone());
+    ws::start(session, &req, stream).inspect_err(|_| {
+        if let Ok(mut rooms) = rooms.write() {
+            rooms.leave(&room);
+        }
+    })
+}
+
+#[derive(Deserialize)]
+pub struct RoomQuery {
+    room: Option<String>,
+}
+
+#[get("/rooms")]
+pub async fn list_rooms(rooms: Data<SharedRooms>) -> HttpResponse {
+    let rooms = rooms.read().map(|rooms| rooms.rooms()).unwrap_or_default();
+
+    HttpResponse::Ok()
+        .insert_header(("Access-Control-Allow-Origin", "*"))
+        .json(rooms)
+}
+
+#[post("/rooms")]
+pub async fn create_private_room(
+    rooms: Data<SharedRooms>,
+    request: Json<PrivateRoomRequest>,
+) -> HttpResponse {
+    let request = request.into_inner();
+    let created = match rooms.write() {
+        Ok(mut rooms) => rooms.create_private(request.bots, request.config),
+        Err(_) => return HttpResponse::InternalServerError().finish(),
+    };
+
+    let mut response = match created {
+        Ok(_) => HttpResponse::Created(),
+        Err(CreateError::TooManyRooms) => HttpResponse::ServiceUnavailable(),
+        Err(CreateError::InvalidConfig(_)) => HttpResponse::BadRequest(),
+    };
+    response.insert_header(("Access-Control-Allow-Origin", "*"));
+    match created {
+        Ok(code) => response.json(PrivateRoom { code }),
+        Err(error) => response.body(error.to_string()),
+    }
+}
+
+/// Settings of a private room. Omitted ones are those of the public rooms.
+#[derive(Deserialize)]
+pub struct PrivateRoomRequest {
+    bots: Option<usize>,
+    config: Option<GameConfig>,
+}
+
+#[derive(Serialize)]
+pub struct PrivateRoom {
+    code: String,
+}
+
+#[get("/teams")]
+pub async fn teams(rooms: Data<SharedRooms>, query: Query<RoomQuery>) -> HttpResponse {
+    let entries = room_ranking(&rooms, query.room.as_deref(), None)
+        .and_then(|ranking| Some(ranking.read().ok()?.team_totals()))
+        .unwrap_or_default();
+
+    HttpResponse::Ok()
+        .insert_header(("Access-Control-Allow-Origin", "*"))
+        .json(entries)
 }
 
 #[get("/health")]
@@ -34,18 +111,15 @@ pub async fn health() -> HttpResponse {
 }
 
 #[get("/leaderboard")]
-pub async fn leaderboard(
-    ranking: Data<SharedRanking>,
-    query: Query<LeaderboardQuery>,
-) -> HttpResponse {
-    let entries = ranking
-        .read()
-        .map(|ranking| {
-            let player_token = query
-                .player
-                .as_deref()
-                .and_then(|value| uuid::Uuid::parse_str(value).ok());
-            ranking.leaderboard(player_token)
+pub async fn leaderboard(rooms: Data<SharedRooms>, query: Query<LeaderboardQuery>) -> HttpResponse {
+    let player_token = query
+        .player
+        .as_deref()
+        .and_then(|value| uuid::Uuid::parse_str(value).ok());
+    let entries = room_ranking(&rooms, query.room.as_deref(), player_token)
+        .and_then(|ranking| {
+            let ranking = ranking.read().ok()?;
+            Some(ranking.leaderboard(player_token, query.order.unwrap_or_default()))
         })
         .unwrap_or_default();
 
@@ -56,7 +130,37 @@ pub async fn leaderboard(
 
 #[derive(Deserialize)]
 pub struct LeaderboardQuery {
+    room: Option<String>,
     player: Option<String>,
+    order: Option<RankingOrder>,
+}
+
+#[get("/kill-feed")]
+pub async fn kill_feed(rooms: Data<SharedRooms>, query: Query<RoomQuery>) -> HttpResponse {
+    let entries = room_ranking(&rooms, query.room.as_deref(), None)
+        .and_then(|ranking| Some(ranking.read().ok()?.kill_feed()))
+        .unwrap_or_default();
+
+    HttpResponse::Ok()
+        .insert_header(("Access-Control-Allow-Origin", "*"))
+        .json(entries)
+}
+
+fn room_ranking(
+    rooms: &SharedRooms,
+    room: Option<&str>,
+    player_token: Option<uuid::Uuid>,
+) -> Option<SharedRanking> {
+    //! The ranking of the requested room. Without a room, a player is shown
+    //! the room they play in, and everyone else the main room.
+
+    let rooms = rooms.read().ok()?;
+    match (room, player_token) {
+        (None, Some(player_token)) => rooms
+            .player_ranking(player_token)
+            .or_else(|| rooms.ranking(None)),
+        _ => rooms.ranking(room),
+    }
 }
 
 #[actix_web::main]
@@ -72,8 +176,11 @@ async fn main() -> std::io::Result<()> {
         Err(_e) => "5173".to_string(),
     };
 
-    let ranking = Arc::new(RwLock::new(RankingStore::default()));
-    let websocket_server = WebsocketActor::new(ranking.clone()).start();
+    let rooms = Arc::new(RwLock::new(RoomRegistry::new(
+        game_config::load_game_config(),
+        rooms::room_capacity(),
+        rooms::bot_count(),
+    )));
 
     println!("Starting server on {}:{}", host, port);
     if std::env::var("PRIVATE_KEY_FILE").is_err()
@@ -84,8 +191,11 @@ async fn main() -> std::io::Result<()> {
                 .service(handle_connection)
                 .service(health)
                 .service(leaderboard)
-                .app_data(Data::new(ranking.clone()))
-                .app_data(Data::new(websocket_server.clone()))
+                .service(kill_feed)
+                .service(teams)
+                .service(list_rooms)
+                .service(create_private_room)
+                .app_data(Data::new(rooms.clone()))
         })
         .bind(format!("{}:{}", host, port))?
         .run()
@@ -107,8 +217,11 @@ async fn main() -> std::io::Result<()> {
                 .service(handle_connection)
                 .service(health)
                 .service(leaderboard)
-                .app_data(Data::new(ranking.clone()))
-                .app_data(Data::new(websocket_server.clone()))
+                .service(kill_feed)
+                .service(teams)
+                .service(list_rooms)
+                .service(create_private_room)
+                .app_data(Data::new(rooms.clone()))
         })
         .bind_openssl(format!("{}:{}", host, port), builder)?
         .run()
@@ -0,0 +1,114 @@
+use game::protocol::{MatchPhase, MatchStatus};
+use std::env;
+
+const DEFAULT_LOBBY_SECONDS: u32 = 10;
+const DEFAULT_RESULTS_SECONDS: u32 = 10;
+
+/// How long each phase of a timed match lasts.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MatchSettings {
+    pub lobby_seconds: u32,
+    pub round_seconds: u32,
+    pub results_seconds: u32,
+}
+
+/// Counts down the phases of timed matches, one frame at a time: lobby,
+/// round, results, then the lobby of the next match.
+pub struct MatchClock {
+    settings: MatchSettings,
+    frames_per_second: u32,
+    phase: MatchPhase,
+    frames_left: u32,
+}
+
+impl MatchClock {
+    pub fn new(settings: MatchSettings, frames_per_second: u32) -> Self {
+        MatchClock {
+            settings,
+            frames_per_second,
+            phase: MatchPhase::Lobby,
+            frames_left: settings.lobby_seconds * frames_per_second,
+        }
+    }
+
+    pub fn phase(&self) -> MatchPhase {
+        self.phase
+    }
+
+    pub fn status(&self) -> MatchStatus {
+        MatchStatus {
+            phase: self.phase,
+            seconds_left: self.frames_left.div_ceil(self.frames_per_second),
+        }
+    }
+
+    pub fn tick(&mut self) -> Option<MatchPhase> {
+        //! Advance by one frame. Returns the phase just entered, if any.
+
+        self.frames_left = self.frames_left.saturating_sub(1);
+        if self.frames_left > 0 {
+            return None;
+        }
+        let (phase, seconds) = match self.phase {
+            MatchPhase::Lobby => (MatchPhase::Round, self.settings.round_seconds),
+            MatchPhase::Round => (MatchPhase::Results, self.settings.results_seconds),
+            MatchPhase::Results => (MatchPhase::Lobby, self.settings.lobby_seconds),
+        };
+        self.phase = phase;
+        self.frames_left = seconds * self.frames_per_second;
+        Some(phase)
+    }
+}
+
+pub fn match_settings() -> Option<MatchSettings> {
+    //! Timed matches are on when `MATCH_ROUND_SECONDS` is set above 0.
+
+    let seconds = |name: &str| {
+        env::var(name)
+            .ok()
+            .and_then(|value| value.parse::<u32>().ok())
+    };
+    let round_seconds = seconds("MATCH_ROUND_SECONDS").filter(|seconds| *seconds > 0)?;
+    Some(MatchSettings {
+        lobby_seconds: seconds("MATCH_LOBBY_SECONDS").unwrap_or(DEFAULT_LOBBY_SECONDS),
+        round_seconds,
+        results_seconds: seconds("MATCH_RESULTS_SECONDS").unwrap_or(DEFAULT_RESULTS_SECONDS),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cycles_through_the_phases() {
+        let settings = MatchSettings {
+            lobby_seconds: 1,
+            round_seconds: 3,
+            results_seconds: 2,
+        };
+        let mut clock = MatchClock::new(settings, 2);
+        assert_eq!(
+            clock.status(),
+            MatchStatus {
+                phase: MatchPhase::Lobby,
+                seconds_left: 1,
+            }
+        );
+
+        let changes: Vec<_> = (0..13)
+            .map(|frame| (frame, clock.tick()))
+            .filter_map(|(frame, phase)| Some((frame, phase?)))
+            .collect();
+
+        assert_eq!(
+            changes,
+            vec![
+                (1, MatchPhase::Round),
+                (7, MatchPhase::Results),
+                (11, MatchPhase::Lobby),
+            ]
+        );
+        assert_eq!(clock.status().seconds_left, 1);
+    }
+}
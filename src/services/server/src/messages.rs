@@ -1,4 +1,5 @@
 use actix::{Message, Recipient};
+use game::protocol::ClientCommand;
 use uuid::Uuid;
 
 #[derive(Message)]
@@ -20,7 +21,14 @@ pub struct Disconnect {
 
 #[derive(Message)]
 #[rtype(result = "()")]
-pub struct ClientMessage {
+pub struct ClientInput {
     pub id: Uuid,
-    pub msg: String,
+    /// `None` for commands sent in their text form.
+    pub sequence: Option<u32>,
+    pub command: ClientCommand,
 }
+
+/// Stops a room that nobody plays in any more.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Shutdown;
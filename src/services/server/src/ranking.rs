@@ -1,9 +1,12 @@
-use serde::Serialize;
-use std::collections::HashMap;
+use game::protocol::{KillFeedEntry, RankingEntry, TeamEntry};
+use serde::Deserialize;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::sync::{Arc, RwLock};
 use uuid::Uuid;
 
 const LEADERBOARD_SIZE: usize = 10;
+const KILL_FEED_SIZE: usize = 10;
 
 #[derive(Clone, Debug, PartialEq)]
 struct CurrentScore {
@@ -13,18 +16,22 @@ struct CurrentScore {
     player_token: Option<Uuid>,
 }
 
-#[derive(Clone, Debug, PartialEq, Serialize)]
-pub struct RankingEntry {
-    pub name: String,
-    pub score: usize,
-    pub is_bot: bool,
-    pub rank: usize,
-    pub is_self: bool,
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RankingOrder {
+    #[default]
+    Score,
+    Kills,
 }
 
 #[derive(Default)]
 pub struct RankingStore {
     current_scores: HashMap<Uuid, CurrentScore>,
+    // Kills of the current life, kept apart from the scores so that a kill
+    // counts even before the killer's first score update.
+    kills: HashMap<Uuid, usize>,
+    teams: HashMap<Uuid, u8>,
+    kill_feed: VecDeque<KillFeedEntry>,
 }
 
 pub type SharedRanking = Arc<RwLock<RankingStore>>;
@@ -51,20 +58,98 @@ impl RankingStore {
 
     pub fn remove(&mut self, id: &Uuid) {
         self.current_scores.remove(id);
+        self.kills.remove(id);
+        self.teams.remove(id);
     }
 
-    pub fn leaderboard(&self, player_token: Option<Uuid>) -> Vec<RankingEntry> {
-        let mut entries: Vec<_> = self.current_scores.values().collect();
-        entries.sort_by(|left, right| {
-            right
-                .score
-                .cmp(&left.score)
-                .then_with(|| left.name.cmp(&right.name))
+    pub fn reset(&mut self) {
+        //! Forget every score, kill and death, e.g. when a new match starts.
+
+        *self = RankingStore::default();
+    }
+
+    pub fn set_team(&mut self, id: Uuid, team: Option<u8>) {
+        match team {
+            Some(team) => self.teams.insert(id, team),
+            None => self.teams.remove(&id),
+        };
+    }
+
+    pub fn team_totals(&self) -> Vec<TeamEntry> {
+        //! The summed scores and kills of the ranked members of each team,
+        //! best team first.
+
+        let mut totals: BTreeMap<u8, TeamEntry> = BTreeMap::new();
+        for (id, entry) in self.current_scores.iter() {
+            let Some(team) = self.teams.get(id) else {
+                continue;
+            };
+            let total = totals.entry(*team).or_insert(TeamEntry {
+                team: *team,
+                score: 0,
+                kills: 0,
+                members: 0,
+            });
+            total.score += entry.score;
+            total.kills += self.kills.get(id).copied().unwrap_or_default();
+            total.members += 1;
+        }
+        let mut totals: Vec<_> = totals.into_values().collect();
+        totals.sort_by_key(|total| Reverse(total.score));
+        totals
+    }
+
+    pub fn has_player(&self, player_token: Uuid) -> bool {
+        self.current_scores
+            .values()
+            .any(|entry| entry.player_token == Some(player_token))
+    }
+
+    pub fn record_death(&mut self, victim: &Uuid, killer: Option<Uuid>, entry: KillFeedEntry) {
+        //! Remove the victim, credit the killer and add the death to the feed.
+
+        self.remove(victim);
+        if let Some(killer) = killer {
+            *self.kills.entry(killer).or_default() += 1;
+        }
+        if self.kill_feed.len() == KILL_FEED_SIZE {
+            self.kill_feed.pop_front();
+        }
+        self.kill_feed.push_back(entry);
+    }
+
+    pub fn kill_feed(&self) -> Vec<KillFeedEntry> {
+        //! The most recent deaths, newest first.
+
+        self.kill_feed.iter().rev().cloned().collect()
+    }
+
+    pub fn leaderboard(
+        &self,
+        player_token: Option<Uuid>,
+        order: RankingOrder,
+    ) -> Vec<RankingEntry> {
+        let mut entries: Vec<_> = self
+            .current_scores
+            .iter()
+            .map(|(id, entry)| {
+                let kills = self.kills.get(id).copied().unwrap_or_default();
+                (entry, kills, self.teams.get(id).copied())
+            })
+            .collect();
+        entries.sort_by(|(left, left_kills, _), (right, right_kills, _)| {
+            let primary = match order {
+                RankingOrder::Score => right.score.cmp(&left.score),
+                RankingOrder::Kills => right_kills
+                    .cmp(left_kills)
+                    .then_with(|| right.score.cmp(&left.score)),
+            };
+            primary.then_with(|| left.name.cmp(&right.name))
         });
         let self_index = player_token.and_then(|token| {
             entries
                 .iter()
-                .position(|entry| entry.player_token == Some(token))
+                .position(|(entry, _, _)| entry.player_token == Some(token))
         });
         let mut visible_indices: Vec<_> = (0..entries.len().min(LEADERBOARD_SIZE)).collect();
         if let Some(index) = self_index.filter(|index| *index >= LEADERBOARD_SIZE) {
@@ -74,13 +159,15 @@ impl RankingStore {
         visible_indices
             .into_iter()
             .map(|index| {
-                let entry = entries[index];
+                let (entry, kills, team) = entries[index];
                 RankingEntry {
                     name: entry.name.clone(),
                     score: entry.score,
+                    kills,
                     is_bot: entry.is_bot,
                     rank: index + 1,
                     is_self: self_index == Some(index),
+                    team,
                 }
             })
             .collect()
@@ -90,6 +177,7 @@ impl RankingStore {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use game::engine::DeathCause;
 
     #[test]
     fn updates_a_players_current_score() {
@@ -99,13 +187,15 @@ mod tests {
         ranking.update(id, "Alice", 12, false, None);
 
         assert_eq!(
-            ranking.leaderboard(None),
+            ranking.leaderboard(None, RankingOrder::Score),
             vec![RankingEntry {
                 name: "Alice".to_string(),
                 score: 12,
+                kills: 0,
                 is_bot: false,
                 rank: 1,
                 is_self: false,
+                team: None,
             }]
         );
     }
@@ -117,7 +207,29 @@ mod tests {
         ranking.update(id, "Alice", 20, false, None);
         ranking.remove(&id);
 
-        assert!(ranking.leaderboard(None).is_empty());
+        assert!(ranking.leaderboard(None, RankingOrder::Score).is_empty());
+    }
+
+    #[test]
+    fn reset_forgets_scores_kills_and_the_feed() {
+        let mut ranking = RankingStore::default();
+        let (hunter, victim) = (Uuid::new_v4(), Uuid::new_v4());
+        ranking.update(hunter, "Hunter", 30, false, None);
+        ranking.record_death(
+            &victim,
+            Some(hunter),
+            KillFeedEntry {
+                victim: "Victim".to_string(),
+                killer: Some("Hunter".to_string()),
+                cause: DeathCause::Body,
+            },
+        );
+        ranking.reset();
+
+        assert!(ranking.leaderboard(None, RankingOrder::Score).is_empty());
+        assert!(ranking.kill_feed().is_empty());
+        ranking.update(hunter, "Hunter", 10, false, None);
+        assert_eq!(ranking.leaderboard(None, RankingOrder::Kills)[0].kills, 0);
     }
 
     #[test]
@@ -133,7 +245,7 @@ mod tests {
             );
         }
 
-        let entries = ranking.leaderboard(None);
+        let entries = ranking.leaderboard(None, RankingOrder::Score);
         assert_eq!(entries.len(), LEADERBOARD_SIZE);
         assert_eq!(entries[0].score, 19);
         assert_eq!(entries[9].score, 10);
@@ -154,9 +266,114 @@ mod tests {
             ranking.update(Uuid::new_v4(), "Other", score, false, None);
         }
 
-        let entries = ranking.leaderboard(Some(player_token));
+        let entries = ranking.leaderboard(Some(player_token), RankingOrder::Score);
         assert_eq!(entries.len(), LEADERBOARD_SIZE + 1);
         assert_eq!(entries.last().unwrap().rank, 12);
         assert!(entries.last().unwrap().is_self);
+        assert!(ranking.has_player(player_token));
+        assert!(!ranking.has_player(Uuid::new_v4()));
+    }
+
+    #[test]
+    fn sums_the_scores_and_kills_of_each_team() {
+        let mut ranking = RankingStore::default();
+        let (red, blue, loner) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        ranking.update(red, "Red", 30, false, None);
+        ranking.update(blue, "Blue", 20, true, None);
+        ranking.update(loner, "Loner", 100, false, None);
+        ranking.set_team(red, Some(0));
+        ranking.set_team(blue, Some(1));
+        let victim = Uuid::new_v4();
+        ranking.update(victim, "Victim", 5, false, None);
+        ranking.set_team(victim, Some(0));
+        ranking.record_death(
+            &victim,
+            Some(blue),
+            KillFeedEntry {
+                victim: "Victim".to_owned(),
+                killer: Some("Blue".to_owned()),
+                cause: DeathCause::Body,
+            },
+        );
+
+        assert_eq!(
+            ranking.team_totals(),
+            vec![
+                TeamEntry {
+                    team: 0,
+                    score: 30,
+                    kills: 0,
+                    members: 1,
+                },
+                TeamEntry {
+                    team: 1,
+                    score: 20,
+                    kills: 1,
+                    members: 1,
+                },
+            ]
+        );
+        assert_eq!(
+            ranking.leaderboard(None, RankingOrder::Score)[1].team,
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn ranks_by_kills_and_counts_only_the_current_life() {
+        let mut ranking = RankingStore::default();
+        let hunter = Uuid::new_v4();
+        let grower = Uuid::new_v4();
+        ranking.update(hunter, "Hunter", 10, false, None);
+        ranking.update(grower, "Grower", 50, false, None);
+        for _ in 0..2 {
+            let victim = Uuid::new_v4();
+            ranking.update(victim, "Victim", 5, false, None);
+            ranking.record_death(
+                &victim,
+                Some(hunter),
+                KillFeedEntry {
+                    victim: "Victim".to_string(),
+                    killer: Some("Hunter".to_string()),
+                    cause: DeathCause::Body,
+                },
+            );
+        }
+
+        let entries = ranking.leaderboard(None, RankingOrder::Kills);
+        assert_eq!(entries.len(), 2);
+        assert_eq!((entries[0].name.as_str(), entries[0].kills), ("Hunter", 2));
+        assert_eq!(
+            ranking.leaderboard(None, RankingOrder::Score)[0].name,
+            "Grower"
+        );
+
+        ranking.remove(&hunter);
+        ranking.update(hunter, "Hunter", 10, false, None);
+        assert!(ranking
+            .leaderboard(None, RankingOrder::Kills)
+            .iter()
+            .all(|entry| entry.kills == 0));
+    }
+
+    #[test]
+    fn kill_feed_keeps_the_newest_deaths_first() {
+        let mut ranking = RankingStore::default();
+        for number in 0..=KILL_FEED_SIZE {
+            ranking.record_death(
+                &Uuid::new_v4(),
+                None,
+                KillFeedEntry {
+                    victim: format!("Player {number}"),
+                    killer: None,
+                    cause: DeathCause::CoinFlip,
+                },
+            );
+        }
+
+        let feed = ranking.kill_feed();
+        assert_eq!(feed.len(), KILL_FEED_SIZE);
+        assert_eq!(feed[0].victim, format!("Player {KILL_FEED_SIZE}"));
+        assert_eq!(feed.last().unwrap().victim, "Player 1");
     }
 }
@@ -0,0 +1,374 @@
+use crate::messages::Shutdown;
+use crate::ranking::{RankingStore, SharedRanking};
+use crate::websocket_actor::WebsocketActor;
+use actix::{Actor, Addr};
+use game::config::GameConfig;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+const DEFAULT_ROOM_CAPACITY: usize = 50;
+const DEFAULT_BOT_COUNT: usize = 6;
+/// The room that is always open, and the default for the HTTP endpoints.
+const MAIN_ROOM: &str = "public-1";
+const MAX_PRIVATE_ROOMS: usize = 100;
+/// A private room that has been empty this long is closed, so that its
+/// host can still reconnect or wait for friends in the meantime.
+const INVITE_TIMEOUT: Duration = Duration::from_secs(600);
+const INVITE_CODE_LENGTH: usize = 6;
+/// Letters and digits that cannot be mistaken for one another.
+const INVITE_CODE_ALPHABET: &[u8; 32] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const MAX_PRIVATE_FIELD_SIZE: f32 = 50_000.0;
+const MAX_PRIVATE_PELLET_COUNT: usize = 20_000;
+const MAX_PRIVATE_MAP_SIZE: usize = 200;
+const MAX_PRIVATE_OBSTACLE_COUNT: usize = 100;
+
+/// An arena with its own engine, tick loop, bots and ranking.
+struct Room {
+    addr: Addr<WebsocketActor>,
+    ranking: SharedRanking,
+    players: usize,
+    is_public: bool,
+    /// When the last player left, or the room was opened.
+    empty_since: Instant,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RoomInfo {
+    pub name: String,
+    pub players: usize,
+    pub capacity: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum JoinError {
+    NotFound,
+    Full,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CreateError {
+    TooManyRooms,
+    InvalidConfig(&'static str),
+}
+
+impl fmt::Display for CreateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CreateError::TooManyRooms => write!(f, "too many private rooms"),
+            CreateError::InvalidConfig(message) => write!(f, "invalid game config: {message}"),
+        }
+    }
+}
+
+/// Every running room. Public rooms are named `public-<number>`; a new one is
+/// opened when all of them are full, and closed again once it is empty.
+/// Private rooms are named by their invite code and only joined by name.
+pub struct RoomRegistry {
+    rooms: BTreeMap<String, Room>,
+    config: GameConfig,
+    capacity: usize,
+    bot_count: usize,
+    next_room_number: usize,
+}
+
+pub type SharedRooms = Arc<RwLock<RoomRegistry>>;
+
+impl RoomRegistry {
+    pub fn new(config: GameConfig, capacity: usize, bot_count: usize) -> Self {
+        //! Open the first public room, which is the only one that restores and
+        //! saves snapshots and replays. Must be called within an actix system.
+
+        let mut registry = RoomRegistry {
+            rooms: BTreeMap::new(),
+            config,
+            capacity: capacity.max(1),
+            bot_count,
+            next_room_number: 1,
+        };
+        registry.open_public_room();
+        registry
+    }
+
+    fn open(&mut self, name: String, config: GameConfig, bot_count: usize, is_public: bool) {
+        let ranking = Arc::new(RwLock::new(RankingStore::default()));
+        let is_main = name == MAIN_ROOM;
+        let addr =
+            WebsocketActor::new(name.clone(), ranking.clone(), config, bot_count, is_main).start();
+        self.rooms.insert(
+            name,
+            Room {
+                addr,
+                ranking,
+                players: 0,
+                is_public,
+                empty_since: Instant::now(),
+            },
+        );
+    }
+
+    fn open_public_room(&mut self) -> String {
+        let name = format!("public-{}", self.next_room_number);
+        self.next_room_number += 1;
+        self.open(name.clone(), self.config.clone(), self.bot_count, true);
+        name
+    }
+
+    pub fn create_private(
+        &mut self,
+        bot_count: Option<usize>,
+        config: Option<GameConfig>,
+    ) -> Result<String, CreateError> {
+        //! Open a private room and return its invite code. Omitted settings
+        //! are those of the public rooms.
+
+        let config = config.unwrap_or_else(|| self.config.clone());
+        validate_private_config(&config).map_err(CreateError::InvalidConfig)?;
+
+        self.close_unused_private_rooms();
+        if self.rooms.values().filter(|room| !room.is_public).count() >= MAX_PRIVATE_ROOMS {
+            return Err(CreateError::TooManyRooms);
+        }
+        let code = loop {
+            let code = invite_code();
+            if !self.rooms.contains_key(&code) {
+                break code;
+            }
+        };
+        self.open(
+            code.clone(),
+            config,
+            bot_count.unwrap_or(self.bot_count),
+            false,
+        );
+        Ok(code)
+    }
+
+    fn close_unused_private_rooms(&mut self) {
+        let unused: Vec<_> = self
+            .rooms
+            .iter()
+            .filter(|(_, room)| {
+                !room.is_public && room.players == 0 && room.empty_since.elapsed() > INVITE_TIMEOUT
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in unused {
+            self.close(&name);
+        }
+    }
+
+    fn close(&mut self, name: &str) {
+        if let Some(room) = self.rooms.remove(name) {
+            room.addr.do_send(Shutdown);
+        }
+    }
+
+    pub fn join(
+        &mut self,
+        name: Option<&str>,
+    ) -> Result<(String, Addr<WebsocketActor>), JoinError> {
+        //! Take a place in the named room, or in the first public room with a
+        //! free place. Invite codes are not case sensitive.
+
+        self.close_unused_private_rooms();
+        let name = match name {
+            Some(name) if self.rooms.contains_key(name) => name.to_owned(),
+            Some(name) => name.to_uppercase(),
+            None => self
+                .rooms
+                .iter()
+                .find(|(_, room)| room.is_public && room.players < self.capacity)
+                .map(|(name, _)| name.clone())
+                .unwrap_or_else(|| self.open_public_room()),
+        };
+        let room = self.rooms.get_mut(&name).ok_or(JoinError::NotFound)?;
+        if room.players >= self.capacity {
+            return Err(JoinError::Full);
+        }
+        room.players += 1;
+        Ok((name, room.addr.clone()))
+    }
+
+    pub fn leave(&mut self, name: &str) {
+        //! Give up a place taken with `join()`. Public rooms other than the
+        //! main room are closed when their last player leaves; private rooms
+        //! stay open for `INVITE_TIMEOUT` so that their code keeps working.
+
+        let Some(room) = self.rooms.get_mut(name) else {
+            return;
+        };
+        room.players = room.players.saturating_sub(1);
+        if room.players == 0 {
+            room.empty_since = Instant::now();
+            if room.is_public && name != MAIN_ROOM {
+                self.close(name);
+            }
+        }
+        self.close_unused_private_rooms();
+    }
+
+    pub fn ranking(&self, name: Option<&str>) -> Option<SharedRanking> {
+        //! The ranking of the named room, or of the first public room.
+
+        self.rooms
+            .get(name.unwrap_or(MAIN_ROOM))
+            .map(|room| room.ranking.clone())
+    }
+
+    pub fn player_ranking(&self, player_token: Uuid) -> Option<SharedRanking> {
+        //! The ranking of the room the player is currently ranked in.
+
+        self.rooms
+            .values()
+            .find(|room| {
+                room.ranking
+                    .read()
+                    .is_ok_and(|ranking| ranking.has_player(player_token))
+            })
+            .map(|room| room.ranking.clone())
+    }
+
+    pub fn rooms(&self) -> Vec<RoomInfo> {
+        //! The public rooms. Private rooms are only found by their code.
+
+        self.rooms
+            .iter()
+            .filter(|(_, room)| room.is_public)
+            .map(|(name, room)| RoomInfo {
+                name: name.clone(),
+                players: room.players,
+                capacity: self.capacity,
+            })
+            .collect()
+    }
+}
+
+fn validate_private_config(config: &GameConfig) -> Result<(), &'static str> {
+    //! Private rooms run on the same server as everyone else, so their worlds
+    //! are kept to a size the tick loop can afford.
+
+    config.validate()?;
+    if config.field_size > MAX_PRIVATE_FIELD_SIZE {
+        return Err("field_size is too large");
+    }
+    if config.max_pellet_count > MAX_PRIVATE_PELLET_COUNT {
+        return Err("max_pellet_count is too large");
+    }
+    if config.max_dropped_pellet_count > MAX_PRIVATE_PELLET_COUNT {
+        return Err("max_dropped_pellet_count is too large");
+    }
+    if config.map_size > MAX_PRIVATE_MAP_SIZE {
+        return Err("map_size is too large");
+    }
+    if config.obstacles.len() > MAX_PRIVATE_OBSTACLE_COUNT {
+        return Err("too many obstacles");
+    }
+    Ok(())
+}
+
+fn invite_code() -> String {
+    Uuid::new_v4()
+        .as_bytes()
+        .iter()
+        .take(INVITE_CODE_LENGTH)
+        .map(|byte| INVITE_CODE_ALPHABET[*byte as usize % INVITE_CODE_ALPHABET.len()] as char)
+        .collect()
+}
+
+pub fn room_capacity() -> usize {
+    env::var("ROOM_CAPACITY")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_ROOM_CAPACITY)
+}
+
+pub fn bot_count() -> usize {
+    env::var("BOT_COUNT")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_BOT_COUNT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn populations(registry: &RoomRegistry) -> Vec<(String, usize)> {
+        registry
+            .rooms()
+            .into_iter()
+            .map(|room| (room.name, room.players))
+            .collect()
+    }
+
+    #[actix::test]
+    async fn opens_a_public_room_when_all_are_full_and_closes_it_when_empty() {
+        let mut registry = RoomRegistry::new(GameConfig::default(), 2, 0);
+
+        for _ in 0..3 {
+            registry.join(None).unwrap();
+        }
+        assert_eq!(
+            populations(&registry),
+            vec![("public-1".to_owned(), 2), ("public-2".to_owned(), 1)]
+        );
+        assert_eq!(registry.join(Some("public-1")).err(), Some(JoinError::Full));
+        assert_eq!(
+            registry.join(Some("lobby")).err(),
+            Some(JoinError::NotFound)
+        );
+
+        registry.leave("public-2");
+        registry.leave("public-1");
+        assert_eq!(populations(&registry), vec![("public-1".to_owned(), 1)]);
+        assert_eq!(registry.join(None).unwrap().0, "public-1");
+        assert!(registry.ranking(Some("public-2")).is_none());
+        assert!(registry.ranking(None).is_some());
+    }
+
+    #[actix::test]
+    async fn private_rooms_are_joined_only_with_their_invite_code() {
+        let mut registry = RoomRegistry::new(GameConfig::default(), 10, 0);
+        let code = registry.create_private(Some(2), None).unwrap();
+
+        assert_eq!(code.len(), INVITE_CODE_LENGTH);
+        assert_eq!(registry.join(None).unwrap().0, "public-1");
+        assert_eq!(registry.join(Some(&code.to_lowercase())).unwrap().0, code);
+        assert_eq!(populations(&registry), vec![("public-1".to_owned(), 1)]);
+    }
+
+    #[actix::test]
+    async fn private_rooms_outlive_a_reconnect_but_not_the_invite_timeout() {
+        let mut registry = RoomRegistry::new(GameConfig::default(), 10, 0);
+        let code = registry.create_private(None, None).unwrap();
+
+        registry.join(Some(&code)).unwrap();
+        registry.leave(&code);
+        assert_eq!(registry.join(Some(&code)).unwrap().0, code);
+
+        registry.leave(&code);
+        let room = registry.rooms.get_mut(&code).unwrap();
+        room.empty_since -= INVITE_TIMEOUT + Duration::from_secs(1);
+        registry.join(None).unwrap();
+        assert_eq!(registry.join(Some(&code)).err(), Some(JoinError::NotFound));
+    }
+
+    #[actix::test]
+    async fn rejects_private_worlds_that_are_too_large() {
+        let mut registry = RoomRegistry::new(GameConfig::default(), 10, 0);
+        let config = GameConfig {
+            max_pellet_count: MAX_PRIVATE_PELLET_COUNT + 1,
+            ..GameConfig::default()
+        };
+
+        assert!(matches!(
+            registry.create_private(None, Some(config)),
+            Err(CreateError::InvalidConfig(_))
+        ));
+    }
+}
@@ -1,30 +1,32 @@
-use crate::messages::{ClientMessage, Connect, Disconnect, WebsocketMessage};
-use crate::ranking::SharedRanking;
-use actix::{Actor, AsyncContext, Context, Handler, Recipient};
+use crate::match_clock::{match_settings, MatchClock};
+use crate::messages::{ClientInput, Connect, Disconnect, Shutdown, WebsocketMessage};
+use crate::ranking::{RankingOrder, RankingStore, SharedRanking};
+use actix::{Actor, ActorContext, AsyncContext, Context, Handler, Recipient};
 use game::bot::BotPolicy;
+use game::config::GameConfig;
 use game::coordinate::Coordinate;
-use game::engine::GameEngine;
-use std::collections::HashMap;
+use game::engine::{DeathEvent, GameEngine};
+use game::protocol::{ClientCommand, KillFeedEntry, MatchPhase, ServerMessage, SessionInfo};
+use game::quantized::WireFormat;
+use game::snapshot::WorldSnapshot;
+use game::view::EntityHandles;
+use game::view_stream::ViewEncoder;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 use uuid::Uuid;
 
 const FPS: u64 = 30;
 const FRAME_INTERVAL: Duration = Duration::from_millis(1000 / FPS);
 const MAP_INTERVAL: Duration = Duration::from_millis(1000);
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(30);
+const REPLAY_SEGMENT_INTERVAL: Duration = Duration::from_secs(300);
 const MAX_WINDOW_SIZE: u16 = 8192;
-const DEFAULT_BOT_COUNT: usize = 6;
 const MAX_BOT_COUNT: usize = 32;
 const BOT_POLICY: &str = include_str!("../assets/bot_policy.json");
 
-#[derive(Debug, PartialEq)]
-enum ClientCommand {
-    Start(Uuid),
-    Accelerate,
-    Velocity(Coordinate),
-    WindowSize { width: u16, height: u16 },
-}
-
 #[derive(Default)]
 struct WindowSize {
     pub width: u16,
@@ -39,6 +41,11 @@ struct Session {
     pub center_coordinate: Coordinate,
     pub name: String,
     pub player_token: Option<Uuid>,
+    pub handles: EntityHandles,
+    pub encoder: ViewEncoder,
+    pub wire_format: WireFormat,
+    pub last_input: Option<u32>,
+    pub party: Option<Uuid>,
 }
 
 struct BotPlayer {
@@ -48,39 +55,349 @@ struct BotPlayer {
 }
 
 pub struct WebsocketActor {
+    room: String,
     sessions: HashMap<Uuid, Session>,
     engine: GameEngine,
     ranking: SharedRanking,
     bots: Vec<BotPlayer>,
     bot_policy: BotPolicy,
+    snapshot_file: Option<PathBuf>,
+    replay_dir: Option<PathBuf>,
+    /// Players who died in the current battle-royale match. They sit out
+    /// until the next one.
+    eliminated: HashSet<Uuid>,
+    /// Set when the server runs timed matches.
+    match_clock: Option<MatchClock>,
 }
 
 impl WebsocketActor {
-    pub fn new(ranking: SharedRanking) -> Self {
-        let bot_count = env::var("BOT_COUNT")
-            .ok()
-            .and_then(|value| value.parse().ok())
-            .unwrap_or(DEFAULT_BOT_COUNT)
-            .min(MAX_BOT_COUNT);
-        let mut engine = GameEngine::new();
+    pub fn new(
+        room: String,
+        ranking: SharedRanking,
+        config: GameConfig,
+        bot_count: usize,
+        is_main: bool,
+    ) -> Self {
+        //! Only the main room restores and saves `SNAPSHOT_FILE` and records
+        //! to `REPLAY_DIR`; the other rooms come and go with their players.
+
+        let bot_count = bot_count.min(MAX_BOT_COUNT);
+        let snapshot_file = is_main
+            .then(|| env::var("SNAPSHOT_FILE").ok().map(PathBuf::from))
+            .flatten();
+        let mut engine = snapshot_file
+            .as_deref()
+            .and_then(load_snapshot)
+            .map(GameEngine::restore)
+            .unwrap_or_else(|| GameEngine::new(config));
+        // Bot ids are stable so that bots in a restored world keep their snakes.
         let bots: Vec<_> = (1..=bot_count)
             .map(|number| BotPlayer {
-                id: Uuid::new_v4(),
+                id: Uuid::from_u128(number as u128),
                 name: format!("RL Bot {number}"),
                 target_id: None,
             })
             .collect();
-        for bot in &bots {
-            engine.add_snake(bot.id);
+        // Players from before a restart cannot reclaim their snakes.
+        for id in engine.snake_ids() {
+            if !bots.iter().any(|bot| bot.id == id) {
+                engine.remove_snake(&id);
+            }
+        }
+        add_bots(&mut engine, &bots);
+        let replay_dir = is_main
+            .then(|| env::var("REPLAY_DIR").ok().map(PathBuf::from))
+            .flatten();
+        if let Some(dir) = replay_dir.as_ref() {
+            if let Err(error) = fs::create_dir_all(dir) {
+                eprintln!("failed to create {}: {error}", dir.display());
+            }
+            engine.start_recording();
         }
 
         WebsocketActor {
+            room,
             sessions: HashMap::new(),
             engine,
             ranking,
             bots,
             bot_policy: BotPolicy::from_json(BOT_POLICY)
                 .expect("embedded Bot policy must be valid"),
+            snapshot_file,
+            replay_dir,
+            eliminated: HashSet::new(),
+            match_clock: match_settings().map(|settings| MatchClock::new(settings, FPS as u32)),
+        }
+    }
+
+    fn is_running(&self) -> bool {
+        //! Without timed matches the world always runs; with them, only
+        //! during the round.
+
+        self.match_clock
+            .as_ref()
+            .is_none_or(|clock| clock.phase() == MatchPhase::Round)
+    }
+
+    fn step(&mut self) {
+        //! Steer the bots and advance the world by one frame.
+
+        for bot in &mut self.bots {
+            if let Some(target_heading) = self.engine.bot_attack_heading(&bot.id) {
+                bot.target_id = None;
+                self.engine.apply_bot_attack(&bot.id, target_heading);
+                continue;
+            }
+            if let Some(observation) = self.engine.bot_observation(&bot.id, bot.target_id) {
+                bot.target_id = observation.target_id;
+                let action = self.bot_policy.action_for(observation);
+                self.engine.apply_bot_action(&bot.id, observation, action);
+            }
+        }
+
+        let events = self.engine.forward();
+        // In battle-royale mode the dead stay dead until the match ends
+        let is_battle_royale = self.engine.zone().is_some();
+        for death in events.deaths.iter() {
+            self.record_death(death);
+            if is_battle_royale {
+                if self.sessions.contains_key(&death.id) {
+                    self.eliminated.insert(death.id);
+                }
+            } else if let Some(bot) = self.bots.iter_mut().find(|bot| bot.id == death.id) {
+                bot.target_id = None;
+                self.engine.add_snake(bot.id);
+            }
+        }
+        if let Some(center) = events.feast {
+            let bytes = ServerMessage::Feast(center).to_bytes();
+            for session in self.sessions.values() {
+                session.addr.do_send(WebsocketMessage(bytes.clone()));
+            }
+        }
+        let is_match_over = events.winner.is_some()
+            || (!events.deaths.is_empty() && self.engine.snake_ids().is_empty());
+        if is_battle_royale && is_match_over {
+            self.end_match(events.winner);
+        }
+    }
+
+    fn enter_phase(&mut self, phase: MatchPhase) {
+        match phase {
+            MatchPhase::Lobby => self.reset_world(),
+            MatchPhase::Round => {}
+            MatchPhase::Results => self.send_results(),
+        }
+        self.send_match_status();
+    }
+
+    fn send_match_status(&self) {
+        let Some(clock) = self.match_clock.as_ref() else {
+            return;
+        };
+        let bytes = ServerMessage::MatchStatus(clock.status()).to_bytes();
+        for session in self.sessions.values() {
+            session.addr.do_send(WebsocketMessage(bytes.clone()));
+        }
+    }
+
+    fn send_results(&self) {
+        //! Send everyone the final standings of the round that just ended.
+
+        let Ok(mut ranking) = self.ranking.write() else {
+            return;
+        };
+        self.update_ranking(&mut ranking);
+        for session in self.sessions.values() {
+            let results = ranking.leaderboard(session.player_token, RankingOrder::Score);
+            session.addr.do_send(WebsocketMessage(
+                ServerMessage::MatchResults(results).to_bytes(),
+            ));
+        }
+    }
+
+    fn reset_world(&mut self) {
+        //! Replace the world with a fresh one for the next match. Bots and
+        //! the players who were playing get new snakes right away.
+
+        let config = self.engine.config().clone();
+        let is_recording = self.engine.is_recording();
+        self.save_replay_segment();
+        self.engine = GameEngine::new(config);
+        if is_recording {
+            self.engine.start_recording();
+        }
+        if let Ok(mut ranking) = self.ranking.write() {
+            ranking.reset();
+        }
+        self.eliminated.clear();
+        for bot in &mut self.bots {
+            bot.target_id = None;
+        }
+        add_bots(&mut self.engine, &self.bots);
+        let players: Vec<_> = self
+            .sessions
+            .iter()
+            .filter(|(_, session)| session.is_playing)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in players {
+            self.join_smallest_team(&id);
+            self.spawn(&id);
+        }
+    }
+
+    fn update_ranking(&self, ranking: &mut RankingStore) {
+        for (id, session) in self.sessions.iter() {
+            if session.is_playing {
+                if let Some(score) = self.engine.score(id) {
+                    ranking.update(*id, &session.name, score, false, session.player_token);
+                    ranking.set_team(*id, self.engine.team(id));
+                }
+            }
+        }
+        for bot in &self.bots {
+            if let Some(score) = self.engine.score(&bot.id) {
+                ranking.update(bot.id, &bot.name, score, true, None);
+                ranking.set_team(bot.id, self.engine.team(&bot.id));
+            }
+        }
+    }
+
+    fn end_match(&mut self, winner: Option<Uuid>) {
+        //! Announce the winner of a battle-royale match and start the next
+        //! one: the zone opens up again, bots respawn and eliminated players
+        //! may start again.
+
+        let message = ServerMessage::MatchOver(winner.and_then(|id| self.player_name(&id)));
+        let bytes = message.to_bytes();
+        for session in self.sessions.values() {
+            session.addr.do_send(WebsocketMessage(bytes.clone()));
+        }
+        self.eliminated.clear();
+        self.engine.restart_zone();
+        for bot in &mut self.bots {
+            if self.engine.get_snake(&bot.id).is_none() {
+                bot.target_id = None;
+                self.engine.add_snake(bot.id);
+            }
+        }
+    }
+
+    fn save_replay_segment(&mut self) {
+        //! Write the inputs recorded since the previous segment and start a
+        //! new one, so that memory stays bounded on a long-running server.
+
+        let Some(dir) = self.replay_dir.as_ref() else {
+            return;
+        };
+        let Some(replay) = self.engine.stop_recording() else {
+            return;
+        };
+        self.engine.start_recording();
+        let path = dir.join(format!(
+            "{:010}-{:010}.replay",
+            replay.start_frame(),
+            replay.end_frame
+        ));
+        if let Err(error) = fs::write(&path, replay.to_bytes()) {
+            eprintln!("failed to save replay to {}: {error}", path.display());
+        }
+    }
+
+    fn join_smallest_team(&mut self, id: &Uuid) {
+        //! Put a player who starts in team mode into the smallest team, then
+        //! even out the teams by moving bots.
+
+        let team_count = self.engine.config().team_count;
+        if team_count == 0 || self.engine.team(id).is_some() {
+            return;
+        }
+        let players = self
+            .sessions
+            .iter()
+            .filter(|(_, session)| session.is_playing)
+            .filter_map(|(other, _)| Some((*other, (self.engine.team(other)?, false))));
+        let bots = self
+            .bots
+            .iter()
+            .filter_map(|bot| Some((bot.id, (self.engine.team(&bot.id)?, true))));
+        let members = players.chain(bots).collect();
+        for (member, team) in team_moves(*id, &members, team_count) {
+            self.engine.set_team(member, Some(team));
+        }
+    }
+
+    fn spawn(&mut self, id: &Uuid) {
+        //! Add the player's snake next to a party member who is alive, or
+        //! anywhere if there is none.
+
+        let party = self.sessions.get(id).and_then(|session| session.party);
+        let friend = party.and_then(|party| {
+            self.sessions
+                .iter()
+                .filter(|(other, session)| *other != id && session.party == Some(party))
+                .map(|(other, _)| *other)
+                .filter(|other| self.engine.get_snake(other).is_some())
+                .min()
+        });
+        match friend {
+            Some(friend) => self.engine.add_snake_near(*id, &friend),
+            None => self.engine.add_snake(*id),
+        }
+    }
+
+    fn player_name(&self, id: &Uuid) -> Option<String> {
+        self.sessions
+            .get(id)
+            .map(|session| session.name.clone())
+            .or_else(|| {
+                self.bots
+                    .iter()
+                    .find(|bot| bot.id == *id)
+                    .map(|bot| bot.name.clone())
+            })
+    }
+
+    fn record_death(&self, death: &DeathEvent) {
+        let Some(victim) = self.player_name(&death.id) else {
+            return;
+        };
+        let entry = KillFeedEntry {
+            victim,
+            killer: death.killer.and_then(|killer| self.player_name(&killer)),
+            cause: death.cause,
+        };
+        if let Ok(mut ranking) = self.ranking.write() {
+            ranking.record_death(&death.id, death.killer, entry);
+        }
+    }
+
+    fn save_snapshot(&self) {
+        let Some(path) = self.snapshot_file.as_ref() else {
+            return;
+        };
+        // Write to a temporary file first so that a crash during the write
+        // never leaves a truncated snapshot behind.
+        let temporary_path = path.with_extension("tmp");
+        let result = fs::write(&temporary_path, self.engine.snapshot().to_bytes())
+            .and_then(|_| fs::rename(&temporary_path, path));
+        if let Err(error) = result {
+            eprintln!("failed to save snapshot to {}: {error}", path.display());
+        }
+    }
+}
+
+fn load_snapshot(path: &Path) -> Option<WorldSnapshot> {
+    let bytes = fs::read(path).ok()?;
+    match WorldSnapshot::from_bytes(&bytes) {
+        Ok(snapshot) => {
+            println!("Restoring world from {}", path.display());
+            Some(snapshot)
+        }
+        Err(error) => {
+            eprintln!("ignoring snapshot {}: {error}", path.display());
+            None
         }
     }
 }
@@ -90,32 +407,11 @@ impl Actor for WebsocketActor {
 
     fn started(&mut self, ctx: &mut Self::Context) {
         ctx.run_interval(FRAME_INTERVAL, |act, _| {
-            for bot in &mut act.bots {
-                if let Some(target_heading) = act.engine.bot_attack_heading(&bot.id) {
-                    bot.target_id = None;
-                    act.engine.apply_bot_attack(&bot.id, target_heading);
-                    continue;
-                }
-                if let Some(observation) = act.engine.bot_observation(&bot.id, bot.target_id) {
-                    bot.target_id = observation.target_id;
-                    let action = act.bot_policy.action_for(observation);
-                    act.engine.apply_bot_action(&bot.id, observation, action);
-                }
+            if let Some(phase) = act.match_clock.as_mut().and_then(MatchClock::tick) {
+                act.enter_phase(phase);
             }
-
-            let events = act.engine.forward();
-            for death in events.deaths {
-                if let Some(bot) = act.bots.iter_mut().find(|bot| bot.id == death.id) {
-                    bot.target_id = None;
-                    if let Ok(mut ranking) = act.ranking.write() {
-                        ranking.remove(&bot.id);
-                    }
-                    act.engine.add_snake(bot.id);
-                } else if act.sessions.contains_key(&death.id) {
-                    if let Ok(mut ranking) = act.ranking.write() {
-                        ranking.remove(&death.id);
-                    }
-                }
+            if act.is_running() {
+                act.step();
             }
 
             for (id, session) in act.sessions.iter_mut() {
@@ -129,44 +425,63 @@ impl Actor for WebsocketActor {
 
                 if session.additional_send_frame_count > 0 {
                     session.additional_send_frame_count -= 1;
+                    let state = act.engine.view_state(
+                        id,
+                        session.center_coordinate.x,
+                        session.center_coordinate.y,
+                        (session.window_size.width + 100).into(),
+                        (session.window_size.height + 100).into(),
+                        &mut session.handles,
+                    );
+                    let packet = session.encoder.encode(state);
                     session.addr.do_send(WebsocketMessage(
-                        act.engine
-                            .view(
-                                id,
-                                session.center_coordinate.x,
-                                session.center_coordinate.y,
-                                (session.window_size.width + 100).into(),
-                                (session.window_size.height + 100).into(),
-                            )
-                            .to_bytes(),
+                        ServerMessage::view(&packet, session.wire_format).to_bytes(),
                     ));
                 }
             }
         });
         ctx.run_interval(MAP_INTERVAL, |act, _| {
-            let mut map = act.engine.map(0.0, 0.0);
-            for (id, session) in act.sessions.iter_mut() {
-                if session.is_playing {
-                    if let Some(score) = act.engine.score(id) {
-                        if let Ok(mut ranking) = act.ranking.write() {
-                            ranking.update(*id, &session.name, score, false, session.player_token);
-                        }
-                    }
-                    map.self_coordinate = GameEngine::map_coordinate(
-                        session.center_coordinate.x,
-                        session.center_coordinate.y,
-                    );
-                    session.addr.do_send(WebsocketMessage(map.to_bytes()));
-                }
-            }
-            for bot in &act.bots {
-                if let Some(score) = act.engine.score(&bot.id) {
-                    if let Ok(mut ranking) = act.ranking.write() {
-                        ranking.update(bot.id, &bot.name, score, true, None);
-                    }
+            act.send_match_status();
+            let Ok(mut ranking) = act.ranking.write() else {
+                return;
+            };
+            act.update_ranking(&mut ranking);
+
+            let map = ServerMessage::Map(act.engine.map()).to_bytes();
+            let kill_feed = ServerMessage::KillFeed(ranking.kill_feed()).to_bytes();
+            let team_leaderboard = (act.engine.config().team_count > 0)
+                .then(|| ServerMessage::TeamLeaderboard(ranking.team_totals()).to_bytes());
+            for (id, session) in act
+                .sessions
+                .iter()
+                .filter(|(_, session)| session.is_playing)
+            {
+                let map_self = act.engine.map_self(
+                    id,
+                    session.center_coordinate.x,
+                    session.center_coordinate.y,
+                );
+                let leaderboard = ranking.leaderboard(session.player_token, RankingOrder::Score);
+                for message in [
+                    map.clone(),
+                    ServerMessage::MapSelf(map_self).to_bytes(),
+                    ServerMessage::Leaderboard(leaderboard).to_bytes(),
+                    kill_feed.clone(),
+                ]
+                .into_iter()
+                .chain(team_leaderboard.clone())
+                {
+                    session.addr.do_send(WebsocketMessage(message));
                 }
             }
         });
+        ctx.run_interval(SNAPSHOT_INTERVAL, |act, _| act.save_snapshot());
+        ctx.run_interval(REPLAY_SEGMENT_INTERVAL, |act, _| act.save_replay_segment());
+    }
+
+    fn stopped(&mut self, _: &mut Self::Context) {
+        self.save_snapshot();
+        self.save_replay_segment();
     }
 }
 
@@ -174,6 +489,14 @@ impl Handler<Connect> for WebsocketActor {
     type Result = ();
 
     fn handle(&mut self, msg: Connect, _: &mut Context<Self>) -> Self::Result {
+        let name = format!("Player-{}", &msg.id.simple().to_string()[..4]);
+        let info = ServerMessage::Session(SessionInfo {
+            id: msg.id,
+            name: name.clone(),
+            room: self.room.clone(),
+            field_size: self.engine.config().field_size,
+        });
+        msg.addr.do_send(WebsocketMessage(info.to_bytes()));
         self.sessions.insert(
             msg.id,
             Session {
@@ -182,8 +505,13 @@ impl Handler<Connect> for WebsocketActor {
                 additional_send_frame_count: 0,
                 window_size: WindowSize::default(),
                 center_coordinate: Coordinate::default(),
-                name: format!("Player-{}", &msg.id.simple().to_string()[..4]),
+                name,
                 player_token: None,
+                handles: EntityHandles::default(),
+                encoder: ViewEncoder::default(),
+                wire_format: WireFormat::default(),
+                last_input: None,
+                party: None,
             },
         );
     }
@@ -198,74 +526,144 @@ impl Handler<Disconnect> for WebsocketActor {
             ranking.remove(&client_id);
         }
         self.sessions.remove(&client_id);
+        self.eliminated.remove(&client_id);
         self.engine.remove_snake(&client_id);
+        if self.engine.team(&client_id).is_some() {
+            self.engine.set_team(client_id, None);
+        }
+    }
+}
+
+impl Handler<Shutdown> for WebsocketActor {
+    type Result = ();
+
+    fn handle(&mut self, _: Shutdown, ctx: &mut Context<Self>) {
+        ctx.stop();
     }
 }
 
-impl Handler<ClientMessage> for WebsocketActor {
+impl Handler<ClientInput> for WebsocketActor {
     type Result = ();
 
-    fn handle(&mut self, msg: ClientMessage, _: &mut Context<Self>) {
+    fn handle(&mut self, msg: ClientInput, _: &mut Context<Self>) {
         let id = &msg.id;
-        let Some(command) = parse_client_message(&msg.msg) else {
+        let Some(session) = self.sessions.get_mut(id) else {
             return;
         };
+        if !accept_input(&mut session.last_input, msg.sequence) {
+            return;
+        }
 
-        match command {
+        match msg.command {
             ClientCommand::Start(player_token) => {
-                if self.engine.get_snake(id).is_none() {
-                    self.engine.add_snake(*id);
+                if self.eliminated.contains(id) {
+                    let error = ServerMessage::Error("wait for the next match".to_owned());
+                    session.addr.do_send(WebsocketMessage(error.to_bytes()));
+                    return;
                 }
-                if let Some(session) = self.sessions.get_mut(id) {
-                    session.is_playing = true;
-                    session.player_token = Some(player_token);
+                session.is_playing = true;
+                session.player_token = Some(player_token);
+                self.join_smallest_team(id);
+                if self.engine.get_snake(id).is_none() {
+                    self.spawn(id);
                 }
             }
             ClientCommand::Accelerate => {
-                if let Some(snake) = self.engine.get_snake_mut(id) {
-                    snake.accelerate();
-                }
+                self.engine.accelerate(id);
             }
             ClientCommand::Velocity(velocity) => {
                 self.engine.change_velocity(id, velocity);
             }
             ClientCommand::WindowSize { width, height } => {
-                if let Some(session) = self.sessions.get_mut(id) {
-                    session.window_size.height = height.clamp(1, MAX_WINDOW_SIZE);
-                    session.window_size.width = width.clamp(1, MAX_WINDOW_SIZE);
-                }
+                session.window_size.height = height.clamp(1, MAX_WINDOW_SIZE);
+                session.window_size.width = width.clamp(1, MAX_WINDOW_SIZE);
+            }
+            ClientCommand::Acknowledge(sequence) => {
+                session.encoder.acknowledge(sequence);
+            }
+            ClientCommand::WireFormat(format) => {
+                session.wire_format = format;
+            }
+            ClientCommand::Party(party) => {
+                session.party = party;
             }
         }
     }
 }
 
-fn parse_client_message(message: &str) -> Option<ClientCommand> {
-    let mut parts = message.split_whitespace();
-    let command = parts.next()?;
-
-    let parsed = match command {
-        "s" => ClientCommand::Start(parts.next()?.parse::<Uuid>().ok()?),
-        "a" => ClientCommand::Accelerate,
-        "v" => {
-            let x = parts.next()?.parse::<f32>().ok()?;
-            let y = parts.next()?.parse::<f32>().ok()?;
-            if !x.is_finite() || !y.is_finite() {
-                return None;
-            }
-            ClientCommand::Velocity(Coordinate { x, y })
+fn add_bots(engine: &mut GameEngine, bots: &[BotPlayer]) {
+    //! Deal the bots round-robin into the teams, if any, and give every bot
+    //! without a snake one.
+
+    let team_count = engine.config().team_count;
+    for (index, bot) in bots.iter().enumerate() {
+        if team_count > 0 && engine.team(&bot.id).is_none() {
+            engine.set_team(bot.id, Some((index % team_count) as u8));
         }
-        "w" => ClientCommand::WindowSize {
-            width: parts.next()?.parse::<u16>().ok()?,
-            height: parts.next()?.parse::<u16>().ok()?,
-        },
-        _ => return None,
-    };
+    }
+    for bot in bots {
+        if engine.get_snake(&bot.id).is_none() {
+            engine.add_snake(bot.id);
+        }
+    }
+}
+
+fn team_moves(
+    player: Uuid,
+    members: &BTreeMap<Uuid, (u8, bool)>,
+    team_count: usize,
+) -> Vec<(Uuid, u8)> {
+    //! The team changes that put `player` into the smallest team and then
+    //! move bots from the largest to the smallest team until no two teams
+    //! differ by more than one. `members` maps ids to their team and whether
+    //! they are a bot.
+
+    let mut members = members.clone();
+    let mut counts = vec![0; team_count];
+    for (team, _) in members.values() {
+        if let Some(count) = counts.get_mut(*team as usize) {
+            *count += 1;
+        }
+    }
+    let smallest = |counts: &[usize]| (0..counts.len()).min_by_key(|&team| counts[team]);
+    let largest = |counts: &[usize]| (0..counts.len()).max_by_key(|&team| (counts[team], team));
 
-    if parts.next().is_some() {
-        return None;
+    let Some(team) = smallest(&counts) else {
+        return Vec::new();
+    };
+    counts[team] += 1;
+    members.insert(player, (team as u8, false));
+    let mut moves = vec![(player, team as u8)];
+    while let (Some(from), Some(to)) = (largest(&counts), smallest(&counts)) {
+        if counts[from] <= counts[to] + 1 {
+            break;
+        }
+        let Some((bot, entry)) = members
+            .iter_mut()
+            .find(|(_, (team, is_bot))| *is_bot && *team as usize == from)
+        else {
+            break;
+        };
+        entry.0 = to as u8;
+        counts[from] -= 1;
+        counts[to] += 1;
+        moves.push((*bot, to as u8));
     }
+    moves
+}
+
+fn accept_input(last_input: &mut Option<u32>, sequence: Option<u32>) -> bool {
+    //! Whether to handle an input, given the sequence number of the last one.
+    //! Text commands carry no sequence number and are always handled.
 
-    Some(parsed)
+    let Some(sequence) = sequence else {
+        return true;
+    };
+    if last_input.is_some_and(|last| sequence <= last) {
+        return false;
+    }
+    *last_input = Some(sequence);
+    true
 }
 
 #[cfg(test)]
@@ -273,34 +671,33 @@ mod tests {
     use super::*;
 
     #[test]
-    fn parses_valid_client_messages() {
-        let player_token = Uuid::new_v4();
-        assert_eq!(
-            parse_client_message(&format!("s {player_token}")),
-            Some(ClientCommand::Start(player_token))
-        );
-        assert_eq!(
-            parse_client_message("v 1 -0.5"),
-            Some(ClientCommand::Velocity(Coordinate { x: 1.0, y: -0.5 }))
-        );
-        assert_eq!(
-            parse_client_message("w 1920 1080"),
-            Some(ClientCommand::WindowSize {
-                width: 1920,
-                height: 1080,
-            })
-        );
+    fn drops_inputs_that_are_not_newer_than_the_last_one() {
+        let mut last_input = None;
+
+        assert!(accept_input(&mut last_input, Some(2)));
+        assert!(!accept_input(&mut last_input, Some(2)));
+        assert!(!accept_input(&mut last_input, Some(1)));
+        assert!(accept_input(&mut last_input, None));
+        assert!(accept_input(&mut last_input, Some(3)));
+        assert_eq!(last_input, Some(3));
     }
 
     #[test]
-    fn rejects_malformed_or_non_finite_client_messages() {
-        assert_eq!(parse_client_message(""), None);
-        assert_eq!(parse_client_message("v 1"), None);
-        assert_eq!(parse_client_message("v NaN 1"), None);
-        assert_eq!(parse_client_message("w large 1080"), None);
-        assert_eq!(parse_client_message("s"), None);
-        assert_eq!(parse_client_message("s Alice"), None);
-        assert_eq!(parse_client_message("status"), None);
+    fn players_join_the_smallest_team_and_bots_even_out_the_rest() {
+        let player = Uuid::from_u128(10);
+        let members = BTreeMap::from([
+            (Uuid::from_u128(1), (0, true)),
+            (Uuid::from_u128(2), (0, true)),
+            (Uuid::from_u128(3), (0, false)),
+            (Uuid::from_u128(4), (1, false)),
+        ]);
+
+        assert_eq!(team_moves(player, &members, 2), vec![(player, 1)]);
+        assert_eq!(
+            team_moves(player, &members, 3),
+            vec![(player, 2), (Uuid::from_u128(1), 1)]
+        );
+        assert_eq!(team_moves(player, &BTreeMap::new(), 0), vec![]);
     }
 
     #[test]
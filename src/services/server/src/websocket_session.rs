@@ -1,4 +1,5 @@
-use crate::messages::{ClientMessage, Connect, Disconnect, WebsocketMessage};
+use crate::messages::{ClientInput, Connect, Disconnect, WebsocketMessage};
+use crate::rooms::SharedRooms;
 use crate::websocket_actor::WebsocketActor;
 use actix::{
     fut, Actor, ActorContext, ActorFutureExt, Addr, AsyncContext, ContextFutureSpawner, Handler,
@@ -6,6 +7,7 @@ use actix::{
 };
 use actix_web::web::Bytes;
 use actix_web_actors::ws;
+use game::protocol::{ClientCommand, ClientMessage, ServerMessage};
 use std::time::{Duration, Instant};
 use uuid::Uuid;
 
@@ -15,14 +17,18 @@ const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
 pub struct WebsocketSession {
     id: Uuid,
     addr: Addr<WebsocketActor>,
+    room: String,
+    rooms: SharedRooms,
     hb: Instant,
 }
 
 impl WebsocketSession {
-    pub fn new(addr: Addr<WebsocketActor>) -> WebsocketSession {
+    pub fn new(addr: Addr<WebsocketActor>, room: String, rooms: SharedRooms) -> WebsocketSession {
         WebsocketSession {
             id: Uuid::new_v4(),
             addr,
+            room,
+            rooms,
             hb: Instant::now(),
         }
     }
@@ -36,6 +42,10 @@ impl WebsocketSession {
             ctx.ping(b"");
         });
     }
+
+    fn reject(&self, error: String, ctx: &mut ws::WebsocketContext<Self>) {
+        ctx.binary(ServerMessage::Error(error).to_bytes());
+    }
 }
 
 impl Actor for WebsocketSession {
@@ -63,6 +73,9 @@ impl Actor for WebsocketSession {
 
     fn stopping(&mut self, _: &mut Self::Context) -> Running {
         self.addr.do_send(Disconnect { id: self.id });
+        if let Ok(mut rooms) = self.rooms.write() {
+            rooms.leave(&self.room);
+        }
         Running::Stop
     }
 }
@@ -85,11 +98,22 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WebsocketSession
             ws::Message::Pong(_) => {
                 self.hb = Instant::now();
             }
-            ws::Message::Text(s) => self.addr.do_send(ClientMessage {
-                id: self.id,
-                msg: s.trim().to_string(),
-            }),
-            ws::Message::Binary(bin) => ctx.binary(bin),
+            ws::Message::Text(text) => match text.parse::<ClientCommand>() {
+                Ok(command) => self.addr.do_send(ClientInput {
+                    id: self.id,
+                    sequence: None,
+                    command,
+                }),
+                Err(_) => self.reject(format!("unrecognized command {:?}", &*text), ctx),
+            },
+            ws::Message::Binary(bytes) => match ClientMessage::from_bytes(&bytes) {
+                Ok(message) => self.addr.do_send(ClientInput {
+                    id: self.id,
+                    sequence: Some(message.sequence),
+                    command: message.command,
+                }),
+                Err(error) => self.reject(format!("malformed message: {error}"), ctx),
+            },
             ws::Message::Close(_) => {
                 ctx.stop();
             }
@@ -15,6 +15,7 @@ interface RankingEntry {
   is_bot: boolean;
   rank: number;
   is_self: boolean;
+  team: number | null;
 }
 
 export type { RankingEntry };